@@ -0,0 +1,58 @@
+/* Generated from src/ffi.rs by test_ffi_header_is_up_to_date, do not edit. */
+#ifndef INTCODE_H
+#define INTCODE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct intcode_cpu intcode_cpu;
+
+#define INTCODE_FAULT -1
+#define INTCODE_HALTED 0
+#define INTCODE_RUNNING 1
+#define INTCODE_MAX_GROWTH 1048576
+
+/* Parses a comma-separated program, returns NULL if `code` isn't a valid program. */
+intcode_cpu *intcode_create(const char *code);
+
+intcode_cpu *intcode_clone(const intcode_cpu *cpu);
+
+void intcode_free(intcode_cpu *cpu);
+
+void intcode_push_input(intcode_cpu *cpu, int64_t value);
+
+/* Stores the oldest pending output in `value`, returns false if there is none. */
+bool intcode_pop_output(intcode_cpu *cpu, int64_t *value);
+
+/* Executes a single instruction, returns INTCODE_RUNNING, INTCODE_HALTED or INTCODE_FAULT. */
+int32_t intcode_step(intcode_cpu *cpu);
+
+/* Runs until an input or output instruction was executed or the program halted. */
+int32_t intcode_run_until_io(intcode_cpu *cpu);
+
+int32_t intcode_run(intcode_cpu *cpu);
+
+/* True if the next instruction reads input and none is queued. */
+bool intcode_waiting_for_input(const intcode_cpu *cpu);
+
+size_t intcode_memory_size(const intcode_cpu *cpu);
+
+/* Addresses beyond the end of memory read as 0. */
+int64_t intcode_read_memory(const intcode_cpu *cpu, size_t addr);
+
+/* Memory grows by up to INTCODE_MAX_GROWTH cells, writes beyond that return INTCODE_FAULT
+   and change nothing. Otherwise returns INTCODE_RUNNING or INTCODE_HALTED. */
+int32_t intcode_write_memory(intcode_cpu *cpu, size_t addr, int64_t value);
+
+size_t intcode_ip(const intcode_cpu *cpu);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,76 @@
+/*
+ * Exercises the C interface of the intcode VM, build and run from the repository root with
+ *   cargo build
+ *   cc -Wall -Wextra -o target/test_intcode ffi/test_intcode.c -Iffi -Ltarget/debug -ladvent_of_code
+ *   LD_LIBRARY_PATH=target/debug target/test_intcode
+ */
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "intcode.h"
+
+#define CHECK(cond) do { \
+    if (!(cond)) { \
+        fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+        exit(1); \
+    } \
+} while (0)
+
+/* day 9 example, outputs a copy of itself */
+static const char *QUINE = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+static const int64_t QUINE_CODE[] = {109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99};
+
+static void test_quine(void) {
+    intcode_cpu *cpu = intcode_create(QUINE);
+    CHECK(cpu != NULL);
+    CHECK(intcode_memory_size(cpu) == 16);
+    CHECK(intcode_run(cpu) == INTCODE_HALTED);
+    for (size_t i = 0; i < 16; i++) {
+        int64_t value;
+        CHECK(intcode_pop_output(cpu, &value));
+        CHECK(value == QUINE_CODE[i]);
+    }
+    int64_t value;
+    CHECK(!intcode_pop_output(cpu, &value));
+    intcode_free(cpu);
+}
+
+static void test_io_and_clone(void) {
+    /* doubles every input */
+    intcode_cpu *cpu = intcode_create("3,11,1002,11,2,11,4,11,1105,1,0");
+    CHECK(intcode_waiting_for_input(cpu));
+    intcode_push_input(cpu, 21);
+    CHECK(intcode_run_until_io(cpu) == INTCODE_RUNNING);
+    CHECK(intcode_ip(cpu) == 2);
+    intcode_cpu *copy = intcode_clone(cpu);
+    CHECK(intcode_write_memory(copy, 11, 50) == INTCODE_RUNNING);
+    CHECK(intcode_write_memory(copy, SIZE_MAX / 8, 50) == INTCODE_FAULT);
+    int64_t value;
+    CHECK(intcode_run_until_io(cpu) == INTCODE_RUNNING);
+    CHECK(intcode_pop_output(cpu, &value) && value == 42);
+    CHECK(intcode_run_until_io(copy) == INTCODE_RUNNING);
+    CHECK(intcode_pop_output(copy, &value) && value == 100);
+    CHECK(intcode_step(copy) == INTCODE_RUNNING);
+    CHECK(intcode_waiting_for_input(copy));
+    CHECK(intcode_read_memory(copy, 11) == 100);
+    CHECK(intcode_read_memory(copy, 1000) == 0);
+    intcode_free(copy);
+    intcode_free(cpu);
+}
+
+static void test_errors(void) {
+    CHECK(intcode_create("1,2,x") == NULL);
+    intcode_cpu *cpu = intcode_create("42");
+    CHECK(intcode_step(cpu) == INTCODE_FAULT);
+    CHECK(intcode_step(cpu) == INTCODE_HALTED);
+    intcode_free(cpu);
+    intcode_free(NULL);
+}
+
+int main(void) {
+    test_quine();
+    test_io_and_clone();
+    test_errors();
+    printf("all tests passed\n");
+    return 0;
+}
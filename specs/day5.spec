@@ -0,0 +1,61 @@
+# Examples from day 5: I/O, parameter modes, comparisons and jumps.
+
+name: echo
+program: 3,0,4,0,99
+input: 42
+output: 42
+---
+name: immediate mode
+program: 1002,4,3,4,33
+memory: 4=99
+---
+name: negative immediate
+program: 1101,100,-1,4,0
+memory: 4=99
+---
+name: position mode equal to 8
+program: 3,9,8,9,10,9,4,9,99,-1,8
+input: 8
+output: 1
+---
+name: position mode less than 8
+program: 3,9,7,9,10,9,4,9,99,-1,8
+input: 9
+output: 0
+---
+name: immediate mode equal to 8
+program: 3,3,1108,-1,8,3,4,3,99
+input: 7
+output: 0
+---
+name: immediate mode less than 8
+program: 3,3,1107,-1,8,3,4,3,99
+input: 7
+output: 1
+---
+name: position mode jump
+program: 3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9
+input: 0
+output: 0
+---
+name: immediate mode jump
+program: 3,3,1105,-1,9,1101,0,0,12,4,12,99,1
+input: 5
+output: 1
+---
+name: compare to 8, below
+program: 3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,
+         1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,
+         999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99
+input: 7
+output: 999
+---
+name: puzzle input, air conditioner
+program-file: ../input/day5.txt
+input: 1
+output: 0, 0, 0, 0, 0, 0, 0, 0, 0, 4887191
+---
+name: puzzle input, thermal radiator
+program-file: ../input/day5.txt
+input: 5
+output: 3419022
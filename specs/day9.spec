@@ -0,0 +1,23 @@
+# Examples from day 9: relative base and large numbers.
+
+name: quine
+program: 109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99
+output: 109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99
+---
+name: 16 digit number
+program: 1102,34915192,34915192,7,4,7,99,0
+output: 1219070632396864
+---
+name: large number
+program: 104,1125899906842624,99
+output: 1125899906842624
+---
+name: puzzle input, test mode
+program-file: ../input/day9.txt
+input: 1
+output: 3013554615
+---
+name: puzzle input, sensor boost
+program-file: ../input/day9.txt
+input: 2
+output: 50158
@@ -1,43 +1,16 @@
-use std::fs;
 use advent_of_code::intcode::IntCodeCpu;
+use advent_of_code::intcode::devices::Device;
 use std::collections::BTreeMap;
 use std::fs::File;
 use image::gif::{Encoder, Frame};
 
 fn main() {
-    let input = fs::read_to_string("./input/day11.txt").unwrap();
-    let mut cpu = IntCodeCpu::from_code(&input);
-    let mut map: Map = BTreeMap::new();
-    let mut x = 0;
-    let mut y = 0;
-    let mut direction = Direction::Up;
-    let mut visited_fields_count = 0;
-    set_color(&mut map, 0, 0, Color::White); // part 2
-    loop {
-        let color = get_color(&map, x, y);
-        cpu.input.push_back(color.get_value());
-        if let Some(new_color) = cpu.run_until_out() {
-            if let Some(turn_direction) = cpu.run_until_out() {
-                if set_color(&mut map, x, y, Color::from(new_color)).is_none() {
-                    visited_fields_count += 1;
-                }
-                if turn_direction != 0 && turn_direction != 1 {
-                    panic!("bad turn direction {}", turn_direction);
-                }
-                direction = direction.turn(&Turn::from(turn_direction));
-                match direction {
-                    Direction::Up => y -= 1,
-                    Direction::Right => x += 1,
-                    Direction::Down => y += 1,
-                    Direction::Left => x -= 1,
-                };
-            } else {
-                break;
-            }
-        } else {
-            break;
-        }
-    }
+    let mut cpu = IntCodeCpu::from_file("./input/day11.txt").unwrap();
+    let mut robot = Robot::default();
+    set_color(&mut robot.map, 0, 0, Color::White); // part 2
+    let robot = cpu.attach_io(robot);
+    cpu.run();
+    let Robot { map, visited_fields_count, .. } = cpu.device::<Robot>(robot).unwrap();
     dbg!(visited_fields_count);
     let mut x_iter = map.iter();
     x_iter.next();
@@ -47,9 +20,8 @@ fn main() {
         .min_by_key(|e| *e.1.iter().next().unwrap().0).unwrap().0;
     let max_y = map.iter()
         .max_by_key(|e| *e.1.iter().next_back().unwrap().0).unwrap().0;
-    let mut pixels: Vec<u8> = Vec::new();
-    pixels.resize(((max_x - min_x) * (max_y - min_y) * 3) as usize, 0);
-    for (x, ys) in &map {
+    let mut pixels = vec![0; ((max_x - min_x) * (max_y - min_y) * 3) as usize];
+    for (x, ys) in map {
         for (y, color) in ys {
             let x_adjusted = x - min_x;
             let y_adjusted = y - min_y;
@@ -66,6 +38,50 @@ fn main() {
 
 type Map = BTreeMap<i32, BTreeMap<i32, Color>>;
 
+// The camera provides the color below the robot as input, outputs alternate between the
+// color to paint and the direction to turn to before moving one field.
+#[derive(Clone)]
+struct Robot {
+    map: Map,
+    x: i32,
+    y: i32,
+    direction: Direction,
+    paint: Option<Color>,
+    visited_fields_count: usize,
+}
+
+impl Default for Robot {
+    fn default() -> Self {
+        Robot { map: Map::new(), x: 0, y: 0, direction: Direction::Up, paint: None, visited_fields_count: 0 }
+    }
+}
+
+impl Device for Robot {
+    fn input(&mut self) -> Option<i64> {
+        Some(get_color(&self.map, self.x, self.y).get_value())
+    }
+
+    fn output(&mut self, value: i64) {
+        let new_color = match self.paint.take() {
+            Some(color) => color,
+            None => {
+                self.paint = Some(Color::from(value));
+                return;
+            }
+        };
+        if set_color(&mut self.map, self.x, self.y, new_color).is_none() {
+            self.visited_fields_count += 1;
+        }
+        self.direction = self.direction.turn(&Turn::from(value));
+        match self.direction {
+            Direction::Up => self.y -= 1,
+            Direction::Right => self.x += 1,
+            Direction::Down => self.y += 1,
+            Direction::Left => self.x -= 1,
+        };
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 enum Color {
     Black,
@@ -97,6 +113,7 @@ impl From<i64> for Color {
     }
 }
 
+#[derive(Clone)]
 enum Direction {
     Up,
     Right,
@@ -148,5 +165,5 @@ fn get_color(map: &Map, x: i32, y: i32) -> &Color {
 }
 
 fn set_color(map: &mut Map, x: i32, y: i32, color: Color) -> Option<Color> {
-    map.entry(x).or_insert_with(BTreeMap::new).insert(y, color)
+    map.entry(x).or_default().insert(y, color)
 }
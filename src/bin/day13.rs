@@ -1,73 +1,46 @@
-use std::fs;
 use advent_of_code::intcode::IntCodeCpu;
-use itertools::Itertools;
-use std::cmp::Ordering;
+use advent_of_code::intcode::devices::Device;
+use advent_of_code::intcode::framebuffer::{Framebuffer, ARCADE_PALETTE};
+
+const BLOCK: i64 = 2;
+const PADDLE: i64 = 3;
+const BALL: i64 = 4;
 
 fn main() {
-    let input = fs::read_to_string("./input/day13.txt").unwrap();
-    let cpu = IntCodeCpu::from_code(&input);
+    let cpu = IntCodeCpu::from_file("./input/day13.txt").unwrap();
     part1(&mut cpu.clone());
     part2(&mut cpu.clone());
 }
 
 fn part1(cpu: &mut IntCodeCpu) {
+    let screen = cpu.attach_output(Framebuffer::triples().with_palette(ARCADE_PALETTE));
     cpu.run();
-    dbg!(cpu.output.iter().tuples().filter(|(_, _, tile)| **tile == 2).count());
+    let screen = cpu.device::<Framebuffer>(screen).unwrap();
+    print!("{}", screen.render_text());
+    dbg!(screen.count(BLOCK));
 }
 
-fn part2(cpu: &mut IntCodeCpu) {
-    cpu.memory[0] = 2;
-    let mut paddle_pos = 0;
-    let mut score = 0;
-    loop {
-        let x = cpu.run_until_out();
-        if x.is_none() {
-            break;
-        }
-        let x = x.unwrap();
-        let y = cpu.run_until_out().unwrap();
-        let tile = cpu.run_until_out().unwrap().into();
-        match tile {
-            TileType::Paddle => {
-                paddle_pos = x;
-            },
-            TileType::Ball => {
-                match x.cmp(&paddle_pos) {
-                    Ordering::Less => cpu.input.push_back(-1),
-                    Ordering::Equal => cpu.input.push_back(0),
-                    Ordering::Greater => cpu.input.push_back(1),
-                }
-            },
-            TileType::Unknown { raw } => {
-                if x == -1 && y == 0 {
-                    score = raw;
-                }
-            }
-            _ => {}
-        }
-    }
-    dbg!(score);
+// Draws the screen and moves the joystick towards the ball.
+#[derive(Clone)]
+struct AutoPlayer {
+    screen: Framebuffer,
 }
 
-#[derive(Debug)]
-enum TileType {
-    Empty,
-    Wall,
-    Block,
-    Paddle,
-    Ball,
-    Unknown { raw: i64 },
-}
+impl Device for AutoPlayer {
+    fn input(&mut self) -> Option<i64> {
+        let ball = self.screen.find(BALL)?;
+        let paddle = self.screen.find(PADDLE)?;
+        Some((ball.0 - paddle.0).signum())
+    }
 
-impl Into<TileType> for i64 {
-    fn into(self) -> TileType {
-        match self {
-            0 => TileType::Empty,
-            1 => TileType::Wall,
-            2 => TileType::Block,
-            3 => TileType::Paddle,
-            4 => TileType::Ball,
-            _ => TileType::Unknown { raw: self },
-        }
+    fn output(&mut self, value: i64) {
+        self.screen.push(value);
     }
 }
+
+fn part2(cpu: &mut IntCodeCpu) {
+    cpu.memory[0] = 2;
+    let player = cpu.attach_io(AutoPlayer { screen: Framebuffer::triples().with_register(-1, 0) });
+    cpu.run();
+    dbg!(cpu.device::<AutoPlayer>(player).unwrap().screen.register(-1, 0).unwrap());
+}
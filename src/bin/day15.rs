@@ -1,66 +1,27 @@
-use std::fs;
 use advent_of_code::intcode::IntCodeCpu;
-use std::collections::VecDeque;
+use advent_of_code::intcode::explorer::{Explorer, Outcome};
 
-fn main() {
-    let input = fs::read_to_string("./input/day15.txt").unwrap();
-    let cpu = IntCodeCpu::from_code(&input);
-    solve(&cpu.clone());
-}
-
-fn solve(cpu: &IntCodeCpu) {
-    let mut todo = VecDeque::new();
-    todo.push_back((cpu.clone(), 0, 0));
-    while let Some((cpu, steps, came_from)) = todo.pop_front() {
-        for direction in 1..=4 {
-            if direction != came_from {
-                let mut clone = cpu.clone();
-                clone.input.push_back(direction);
-                let result = clone.run_until_out().unwrap();
-                if result == 1 {
-                    let came_from = match direction {
-                        1 => 2,
-                        2 => 1,
-                        3 => 4,
-                        4 => 3,
-                        _ => panic!("bad direction")
-                    };
-                    todo.push_back((clone, steps + 1, came_from));
-                } else if result == 2 {
-                    dbg!(steps + 1);
-                    part2(&clone);
-                    break;
-                }
-            }
-        }
-    }
-}
+// north, south, west and east as the droid numbers them
+const MOVES: [(i64, (i64, i64)); 4] = [(1, (0, -1)), (2, (0, 1)), (3, (-1, 0)), (4, (1, 0))];
 
-fn part2(cpu: &IntCodeCpu) {
-    let mut todo = VecDeque::new();
-    todo.push_back((cpu.clone(), 0, 0));
-    let mut max_steps = 0;
-    while let Some((cpu, steps, came_from)) = todo.pop_front() {
-        for direction in 1..=4 {
-            if direction != came_from {
-                let mut clone = cpu.clone();
-                clone.input.push_back(direction);
-                let result = clone.run_until_out().unwrap();
-                if result == 1 {
-                    let came_from = match direction {
-                        1 => 2,
-                        2 => 1,
-                        3 => 4,
-                        4 => 3,
-                        _ => panic!("bad direction")
-                    };
-                    todo.push_back((clone, steps + 1, came_from));
-                    if steps + 1 > max_steps {
-                        max_steps = steps + 1;
-                    }
-                }
+fn main() {
+    let cpu = IntCodeCpu::from_file("./input/day15.txt").unwrap();
+    let explorer = Explorer::new(
+        MOVES.to_vec(),
+        |cpu: &mut IntCodeCpu, (command, _): &(i64, (i64, i64))| {
+            cpu.input.push_back(*command);
+            match cpu.run_until_out().unwrap() {
+                0 => Outcome::Blocked,
+                1 => Outcome::Open,
+                2 => Outcome::Goal,
+                status => panic!("bad status {}", status),
             }
-        }
-    }
-    dbg!(max_steps);
+        },
+        |(x, y): &(i64, i64), (_, (dx, dy)): &(i64, (i64, i64))| (x + dx, y + dy),
+    );
+    let maze = explorer.explore(&cpu, (0, 0));
+    let (oxygen, oxygen_cpu) = &maze.goals[0];
+    dbg!(maze.distances[oxygen]);
+    // the oxygen spreads one step per minute
+    dbg!(explorer.explore(oxygen_cpu, *oxygen).max_distance());
 }
@@ -1,9 +1,8 @@
-use std::fs;
 use advent_of_code::intcode::IntCodeCpu;
+use advent_of_code::intcode::batch::Batch;
 
 fn main() {
-    let input = fs::read_to_string("./input/day19.txt").unwrap();
-    let cpu = IntCodeCpu::from_code(&input);
+    let cpu = IntCodeCpu::from_file("./input/day19.txt").unwrap();
     part1(&cpu.clone());
     part2(&cpu.clone());
 }
@@ -16,20 +15,14 @@ fn is_in_beam(cpu: &IntCodeCpu, x: i64, y: i64) -> bool {
     cpu.output.pop_front() == Some(1)
 }
 
-
 fn part1(cpu: &IntCodeCpu) {
-    let mut count = 0;
-    for y in 0..50 {
-        for x in 0..50 {
-            if is_in_beam(cpu, x, y) {
-                print!("#");
-                count += 1;
-            } else {
-                print!(".");
-            }
-        }
-        println!(" ");
+    let coordinates = (0..50).flat_map(|y| (0..50).map(move |x| vec![x, y]));
+    let beam = Batch::new(cpu).run(coordinates, |cpu| cpu.output.pop_front() == Some(1));
+    for row in beam.chunks(50) {
+        let line = row.iter().map(|in_beam| if *in_beam { '#' } else { '.' }).collect::<String>();
+        println!("{} ", line);
     }
+    let count = beam.iter().filter(|in_beam| **in_beam).count();
     dbg!(count);
 }
 
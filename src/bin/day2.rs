@@ -1,21 +1,42 @@
-use std::fs::File;
-use std::io::{BufReader, BufRead};
 use advent_of_code::intcode::IntCodeCpu;
+use advent_of_code::intcode::profile::Profile;
+use advent_of_code::intcode::symbolic::{PathEnd, SymbolicCpu};
 
 fn main() {
-    let file = File::open("./input/day2.txt").unwrap();
-    let mut code = String::new();
-    BufReader::new(file).read_line(&mut code).ok();
-    let cpu = IntCodeCpu::from_code(&code);
-    for noun in 0..99 {
-        for verb in 0..99 {
-            let mut copy = cpu.clone();
-            copy.memory[1] = noun;
-            copy.memory[2] = verb;
-            copy.run();
-            if copy.memory[0] == 19_690_720 {
+    let cpu = IntCodeCpu::from_file("./input/day2.txt").unwrap();
+    part1(&cpu);
+    let mut symbolic = SymbolicCpu::from_cpu(&cpu);
+    symbolic.make_symbolic(1, "noun");
+    symbolic.make_symbolic(2, "verb");
+    // only halted paths computed a result, the others are cut short or left the supported subset
+    for path in symbolic.explore(10_000, 16) {
+        if path.end != Some(PathEnd::Halted) {
+            eprintln!("skipping path: {:?}", path.end);
+            continue;
+        }
+        match path.solve(&path.memory[0], 19_690_720, &[("noun", 0..=99), ("verb", 0..=99)]) {
+            Ok(solutions) => for solution in solutions {
+                // the symbolic CPU has no profiles, check the answer on the original machine
+                let (noun, verb) = (solution["noun"], solution["verb"]);
+                assert_eq!(run_with(&cpu, noun, verb), 19_690_720);
                 dbg!(noun * 100 + verb);
-            }
+            },
+            Err(e) => eprintln!("can't solve path: {}", e),
         }
     }
 }
+
+// restores the "1202 program alarm" state
+fn part1(cpu: &IntCodeCpu) {
+    dbg!(run_with(cpu, 12, 2));
+}
+
+// runs the program on the original add/mul/halt machine
+fn run_with(cpu: &IntCodeCpu, noun: i64, verb: i64) -> i64 {
+    let mut cpu = cpu.clone();
+    cpu.set_profile(Profile::Day2);
+    cpu.write_memory(1, noun);
+    cpu.write_memory(2, verb);
+    cpu.run();
+    cpu.memory[0]
+}
@@ -1,11 +1,9 @@
-use std::fs;
 use advent_of_code::intcode::IntCodeCpu;
+use advent_of_code::intcode::batch::{Batch, Job};
 use itertools::Itertools;
-use rayon::iter::{ParallelBridge, ParallelIterator};
 
 fn main() {
-    let input = fs::read_to_string("./input/day21.txt").unwrap();
-    let cpu = IntCodeCpu::from_code(&input);
+    let cpu = IntCodeCpu::from_file("./input/day21.txt").unwrap();
     // part 1 can be brute forced in a few seconds
     brute_force_search(&cpu, "WALK", &["A", "B", "C", "D", "T", "J"]);
     print_cpu_result(&cpu, "WALK", &["OR A T", "AND C T", "NOT T J", "AND D J"]);
@@ -16,18 +14,14 @@ fn main() {
 fn print_cpu_result(cpu: &IntCodeCpu, mode: &str, inst: &[&str]) {
     let mut cpu = cpu.clone();
     for inst in inst {
-        cpu.input_ascii(inst);
-        cpu.input_ascii("\n");
+        cpu.send_line(inst);
     }
-    cpu.input_ascii(mode);
-    cpu.input_ascii("\n");
-    cpu.run();
-    if let Some(result) = cpu.output.iter().find(|o| **o > 255) {
+    cpu.send_line(mode);
+    let screen = cpu.collect_screen().unwrap();
+    if let Some(result) = screen.answer {
         dbg!(result);
     } else {
-        for c in cpu.output.iter() {
-            print!("{}", String::from_utf8(vec![*c as u8]).unwrap());
-        }
+        print!("{}", screen.text);
     }
 }
 
@@ -52,7 +46,7 @@ fn brute_force_search(cpu: &IntCodeCpu, mode: &str, reg1: &[&str]) {
             instruction
         })
         .collect();
-    let result = instructions
+    let programs = instructions
         .iter()
         .cartesian_product(
             instructions.iter()
@@ -63,30 +57,12 @@ fn brute_force_search(cpu: &IntCodeCpu, mode: &str, reg1: &[&str]) {
         .cartesian_product(
             instructions.iter()
         )
-        .par_bridge()
-        .find_any(|(((op1, op2), op3), op4)| {
-            run_cpu(cpu, mode, op1, op2, op3, op4)
-        }).unwrap();
-    dbg!(result);
-}
-
-fn run_cpu(cpu: &IntCodeCpu, mode: &str, op1: &str, op2: &str, op3: &str, op4: &str) -> bool {
-    let mut cpu = cpu.clone();
-    cpu.input_ascii(op1);
-    cpu.input_ascii("\n");
-    cpu.input_ascii(op2);
-    cpu.input_ascii("\n");
-    cpu.input_ascii(op3);
-    cpu.input_ascii("\n");
-    cpu.input_ascii(op4);
-    cpu.input_ascii("\n");
-    cpu.input_ascii(mode);
-    cpu.input_ascii("\n");
-    cpu.run();
-    if let Some(result) = cpu.output.iter().find(|o| **o > 255) {
-        dbg!(result);
-        true
-    } else {
-        false
-    }
+        .map(|(((op1, op2), op3), op4)| [op1, op2, op3, op4]);
+    let jobs = programs.clone().map(|program| {
+        program.iter().fold(Job::new(), |job, op| job.line(op)).line(mode)
+    });
+    let (index, result) = Batch::new(cpu)
+        .find_any(jobs, |cpu| cpu.collect_screen().unwrap().answer)
+        .unwrap();
+    dbg!(programs.clone().nth(index).unwrap(), result);
 }
@@ -1,9 +1,22 @@
-use std::fs;
 use advent_of_code::intcode::IntCodeCpu;
+use advent_of_code::intcode::messages::FromOutputs;
+
+struct Packet {
+    dst: usize,
+    x: i64,
+    y: i64,
+}
+
+impl FromOutputs for Packet {
+    const LEN: usize = 3;
+
+    fn from_outputs(values: &[i64]) -> Self {
+        Packet { dst: values[0] as usize, x: values[1], y: values[2] }
+    }
+}
 
 fn main() {
-    let input = fs::read_to_string("./input/day23.txt").unwrap();
-    let cpu = IntCodeCpu::from_code(&input);
+    let cpu = IntCodeCpu::from_file("./input/day23.txt").unwrap();
     solve(&cpu);
 }
 
@@ -21,10 +34,9 @@ fn solve(cpu: &IntCodeCpu) {
     loop {
         for i in 0..50 {
             cpus[i].run_until_io();
-            if let Some(dst) = cpus[i].output.pop_front() {
+            if !cpus[i].output.is_empty() {
                 did_send[i] = true;
-                let x = cpus[i].run_until_out().unwrap();
-                let y = cpus[i].run_until_out().unwrap();
+                let Packet { dst, x, y } = cpus[i].next_message().unwrap();
                 if dst == 255 {
                     nat = (x, y);
                     if is_first_nat_packet {
@@ -32,8 +44,8 @@ fn solve(cpu: &IntCodeCpu) {
                         is_first_nat_packet = false;
                     }
                 } else {
-                    cpus[dst as usize].input.push_back(x);
-                    cpus[dst as usize].input.push_back(y);
+                    cpus[dst].input.push_back(x);
+                    cpus[dst].input.push_back(y);
                 }
             } else if !cpus[i].input.is_empty() {
                 did_receive[i] = true;
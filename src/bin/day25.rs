@@ -1,82 +1,57 @@
-use std::fs;
 use advent_of_code::intcode::IntCodeCpu;
+use advent_of_code::intcode::expect::ExpectError;
 #[allow(unused_imports)]
 use std::io::stdin;
 
 fn main() {
-    let input = fs::read_to_string("./input/day25.txt").unwrap();
-    let cpu = IntCodeCpu::from_code(&input);
+    let cpu = IntCodeCpu::from_file("./input/day25.txt").unwrap();
     part1(&mut cpu.clone());
 }
 
 fn run_until_command(cpu: &mut IntCodeCpu) {
-    while let Some(out) = cpu.read_ascii_line() {
-        println!("{}", out);
-        if &out == "Command?" {
-            break;
-        }
+    match cpu.read_until_prompt("Command?") {
+        Ok(out) => println!("{}Command?", out),
+        Err(e) => println!("{}", e.transcript()),
     }
 }
 
 fn take_all_items(cpu: &mut IntCodeCpu) {
-    cpu.input_ascii("north\n");
-    run_until_command(cpu);
-    cpu.input_ascii("take tambourine\n");
-    run_until_command(cpu);
-    cpu.input_ascii("east\n");
-    run_until_command(cpu);
-    cpu.input_ascii("take astrolabe\n");
-    run_until_command(cpu);
-    cpu.input_ascii("east\n");
-    run_until_command(cpu);
-    cpu.input_ascii("north\n");
-    run_until_command(cpu);
-    cpu.input_ascii("take klein bottle\n");
-    run_until_command(cpu);
-    cpu.input_ascii("north\n");
-    run_until_command(cpu);
-    cpu.input_ascii("take easter egg\n");
-    run_until_command(cpu);
-    cpu.input_ascii("south\n");
-    run_until_command(cpu);
-    cpu.input_ascii("south\n");
-    run_until_command(cpu);
-    cpu.input_ascii("west\n");
-    run_until_command(cpu);
-    cpu.input_ascii("south\n");
-    run_until_command(cpu);
-    cpu.input_ascii("take shell\n");
-    run_until_command(cpu);
-    cpu.input_ascii("north\n");
-    run_until_command(cpu);
-    cpu.input_ascii("west\n");
-    run_until_command(cpu);
-    cpu.input_ascii("south\n");
-    run_until_command(cpu);
-    cpu.input_ascii("south\n");
-    run_until_command(cpu);
-    cpu.input_ascii("south\n");
-    run_until_command(cpu);
-    cpu.input_ascii("take hypercube\n");
-    run_until_command(cpu);
-    cpu.input_ascii("north\n");
-    run_until_command(cpu);
-    cpu.input_ascii("north\n");
-    run_until_command(cpu);
-    cpu.input_ascii("west\n");
-    run_until_command(cpu);
-    cpu.input_ascii("take dark matter\n");
-    run_until_command(cpu);
-    cpu.input_ascii("west\n");
-    run_until_command(cpu);
-    cpu.input_ascii("north\n");
-    run_until_command(cpu);
-    cpu.input_ascii("west\n");
-    run_until_command(cpu);
-    cpu.input_ascii("take coin\n");
-    run_until_command(cpu);
-    cpu.input_ascii("south\n");
     run_until_command(cpu);
+    let commands = [
+        "north",
+        "take tambourine",
+        "east",
+        "take astrolabe",
+        "east",
+        "north",
+        "take klein bottle",
+        "north",
+        "take easter egg",
+        "south",
+        "south",
+        "west",
+        "south",
+        "take shell",
+        "north",
+        "west",
+        "south",
+        "south",
+        "south",
+        "take hypercube",
+        "north",
+        "north",
+        "west",
+        "take dark matter",
+        "west",
+        "north",
+        "west",
+        "take coin",
+        "south",
+    ];
+    for command in commands.iter() {
+        cpu.send_line(command);
+        run_until_command(cpu);
+    }
 }
 
 fn part1(cpu: &mut IntCodeCpu) {
@@ -95,19 +70,14 @@ fn part1(cpu: &mut IntCodeCpu) {
         let mut cpu = cpu.clone();
         for (i, item) in items.iter().enumerate() {
             if combination & (1 << i) == 0 {
-                cpu.input_ascii("drop ");
-                cpu.input_ascii(item);
-                cpu.input_ascii("\n");
+                cpu.send_line(&format!("drop {}", item));
                 run_until_command(&mut cpu);
             }
         }
-        cpu.input_ascii("south\n");
-        run_until_command(&mut cpu);
-        while let Some(out) = cpu.read_ascii_line() {
-            println!("{}", out);
-            if out.contains("keypad") {
-                return;
-            }
+        cpu.send_line("south");
+        if let Err(ExpectError::Halted { transcript }) = cpu.read_until_prompt("Command?") {
+            println!("{}", transcript);
+            return;
         }
     }
     // interactive mode
@@ -116,7 +86,7 @@ fn part1(cpu: &mut IntCodeCpu) {
         run_until_command(cpu);
         let mut cmd = String::new();
         stdin().read_line(&mut cmd).unwrap();
-        cpu.input_ascii(&cmd);
+        cpu.send_line(cmd.trim_end());
     }
     */
 }
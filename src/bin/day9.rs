@@ -1,9 +1,7 @@
-use std::fs;
 use advent_of_code::intcode::IntCodeCpu;
 
 fn main() {
-    let input = fs::read_to_string("./input/day9.txt").unwrap();
-    let cpu = IntCodeCpu::from_code(&input);
+    let cpu = IntCodeCpu::from_file("./input/day9.txt").unwrap();
     part1(&mut cpu.clone());
     part2(&mut cpu.clone());
 }
@@ -0,0 +1,244 @@
+// C interface to the intcode VM, see ffi/intcode.h. CPUs are handed out as opaque pointers
+// that must be released with intcode_free, every function taking a CPU expects such a
+// pointer that hasn't been freed yet. Faults (bad opcodes etc.) don't unwind into C and
+// aren't printed, they stop the CPU and are reported as INTCODE_FAULT by the functions that
+// execute code.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::ptr;
+use crate::intcode::IntCodeCpu;
+use crate::intcode::fault::catch_fault;
+
+pub const INTCODE_FAULT: i32 = -1;
+pub const INTCODE_HALTED: i32 = 0;
+pub const INTCODE_RUNNING: i32 = 1;
+pub const INTCODE_MAX_GROWTH: usize = 1048576;
+
+fn execute<F: FnOnce(&mut IntCodeCpu)>(cpu: &mut IntCodeCpu, f: F) -> i32 {
+    match catch_fault(|| f(cpu)) {
+        Ok(()) if cpu.running => INTCODE_RUNNING,
+        Ok(()) => INTCODE_HALTED,
+        Err(_) => {
+            cpu.running = false;
+            INTCODE_FAULT
+        }
+    }
+}
+
+// Parses a comma-separated program, returns NULL if `code` isn't a valid program.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_create(code: *const c_char) -> *mut IntCodeCpu {
+    if code.is_null() {
+        return ptr::null_mut();
+    }
+    let code = match CStr::from_ptr(code).to_str() {
+        Ok(code) => code,
+        Err(_) => return ptr::null_mut(),
+    };
+    match crate::intcode::loader::parse_program(code) {
+        Ok(program) => Box::into_raw(Box::new(IntCodeCpu::from_program(program))),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn intcode_clone(cpu: *const IntCodeCpu) -> *mut IntCodeCpu {
+    Box::into_raw(Box::new((*cpu).clone()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn intcode_free(cpu: *mut IntCodeCpu) {
+    if !cpu.is_null() {
+        drop(Box::from_raw(cpu));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn intcode_push_input(cpu: *mut IntCodeCpu, value: i64) {
+    (*cpu).input.push_back(value);
+}
+
+// Stores the oldest pending output in `value`, returns false if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_pop_output(cpu: *mut IntCodeCpu, value: *mut i64) -> bool {
+    match (*cpu).output.pop_front() {
+        Some(output) => {
+            *value = output;
+            true
+        }
+        None => false,
+    }
+}
+
+// Executes a single instruction, returns INTCODE_RUNNING, INTCODE_HALTED or INTCODE_FAULT.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_step(cpu: *mut IntCodeCpu) -> i32 {
+    execute(&mut *cpu, IntCodeCpu::single_step)
+}
+
+// Runs until an input or output instruction was executed or the program halted.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_run_until_io(cpu: *mut IntCodeCpu) -> i32 {
+    execute(&mut *cpu, IntCodeCpu::run_until_io)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn intcode_run(cpu: *mut IntCodeCpu) -> i32 {
+    execute(&mut *cpu, IntCodeCpu::run)
+}
+
+// True if the next instruction reads input and none is queued.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_waiting_for_input(cpu: *const IntCodeCpu) -> bool {
+    (*cpu).waiting_for_input()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn intcode_memory_size(cpu: *const IntCodeCpu) -> usize {
+    (&*cpu).memory.len()
+}
+
+// Addresses beyond the end of memory read as 0.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_read_memory(cpu: *const IntCodeCpu, addr: usize) -> i64 {
+    (&*cpu).memory.get(addr).copied().unwrap_or(0)
+}
+
+// Memory grows by up to INTCODE_MAX_GROWTH cells, writes beyond that return INTCODE_FAULT
+// and change nothing. Otherwise returns INTCODE_RUNNING or INTCODE_HALTED.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_write_memory(cpu: *mut IntCodeCpu, addr: usize, value: i64) -> i32 {
+    let cpu = &mut *cpu;
+    if addr.saturating_sub(cpu.memory.len()) >= INTCODE_MAX_GROWTH {
+        return INTCODE_FAULT;
+    }
+    cpu.write_memory(addr, value);
+    if cpu.running { INTCODE_RUNNING } else { INTCODE_HALTED }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn intcode_ip(cpu: *const IntCodeCpu) -> usize {
+    (*cpu).ip()
+}
+
+// Builds ffi/intcode.h from the exported functions in this file. Only the handful of types
+// used above are supported.
+#[cfg(test)]
+fn generate_header(source: &str) -> String {
+    let c_type = |rust: &str| match rust.trim() {
+        "*const c_char" => "const char *",
+        "*mut IntCodeCpu" => "intcode_cpu *",
+        "*const IntCodeCpu" => "const intcode_cpu *",
+        "*mut i64" => "int64_t *",
+        "i64" => "int64_t ",
+        "i32" => "int32_t ",
+        "usize" => "size_t ",
+        "bool" => "bool ",
+        "" => "void ",
+        other => panic!("no C type for {}", other),
+    };
+    let mut header = String::from("\
+/* Generated from src/ffi.rs by test_ffi_header_is_up_to_date, do not edit. */
+#ifndef INTCODE_H
+#define INTCODE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern \"C\" {
+#endif
+
+typedef struct intcode_cpu intcode_cpu;
+
+");
+    // comment lines directly in front of a function are copied into the header
+    let mut comment = vec![];
+    for line in source.lines() {
+        let line = line.trim();
+        if let Some(constant) = line.strip_prefix("pub const ") {
+            let name = &constant[..constant.find(':').unwrap()];
+            let value = constant[constant.find('=').unwrap() + 1..].trim_end_matches(';').trim();
+            header.push_str(&format!("#define {} {}\n", name, value));
+        } else if let Some(text) = line.strip_prefix("// ") {
+            comment.push(text.to_string());
+        } else if let Some(signature) = line.strip_prefix("pub unsafe extern \"C\" fn ") {
+            let signature = signature.trim_end_matches('{').trim();
+            let open = signature.find('(').unwrap();
+            let close = signature.rfind(')').unwrap();
+            let args = signature[open + 1..close].split(", ")
+                .map(|arg| {
+                    let (name, ty) = arg.split_at(arg.find(':').unwrap());
+                    format!("{}{}", c_type(&ty[1..]), name)
+                })
+                .collect::<Vec<String>>();
+            let ret = signature[close + 1..].trim().trim_start_matches("->");
+            header.push('\n');
+            if !comment.is_empty() {
+                header.push_str(&format!("/* {} */\n", comment.join("\n   ")));
+            }
+            header.push_str(&format!("{}{}({});\n", c_type(ret), &signature[..open], args.join(", ")));
+        }
+        if !line.starts_with("// ") && !line.starts_with("#[") {
+            comment.clear();
+        }
+    }
+    header.push_str("\n#ifdef __cplusplus\n}\n#endif\n\n#endif\n");
+    header
+}
+
+#[test]
+fn test_ffi_header_is_up_to_date() {
+    let header = generate_header(include_str!("ffi.rs"));
+    if std::env::var_os("UPDATE_FFI_HEADER").is_some() {
+        std::fs::write("./ffi/intcode.h", &header).unwrap();
+    }
+    assert_eq!(std::fs::read_to_string("./ffi/intcode.h").unwrap(), header,
+               "ffi/intcode.h is outdated, rerun with UPDATE_FFI_HEADER=1");
+}
+
+#[test]
+fn test_ffi_roundtrip() {
+    unsafe {
+        let code = std::ffi::CString::new("3,0,4,0,1101,20,22,9,99,0").unwrap();
+        let cpu = intcode_create(code.as_ptr());
+        assert!(!cpu.is_null());
+        assert!(intcode_waiting_for_input(cpu));
+        intcode_push_input(cpu, 17);
+        assert_eq!(intcode_run_until_io(cpu), INTCODE_RUNNING);
+        let copy = intcode_clone(cpu);
+        let mut value = 0;
+        assert_eq!(intcode_run_until_io(cpu), INTCODE_RUNNING);
+        assert!(intcode_pop_output(cpu, &mut value));
+        assert_eq!(value, 17);
+        assert!(!intcode_pop_output(cpu, &mut value));
+        assert_eq!(intcode_step(cpu), INTCODE_RUNNING);
+        assert_eq!(intcode_read_memory(cpu, 9), 42);
+        assert_eq!(intcode_step(cpu), INTCODE_HALTED);
+        // the clone is still in front of the output instruction
+        assert_eq!(intcode_ip(copy), 2);
+        assert_eq!(intcode_write_memory(copy, 0, 7), INTCODE_RUNNING);
+        assert_eq!(intcode_write_memory(copy, usize::MAX / 8, 7), INTCODE_FAULT);
+        assert_eq!(intcode_memory_size(copy), 10);
+        assert_eq!(intcode_run(copy), INTCODE_HALTED);
+        assert!(intcode_pop_output(copy, &mut value));
+        assert_eq!(value, 7);
+        intcode_free(copy);
+        intcode_free(cpu);
+    }
+}
+
+#[test]
+fn test_ffi_fault() {
+    unsafe {
+        let code = std::ffi::CString::new("1,0,0,0,42").unwrap();
+        let cpu = intcode_create(code.as_ptr());
+        assert_eq!(intcode_run(cpu), INTCODE_FAULT);
+        assert_eq!(intcode_step(cpu), INTCODE_HALTED);
+        intcode_free(cpu);
+        assert!(intcode_create(std::ffi::CString::new("1,x").unwrap().as_ptr()).is_null());
+    }
+}
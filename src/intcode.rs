@@ -1,22 +1,97 @@
-use std::collections::VecDeque;
+pub mod async_cpu;
+pub mod batch;
+pub mod cfg;
+pub mod compiler;
+#[cfg(feature = "hooks")]
+pub mod coverage;
+#[cfg(feature = "hooks")]
+pub mod debugger;
+pub mod decompiler;
+pub mod devices;
+pub mod disasm;
+pub mod expect;
+pub mod explorer;
+pub mod extensions;
+pub mod fault;
+pub mod framebuffer;
+pub mod gdbstub;
+#[cfg(feature = "hooks")]
+pub mod hooks;
+pub mod loader;
+pub mod memdiff;
+pub mod memory;
+pub mod messages;
+pub mod optimizer;
+pub mod profile;
+pub mod recording;
+pub mod spec;
+pub mod symbolic;
+pub mod symbols;
+#[cfg(feature = "hooks")]
+pub mod watch;
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use devices::Devices;
+use extensions::Extension;
+#[cfg(feature = "hooks")]
+use hooks::Hooks;
+use memory::Memory;
+use profile::Profile;
+use recording::{IoEvent, Recording};
 
 #[derive(Clone)]
 pub struct IntCodeCpu {
     ip: usize,
-    rbp: usize,
+    rbp: i64,
+    address_policy: AddressPolicy,
+    // memory size negative addresses wrap around with `AddressPolicy::Wrap`
+    wrap_size: usize,
+    profile: Profile,
+    steps: u64,
+    step_budget: Option<u64>,
+    recording: Option<(u64, Recording)>,
+    extensions: HashMap<i64, Extension>,
+    strict: bool,
+    devices: Devices,
+    #[cfg(feature = "hooks")]
+    hooks: Hooks,
     pub running: bool,
     pub input: VecDeque<i64>,
     pub output: VecDeque<i64>,
-    pub memory: Vec<i64>,
+    pub memory: Memory,
 }
 
-#[derive(Debug)]
-enum ParameterMode {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParameterMode {
     Position,
     Immediate,
     Relative,
 }
 
+impl ParameterMode {
+    // mode of the n-th (0-based) operand of an encoded instruction
+    pub fn of_operand(inst: i64, n: u32) -> ParameterMode {
+        match inst / 10_i64.pow(n + 2) % 10 {
+            1 => ParameterMode::Immediate,
+            2 => ParameterMode::Relative,
+            _ => ParameterMode::Position
+        }
+    }
+}
+
+// What happens when an operand addresses a negative cell or a relative address overflows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressPolicy {
+    // panic like for any other invalid instruction
+    Fault,
+    // count from the end of the memory as it was when the policy was set, like negative
+    // indices in Python, so the same address always hits the same cell
+    Wrap,
+    // reads return 0, writes are dropped
+    Ignore,
+}
+
 enum Instruction {
     Add { src1: i64, src2: i64, dst: i64 },
     Mul { src1: i64, src2: i64, dst: i64 },
@@ -28,29 +103,105 @@ enum Instruction {
     Equals { src1: i64, src2: i64, dst: i64 },
     AdjustRbp { src: i64 },
     Halt,
+    Extension { opcode: i64, args: Vec<i64> },
 }
 
 impl IntCodeCpu {
     pub fn from_code(code: &str) -> IntCodeCpu {
+        match loader::parse_program(code) {
+            Ok(program) => IntCodeCpu::from_program(program),
+            Err(e) => panic!("bad program: {}", e),
+        }
+    }
+
+    pub fn from_program(program: Vec<i64>) -> IntCodeCpu {
         IntCodeCpu {
             ip: 0,
             rbp: 0,
+            address_policy: AddressPolicy::Fault,
+            wrap_size: 1,
+            profile: Profile::FULL,
+            steps: 0,
+            step_budget: None,
+            recording: None,
+            extensions: HashMap::new(),
+            strict: false,
+            devices: Devices::default(),
+            #[cfg(feature = "hooks")]
+            hooks: Hooks::default(),
             running: true,
             input: VecDeque::new(),
             output: VecDeque::new(),
-            memory: code.split(',').map(|e| e.trim().parse::<i64>().unwrap()).collect(),
+            memory: Memory::new(program),
+        }
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn rbp(&self) -> i64 {
+        self.rbp
+    }
+
+    pub fn set_address_policy(&mut self, policy: AddressPolicy) {
+        self.address_policy = policy;
+        self.wrap_size = self.memory.len().max(1);
+    }
+
+    // O(1) hash of ip, rbp and memory, see `Memory` for how it's maintained
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn start_recording(&mut self) {
+        self.recording = Some((self.steps, Recording::default()));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take().map(|(_, recording)| recording)
+    }
+
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref().map(|(_, recording)| recording)
+    }
+
+    fn record(&mut self, event: impl FnOnce(u64) -> IoEvent) {
+        if let Some((start, recording)) = &mut self.recording {
+            recording.events.push(event(self.steps - *start));
         }
     }
 
+    // The run methods also return once a watchpoint fired, see `watch::take_stop_reason`.
     pub fn run(&mut self) {
         while self.running {
             self.step();
+            if self.watchpoint_fired() {
+                break;
+            }
+        }
+    }
+
+    pub fn single_step(&mut self) {
+        if self.running {
+            self.step();
+            self.watchpoint_fired();
         }
     }
 
     pub fn run_until_io(&mut self) {
         while self.running {
-            match self.step() {
+            let inst = self.step();
+            if self.watchpoint_fired() {
+                break;
+            }
+            match inst {
                 Instruction::In { .. } => break,
                 Instruction::Out { .. } => break,
                 _ => {}
@@ -58,16 +209,25 @@ impl IntCodeCpu {
         }
     }
 
+    // `None` if the program halted or a watchpoint fired before it output something.
     pub fn run_until_out(&mut self) -> Option<i64> {
         while self.running {
             self.step();
             if let Some(output) = self.output.pop_front() {
                 return Some(output);
             }
+            if self.watchpoint_fired() {
+                break;
+            }
         }
         None
     }
 
+    #[cfg(not(feature = "hooks"))]
+    fn watchpoint_fired(&mut self) -> bool {
+        false
+    }
+
     pub fn input_ascii(&mut self, ascii: &str) {
         ascii.chars().for_each(|c| self.input.push_back(c as i64));
         while !self.input.is_empty() {
@@ -94,54 +254,93 @@ impl IntCodeCpu {
         Some(result)
     }
 
-    fn fetch_and_resize_memory(&mut self, addr: usize) -> i64 {
-        if addr >= self.memory.len() {
-            self.memory.resize(addr + 1, 0);
+    // Applies the address policy, `None` means the access is ignored.
+    fn checked_address(&self, addr: i64) -> Option<usize> {
+        if addr >= 0 {
+            return Some(addr as usize);
+        }
+        match self.address_policy {
+            AddressPolicy::Fault => panic!("negative address {} at ip {} (rbp={})", addr, self.ip, self.rbp),
+            AddressPolicy::Wrap => Some(addr.rem_euclid(self.wrap_size as i64) as usize),
+            AddressPolicy::Ignore => None,
+        }
+    }
+
+    // rbp + offset, a sum that doesn't fit into an i64 goes through the address policy
+    fn relative_address(&self, offset: i64) -> i64 {
+        match self.rbp.checked_add(offset) {
+            Some(addr) => addr,
+            None => match self.address_policy {
+                AddressPolicy::Fault => panic!("relative address {} + {} overflows at ip {}", self.rbp, offset, self.ip),
+                AddressPolicy::Wrap => (i128::from(self.rbp) + i128::from(offset)).rem_euclid(self.wrap_size as i128) as i64,
+                // any negative address is ignored
+                AddressPolicy::Ignore => -1,
+            },
         }
-        self.memory[addr]
     }
 
-    fn store_and_resize_memory(&mut self, addr: usize, val: i64) {
-        if addr >= self.memory.len() {
-            self.memory.resize(addr + 1, 0);
+    fn fetch_and_resize_memory(&mut self, addr: i64) -> i64 {
+        let addr = match self.checked_address(addr) {
+            Some(addr) => addr,
+            None => return 0,
+        };
+        let value = match self.devices.read(addr) {
+            Some(value) => value,
+            None => {
+                self.memory.resize(addr + 1);
+                self.memory[addr]
+            }
+        };
+        #[cfg(feature = "hooks")]
+        self.notify(|hook| hook.memory_read(addr, value));
+        value
+    }
+
+    pub fn write_memory(&mut self, addr: usize, val: i64) {
+        #[cfg(feature = "hooks")]
+        let old = if self.hooks.is_empty() {
+            0
+        } else {
+            self.devices.peek(addr).unwrap_or_else(|| self.memory.get(addr).copied().unwrap_or(0))
+        };
+        if !self.devices.write(addr, val) {
+            self.memory.write(addr, val);
+        }
+        #[cfg(feature = "hooks")]
+        self.notify(|hook| hook.memory_write(addr, old, val));
+    }
+
+    fn store_and_resize_memory(&mut self, addr: i64, val: i64) {
+        if let Some(addr) = self.checked_address(addr) {
+            self.write_memory(addr, val);
         }
-        self.memory[addr] = val;
     }
 
     fn fetch_dst_address(&self, mode: ParameterMode, immediate: i64) -> i64 {
         match mode {
             ParameterMode::Position => immediate,
             ParameterMode::Immediate => panic!("dst operand cannot use immediate mode"),
-            ParameterMode::Relative => self.rbp as i64 + immediate,
+            ParameterMode::Relative => self.relative_address(immediate),
         }
     }
 
     fn fetch_operand(&mut self, mode: ParameterMode, immediate: i64) -> i64 {
         match mode {
-            ParameterMode::Position => self.fetch_and_resize_memory(immediate as usize),
+            ParameterMode::Position => self.fetch_and_resize_memory(immediate),
             ParameterMode::Immediate => immediate,
-            ParameterMode::Relative => self.fetch_and_resize_memory((self.rbp as i64 + immediate) as usize),
+            ParameterMode::Relative => self.fetch_and_resize_memory(self.relative_address(immediate)),
         }
     }
 
     fn fetch_and_decode(&mut self) -> Instruction {
         let inst = self.memory[self.ip];
+        if self.profile != Profile::FULL {
+            self.check_profile(inst);
+        }
         let opcode = inst % 100;
-        let mode1 = match inst / 100 % 10 {
-            1 => ParameterMode::Immediate,
-            2 => ParameterMode::Relative,
-            _ => ParameterMode::Position
-        };
-        let mode2 = match inst / 1_000 % 10 {
-            1 => ParameterMode::Immediate,
-            2 => ParameterMode::Relative,
-            _ => ParameterMode::Position
-        };
-        let mode3 = match inst / 10_000 % 10 {
-            1 => ParameterMode::Immediate,
-            2 => ParameterMode::Relative,
-            _ => ParameterMode::Position
-        };
+        let mode1 = ParameterMode::of_operand(inst, 0);
+        let mode2 = ParameterMode::of_operand(inst, 1);
+        let mode3 = ParameterMode::of_operand(inst, 2);
         match opcode {
             1 => Instruction::Add {
                 src1: self.fetch_operand(mode1, self.memory[self.ip + 1]),
@@ -181,27 +380,59 @@ impl IntCodeCpu {
                 src: self.fetch_operand(mode1, self.memory[self.ip + 1])
             },
             99 => Instruction::Halt,
-            _ => panic!("bad opcode {}", opcode),
+            _ => self.fetch_extension(inst),
         }
     }
 
+    fn fetch_extension(&mut self, inst: i64) -> Instruction {
+        let opcode = inst % 100;
+        let (operand_count, dst_operands) = match self.extensions.get(&opcode) {
+            Some(extension) => (extension.operand_count, extension.dst_operands.clone()),
+            None => panic!("bad opcode {}", opcode),
+        };
+        if self.strict {
+            panic!("extension opcode {} used in strict mode", opcode);
+        }
+        let args = (0..operand_count).map(|n| {
+            let mode = ParameterMode::of_operand(inst, n as u32);
+            let immediate = self.memory[self.ip + 1 + n];
+            if dst_operands.contains(&n) {
+                self.fetch_dst_address(mode, immediate)
+            } else {
+                self.fetch_operand(mode, immediate)
+            }
+        }).collect();
+        Instruction::Extension { opcode, args }
+    }
+
     fn execute(&mut self, inst: &Instruction) {
         match inst {
             Instruction::Add { src1, src2, dst } => {
-                self.store_and_resize_memory(*dst as usize, src1 + src2);
+                self.store_and_resize_memory(*dst, src1 + src2);
                 self.ip += 4;
             }
             Instruction::Mul { src1, src2, dst } => {
-                self.store_and_resize_memory(*dst as usize, src1 * src2);
+                self.store_and_resize_memory(*dst, src1 * src2);
                 self.ip += 4;
             }
             Instruction::In { dst } => {
-                let src = self.input.pop_front().unwrap_or(-1);
-                self.store_and_resize_memory(*dst as usize, src);
+                let src = match self.input.pop_front() {
+                    Some(src) => src,
+                    None => self.devices.input().unwrap_or(-1),
+                };
+                self.record(|step| IoEvent::Input { step, value: src });
+                #[cfg(feature = "hooks")]
+                self.notify(|hook| hook.input(src));
+                self.store_and_resize_memory(*dst, src);
                 self.ip += 2;
             }
             Instruction::Out { src } => {
-                self.output.push_back(*src);
+                if !self.devices.output(*src) {
+                    self.output.push_back(*src);
+                }
+                self.record(|step| IoEvent::Output { step, value: *src });
+                #[cfg(feature = "hooks")]
+                self.notify(|hook| hook.output(*src));
                 self.ip += 2;
             }
             Instruction::JumpNotZero { cond, target } => {
@@ -219,30 +450,77 @@ impl IntCodeCpu {
                 }
             }
             Instruction::LessThan { src1, src2, dst } => {
-                self.store_and_resize_memory(*dst as usize, if *src1 < *src2 { 1 } else { 0 });
+                self.store_and_resize_memory(*dst, if *src1 < *src2 { 1 } else { 0 });
                 self.ip += 4;
             }
             Instruction::Equals { src1, src2, dst } => {
-                self.store_and_resize_memory(*dst as usize, if *src1 == *src2 { 1 } else { 0 });
+                self.store_and_resize_memory(*dst, if *src1 == *src2 { 1 } else { 0 });
                 self.ip += 4;
             }
             Instruction::AdjustRbp { src } => {
-                self.rbp = ((self.rbp as i64) + *src) as usize;
+                self.rbp = self.rbp.checked_add(*src)
+                    .unwrap_or_else(|| panic!("relative base {} + {} overflows at ip {}", self.rbp, src, self.ip));
                 self.ip += 2;
             }
             Instruction::Halt => {
                 self.running = false;
             }
+            Instruction::Extension { opcode, args } => {
+                let handler = self.extensions[opcode].handler();
+                let ip = self.ip;
+                handler(self, args);
+                if self.running && self.ip == ip {
+                    self.ip += 1 + args.len();
+                }
+            }
         }
     }
 
     fn step(&mut self) -> Instruction {
+        #[cfg(feature = "hooks")]
+        let ip = self.ip;
+        #[cfg(feature = "hooks")]
+        {
+            let encoded = self.memory[ip];
+            self.notify(|hook| hook.before_decode(ip, encoded));
+        }
         let inst = self.fetch_and_decode();
         self.execute(&inst);
+        #[cfg(feature = "hooks")]
+        {
+            let next_ip = self.ip;
+            self.notify(|hook| hook.after_execute(ip, next_ip));
+        }
+        self.steps += 1;
+        if !self.devices.is_empty() {
+            self.devices.tick();
+        }
         inst
     }
 }
 
+// Two CPUs are equal if the machine is in the same state. Pending input and output, attached
+// devices and debugging state like recordings or hooks aren't compared.
+impl PartialEq for IntCodeCpu {
+    fn eq(&self, other: &Self) -> bool {
+        self.ip == other.ip
+            && self.rbp == other.rbp
+            && self.running == other.running
+            && self.memory == other.memory
+    }
+}
+
+impl Eq for IntCodeCpu {}
+
+impl Hash for IntCodeCpu {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ip.hash(state);
+        self.rbp.hash(state);
+        self.running.hash(state);
+        self.memory.hash(state);
+    }
+}
+
 #[test]
 fn test_step_add_mul() {
     let mut cpu = IntCodeCpu::from_code("1,4,5,6,10,20,0");
@@ -327,3 +605,91 @@ fn test_large_numbers() {
     assert_eq!(cpu.output.pop_front(), Some(34_915_192 * 34_915_192));
 }
 
+#[test]
+fn test_detect_repeated_state() {
+    // counts mem[30] up to 3, then resets it and jumps back to the start
+    let initial = IntCodeCpu::from_code("1001,30,1,30,1008,30,3,31,1006,31,0,1101,0,0,30,1101,0,0,31,1105,1,0");
+    let mut cpu = initial.clone();
+    let mut seen = std::collections::HashSet::new();
+    while seen.insert(cpu.clone()) {
+        cpu.step();
+    }
+    assert_eq!(cpu.steps(), 12);
+    assert!(cpu == initial);
+    assert_eq!(cpu.fingerprint(), initial.fingerprint());
+    cpu.output.push_back(1);
+    assert!(cpu == initial);
+    cpu.write_memory(40, 1);
+    assert!(cpu != initial);
+}
+
+#[test]
+fn test_negative_relative_addressing() {
+    // rbp goes negative and comes back, only the addresses actually used must be valid
+    let mut cpu = IntCodeCpu::from_code("109,-5,21101,1,2,10,204,10,109,7,4,3,99");
+    cpu.run();
+    assert_eq!(cpu.rbp(), 2);
+    assert_eq!(cpu.output, vec![3, 1]);
+    assert_eq!(cpu.memory[5], 3);
+}
+
+#[test]
+#[should_panic(expected = "negative address -3 at ip 2 (rbp=-1)")]
+fn test_negative_address_faults() {
+    let mut cpu = IntCodeCpu::from_code("109,-1,204,-2,99");
+    cpu.run();
+}
+
+#[test]
+fn test_negative_address_policies() {
+    // out [rbp-2], add 7, 0, [rbp-1] and out [-1] with rbp = 0
+    let program = "204,-2,21101,7,0,-1,4,-1,99,0";
+    let mut cpu = IntCodeCpu::from_code(program);
+    cpu.set_address_policy(AddressPolicy::Wrap);
+    cpu.run();
+    assert_eq!(cpu.output, vec![99, 7]);
+    assert_eq!(cpu.memory[9], 7);
+
+    let mut cpu = IntCodeCpu::from_code(program);
+    cpu.set_address_policy(AddressPolicy::Ignore);
+    cpu.run();
+    assert_eq!(cpu.output, vec![0, 0]);
+    assert_eq!(cpu.memory, vec![204, -2, 21101, 7, 0, -1, 4, -1, 99, 0]);
+}
+
+#[test]
+fn test_wrap_size_is_fixed() {
+    // add 5, 0, [rbp-1], grow the memory with add 0, 0, [20], out [rbp-1]
+    let mut cpu = IntCodeCpu::from_code("21101,5,0,-1,1101,0,0,20,204,-1,99,0");
+    cpu.set_address_policy(AddressPolicy::Wrap);
+    cpu.run();
+    assert_eq!(cpu.memory.len(), 21);
+    assert_eq!(cpu.output, vec![5]);
+}
+
+#[test]
+fn test_relative_address_overflow() {
+    // out [rbp+1] with rbp = i64::MAX
+    let program = "109,9223372036854775807,204,1,99";
+    let mut cpu = IntCodeCpu::from_code(program);
+    cpu.set_address_policy(AddressPolicy::Ignore);
+    cpu.run();
+    assert_eq!(cpu.output, vec![0]);
+    // 2^63 % 5 == 3
+    let mut cpu = IntCodeCpu::from_code(program);
+    cpu.set_address_policy(AddressPolicy::Wrap);
+    cpu.run();
+    assert_eq!(cpu.output, vec![1]);
+}
+
+#[test]
+#[should_panic(expected = "relative address 9223372036854775807 + 1 overflows at ip 2")]
+fn test_relative_address_overflow_faults() {
+    IntCodeCpu::from_code("109,9223372036854775807,204,1,99").run();
+}
+
+#[test]
+#[should_panic(expected = "relative base 9223372036854775807 + 1 overflows at ip 2")]
+fn test_relative_base_overflow_faults() {
+    IntCodeCpu::from_code("109,9223372036854775807,109,1,99").run();
+}
@@ -0,0 +1,342 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use super::IntCodeCpu;
+
+// Minimal asynchronous iterator, so no external runtime is needed.
+pub trait Stream {
+    type Item;
+
+    fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<Self::Item>>;
+
+    fn next(&mut self) -> Next<'_, Self> where Self: Sized {
+        Next { stream: self }
+    }
+}
+
+pub struct Next<'a, S> {
+    stream: &'a mut S,
+}
+
+impl<S: Stream> Future for Next<'_, S> {
+    type Output = Option<S::Item>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.stream.poll_next(cx)
+    }
+}
+
+// Resolves immediately with the next item if one is ready, `None` if the stream would block.
+struct TryNext<'a, S> {
+    stream: &'a mut S,
+}
+
+impl<S: Stream> Future for TryNext<'_, S> {
+    type Output = Option<Option<S::Item>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match self.stream.poll_next(cx) {
+            Poll::Ready(item) => Poll::Ready(Some(item)),
+            Poll::Pending => Poll::Ready(None),
+        }
+    }
+}
+
+pub struct Iter<I> {
+    iter: I,
+}
+
+impl<I: Iterator> Stream for Iter<I> {
+    type Item = I::Item;
+
+    fn poll_next(&mut self, _: &mut Context) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.iter.next())
+    }
+}
+
+pub fn iter<I: IntoIterator>(iter: I) -> Iter<I::IntoIter> {
+    Iter { iter: iter.into_iter() }
+}
+
+struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+// Lets the other tasks run before continuing.
+pub fn yield_now() -> impl Future<Output = ()> {
+    YieldNow { yielded: false }
+}
+
+struct Channel<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    waker: Option<Waker>,
+}
+
+impl<T> Channel<T> {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// Unbounded channel, the receiving stream ends once all senders are dropped.
+pub struct Sender<T> {
+    channel: Arc<Mutex<Channel<T>>>,
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Mutex<Channel<T>>>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Mutex::new(Channel { queue: VecDeque::new(), senders: 1, waker: None }));
+    (Sender { channel: channel.clone() }, Receiver { channel })
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) {
+        let mut channel = self.channel.lock().unwrap();
+        channel.queue.push_back(value);
+        channel.wake();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.lock().unwrap().senders += 1;
+        Sender { channel: self.channel.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut channel = self.channel.lock().unwrap();
+        channel.senders -= 1;
+        if channel.senders == 0 {
+            channel.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.channel.lock().unwrap().queue.pop_front()
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<T>> {
+        let mut channel = self.channel.lock().unwrap();
+        match channel.queue.pop_front() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if channel.senders == 0 => Poll::Ready(None),
+            None => {
+                channel.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl IntCodeCpu {
+    // Runs until the program halts or `input` ends while the CPU is waiting for input. Queued
+    // input is used first, outputs are sent to `output` as soon as they are produced. With
+    // `idle_input` the CPU doesn't wait for input that isn't available yet but reads that
+    // value and lets other tasks run, like day 23's network interface expects.
+    pub async fn run_async<S: Stream<Item = i64>>(&mut self, input: &mut S, output: &Sender<i64>, idle_input: Option<i64>) {
+        while self.running {
+            if self.waiting_for_input() {
+                let value = match idle_input {
+                    None => input.next().await,
+                    Some(idle) => match (TryNext { stream: &mut *input }).await {
+                        Some(value) => value,
+                        None => {
+                            yield_now().await;
+                            Some(idle)
+                        }
+                    },
+                };
+                match value {
+                    Some(value) => self.input.push_back(value),
+                    None => return,
+                }
+            }
+            self.step();
+            for value in self.output.drain(..) {
+                output.send(value);
+            }
+        }
+    }
+}
+
+type Task<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.ready.lock().unwrap().push_back(self.id);
+    }
+}
+
+// Single-threaded executor, tasks only get polled again after they were woken.
+#[derive(Default)]
+pub struct Executor<'a> {
+    tasks: Vec<Option<Task<'a>>>,
+    ready: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new() -> Executor<'a> {
+        Executor::default()
+    }
+
+    pub fn spawn<F: Future<Output = ()> + 'a>(&mut self, future: F) {
+        self.ready.lock().unwrap().push_back(self.tasks.len());
+        self.tasks.push(Some(Box::pin(future)));
+    }
+
+    // Spawns a task running the CPU and returns its outputs as a stream.
+    pub fn spawn_cpu<S>(&mut self, mut cpu: IntCodeCpu, mut input: S, idle_input: Option<i64>) -> Receiver<i64>
+        where S: Stream<Item = i64> + 'a {
+        let (sender, receiver) = channel();
+        self.spawn(async move {
+            cpu.run_async(&mut input, &sender, idle_input).await;
+        });
+        receiver
+    }
+
+    // Runs until every task finished or is waiting for something no task will provide.
+    // Returns the number of unfinished tasks.
+    pub fn run(&mut self) -> usize {
+        self.run_until(|| false);
+        self.tasks.iter().filter(|task| task.is_some()).count()
+    }
+
+    // Runs the other tasks until `future` completes, returns `None` if they got stuck before.
+    pub fn block_on<F>(&mut self, future: F) -> Option<F::Output> where F: Future + 'a {
+        let result = Rc::new(RefCell::new(None));
+        let slot = result.clone();
+        self.spawn(async move {
+            *slot.borrow_mut() = Some(future.await);
+        });
+        self.run_until(|| result.borrow().is_some());
+        result.take()
+    }
+
+    fn run_until<F: Fn() -> bool>(&mut self, done: F) {
+        while !done() {
+            let id = match self.ready.lock().unwrap().pop_front() {
+                Some(id) => id,
+                None => return,
+            };
+            if let Some(task) = &mut self.tasks[id] {
+                let waker = Waker::from(Arc::new(TaskWaker { id, ready: self.ready.clone() }));
+                if task.as_mut().poll(&mut Context::from_waker(&waker)).is_ready() {
+                    self.tasks[id] = None;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_async_pipeline() {
+    let mut executor = Executor::new();
+    let doubler = IntCodeCpu::from_code("3,11,1002,11,2,11,4,11,1105,1,0");
+    let first = executor.spawn_cpu(doubler.clone(), iter(vec![1, 2, 3]), None);
+    let mut second = executor.spawn_cpu(doubler, first, None);
+    let collected = executor.block_on(async move {
+        let mut collected = vec![];
+        while let Some(value) = second.next().await {
+            collected.push(value);
+        }
+        collected
+    });
+    assert_eq!(collected, Some(vec![4, 8, 12]));
+    assert_eq!(executor.run(), 0);
+}
+
+#[test]
+fn test_async_feedback_loop() {
+    // day 7 part 2 example
+    let amplifier = IntCodeCpu::from_code("3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5");
+    let mut executor = Executor::new();
+    let (feedback, input) = channel();
+    feedback.send(0);
+    let mut signal = [9, 8, 7, 6, 5].iter().fold(input, |input, phase| {
+        let mut cpu = amplifier.clone();
+        cpu.input.push_back(*phase);
+        executor.spawn_cpu(cpu, input, None)
+    });
+    let thruster = executor.block_on(async move {
+        let mut last = None;
+        while let Some(value) = signal.next().await {
+            last = Some(value);
+            feedback.send(value);
+        }
+        last
+    });
+    assert_eq!(thruster, Some(Some(139_629_729)));
+}
+
+#[test]
+fn test_async_day23_network() {
+    let nic = IntCodeCpu::from_file("./input/day23.txt").unwrap();
+    let mut executor = Executor::new();
+    let (senders, receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) = (0..50).map(|_| channel()).unzip();
+    let (nat, mut nat_packets) = channel();
+    for (address, input) in receivers.into_iter().enumerate() {
+        senders[address].send(address as i64);
+        let mut packets = executor.spawn_cpu(nic.clone(), input, Some(-1));
+        let senders = senders.clone();
+        let nat = nat.clone();
+        executor.spawn(async move {
+            while let (Some(dst), Some(x), Some(y)) = (packets.next().await, packets.next().await, packets.next().await) {
+                if dst == 255 {
+                    nat.send((x, y));
+                } else {
+                    senders[dst as usize].send(x);
+                    senders[dst as usize].send(y);
+                }
+            }
+        });
+    }
+    let first = executor.block_on(async move { nat_packets.next().await });
+    assert_eq!(first.flatten().map(|(_, y)| y), Some(26464));
+}
+
+#[test]
+fn test_async_waiting_for_input_gets_stuck() {
+    let mut executor = Executor::new();
+    let (_sender, input) = channel();
+    let mut output = executor.spawn_cpu(IntCodeCpu::from_code("3,0,4,0,99"), input, None);
+    assert_eq!(executor.block_on(async move { output.next().await }), None);
+    // both the CPU and the future passed to block_on are still waiting
+    assert_eq!(executor.run(), 2);
+}
@@ -0,0 +1,127 @@
+use rayon::prelude::*;
+use super::IntCodeCpu;
+
+// Input and memory patches for one run of a batch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Job {
+    pub input: Vec<i64>,
+    pub patches: Vec<(usize, i64)>,
+}
+
+impl Job {
+    pub fn new() -> Job {
+        Job::default()
+    }
+
+    pub fn input(mut self, values: &[i64]) -> Job {
+        self.input.extend_from_slice(values);
+        self
+    }
+
+    // ASCII text followed by a newline
+    pub fn line(mut self, text: &str) -> Job {
+        self.input.extend(text.bytes().map(i64::from));
+        self.input.push(i64::from(b'\n'));
+        self
+    }
+
+    pub fn patch(mut self, addr: usize, value: i64) -> Job {
+        self.patches.push((addr, value));
+        self
+    }
+}
+
+impl From<Vec<i64>> for Job {
+    fn from(input: Vec<i64>) -> Self {
+        Job { input, patches: vec![] }
+    }
+}
+
+// Runs copies of a base CPU for many jobs in parallel. Every copy runs until it halts, waits
+// for more input or exhausts the step budget, then the result function extracts whatever the
+// caller needs from it, e.g. the output or a memory cell.
+pub struct Batch<'a> {
+    base: &'a IntCodeCpu,
+    step_budget: Option<u64>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new(base: &'a IntCodeCpu) -> Batch<'a> {
+        Batch { base, step_budget: None }
+    }
+
+    pub fn with_step_budget(mut self, steps: u64) -> Batch<'a> {
+        self.step_budget = Some(steps);
+        self
+    }
+
+    fn execute(&self, job: &Job) -> IntCodeCpu {
+        let mut cpu = self.base.clone();
+        for (addr, value) in &job.patches {
+            cpu.write_memory(*addr, *value);
+        }
+        cpu.input.extend(&job.input);
+        let deadline = self.step_budget.map(|budget| cpu.steps + budget);
+        while cpu.running && !cpu.waiting_for_input() && deadline.is_none_or(|deadline| cpu.steps < deadline) {
+            cpu.step();
+        }
+        cpu
+    }
+
+    // Results in the same order as the jobs.
+    pub fn run<I, F, T>(&self, jobs: I, result: F) -> Vec<T>
+        where I: IntoIterator, I::Item: Into<Job>, F: Fn(&mut IntCodeCpu) -> T + Sync, T: Send {
+        let jobs = jobs.into_iter().map(Into::into).collect::<Vec<Job>>();
+        jobs.par_iter().map(|job| result(&mut self.execute(job))).collect()
+    }
+
+    // Index and result of the first job in order for which `result` returns something.
+    pub fn find_first<I, F, T>(&self, jobs: I, result: F) -> Option<(usize, T)>
+        where I: IntoIterator, I::Item: Into<Job>, F: Fn(&mut IntCodeCpu) -> Option<T> + Sync, T: Send {
+        let jobs = jobs.into_iter().map(Into::into).collect::<Vec<Job>>();
+        jobs.par_iter()
+            .enumerate()
+            .find_map_first(|(index, job)| result(&mut self.execute(job)).map(|result| (index, result)))
+    }
+
+    // Like `find_first`, but jobs are streamed from the iterator instead of being collected
+    // first and whichever match is found first wins, so this works for huge search spaces.
+    pub fn find_any<I, F, T>(&self, jobs: I, result: F) -> Option<(usize, T)>
+        where I: IntoIterator, I::IntoIter: Send, I::Item: Into<Job> + Send,
+              F: Fn(&mut IntCodeCpu) -> Option<T> + Sync, T: Send {
+        jobs.into_iter()
+            .enumerate()
+            .par_bridge()
+            .find_map_any(|(index, job)| result(&mut self.execute(&job.into())).map(|result| (index, result)))
+    }
+}
+
+#[test]
+fn test_batch_run_in_order() {
+    // outputs the product of two inputs
+    let cpu = IntCodeCpu::from_code("3,11,3,12,2,11,12,13,4,13,99");
+    let jobs = (0..100).map(|i| vec![i, i + 1]);
+    let products = Batch::new(&cpu).run(jobs, |cpu| cpu.output.pop_front());
+    assert_eq!(products, (0..100).map(|i| Some(i * (i + 1))).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_batch_patches_and_budget() {
+    let cpu = IntCodeCpu::from_code("1,0,0,0,99");
+    let jobs = vec![Job::new(), Job::new().patch(1, 4), Job::new().patch(4, 1105).patch(5, 1).patch(6, 4)];
+    let results = Batch::new(&cpu).with_step_budget(1000).run(jobs, |cpu| (cpu.memory[0], cpu.running, cpu.steps()));
+    assert_eq!(results, vec![(2, false, 2), (100, false, 2), (2, true, 1000)]);
+}
+
+#[test]
+fn test_batch_find() {
+    // outputs 1 if the input is 1234
+    let cpu = IntCodeCpu::from_code("3,9,1008,9,1234,10,4,10,99");
+    let is_match = |cpu: &mut IntCodeCpu| if cpu.output.pop_front() == Some(1) { Some(cpu.steps()) } else { None };
+    let batch = Batch::new(&cpu);
+    assert_eq!(batch.find_first((0..2000).map(|i| vec![i]), is_match), Some((1234, 4)));
+    assert_eq!(batch.find_any((0..2000).map(|i| vec![i]), is_match), Some((1234, 4)));
+    assert_eq!(batch.find_first((0..10).map(|i| vec![i]), is_match), None);
+    // waiting for input counts as done
+    assert_eq!(batch.run(vec![Job::new()], |cpu| cpu.waiting_for_input()), vec![true]);
+}
@@ -0,0 +1,451 @@
+use std::collections::{BTreeMap, BTreeSet};
+use super::ParameterMode;
+use super::disasm::{decode, DecodedInstruction};
+
+// How control leaves a basic block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Terminator {
+    // the next instruction starts another block
+    Fallthrough(usize),
+    Jump(usize),
+    // `taken` is the jump target
+    Branch { taken: usize, not_taken: usize },
+    // conditional jump to a computed address
+    IndirectBranch { not_taken: usize },
+    Return,
+    // unconditional jump to an address read from memory that isn't a return
+    IndirectJump,
+    Halt,
+    // execution runs into a word that doesn't decode
+    Invalid(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub instructions: Vec<DecodedInstruction>,
+    pub terminator: Terminator,
+}
+
+impl BasicBlock {
+    pub fn successors(&self) -> Vec<usize> {
+        match self.terminator {
+            Terminator::Fallthrough(next) | Terminator::Jump(next) => vec![next],
+            Terminator::Branch { taken, not_taken } if taken == not_taken => vec![taken],
+            Terminator::Branch { taken, not_taken } => vec![taken, not_taken],
+            Terminator::IndirectBranch { not_taken } => vec![not_taken],
+            Terminator::Return | Terminator::IndirectJump | Terminator::Halt | Terminator::Invalid(_) => vec![],
+        }
+    }
+}
+
+// A call is an unconditional jump preceded by a store of the address after the jump into the
+// slot that becomes [rbp+0] for the callee, optionally followed by adjusting rbp to that slot.
+// The arguments are stored to the slots after it. Both the usual puzzle input convention and
+// the code from our compiler look like this. Calls don't end basic blocks, execution
+// continues at the return address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallSite {
+    // address of the jump
+    pub address: usize,
+    // `None` for calls through a pointer
+    pub target: Option<usize>,
+    pub return_address: usize,
+    // address of the instruction storing the return address
+    pub return_store: usize,
+    // addresses of the instructions storing the arguments, in argument order
+    pub args: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub entry: usize,
+    pub blocks: BTreeSet<usize>,
+    // rbp at the start of every block relative to its value on entry, `None` if it differs
+    // between paths or is adjusted by a computed amount
+    pub frame_offsets: BTreeMap<usize, Option<i64>>,
+    // largest number of arguments passed by any direct call
+    pub params: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    pub calls: BTreeMap<usize, CallSite>,
+    pub functions: BTreeMap<usize, Function>,
+}
+
+fn is_jump(inst: &DecodedInstruction) -> bool {
+    inst.opcode == 5 || inst.opcode == 6
+}
+
+// value of an add or mul with two immediate operands
+pub(super) fn constant_result(inst: &DecodedInstruction) -> Option<i64> {
+    let (a, b) = match &inst.operands[..] {
+        [a, b, _] if a.mode == ParameterMode::Immediate && b.mode == ParameterMode::Immediate => (a.value, b.value),
+        _ => return None,
+    };
+    match inst.opcode {
+        1 => Some(a.wrapping_add(b)),
+        2 => Some(a.wrapping_mul(b)),
+        _ => None,
+    }
+}
+
+// Some(true) if a jump is always taken, Some(false) if never
+fn constant_condition(inst: &DecodedInstruction) -> Option<bool> {
+    let cond = inst.operands[0];
+    if cond.mode != ParameterMode::Immediate {
+        return None;
+    }
+    Some((cond.value != 0) == (inst.opcode == 5))
+}
+
+// `run` holds the straight-line instructions executed before `jump`
+fn detect_call(run: &[DecodedInstruction], jump: &DecodedInstruction) -> Option<CallSite> {
+    let return_address = jump.address + jump.size();
+    let mut adjust = 0;
+    let mut store = None;
+    for (i, inst) in run.iter().enumerate().rev() {
+        if is_jump(inst) {
+            return None;
+        }
+        if inst.opcode == 9 {
+            if inst.operands[0].mode != ParameterMode::Immediate {
+                return None;
+            }
+            adjust += inst.operands[0].value;
+            continue;
+        }
+        let dst = inst.info.dst.map(|dst| inst.operands[dst]);
+        if dst.is_some_and(|dst| dst.mode == ParameterMode::Relative && dst.value == adjust)
+            && constant_result(inst) == Some(return_address as i64) {
+            store = Some(i);
+            break;
+        }
+    }
+    let store = store?;
+    // the last store to every slot above the return address, up to the previous call
+    let mut arg_stores = BTreeMap::new();
+    for inst in run[..store].iter().rev() {
+        if is_jump(inst) || inst.opcode == 9 {
+            break;
+        }
+        if let Some(dst) = inst.info.dst.map(|dst| inst.operands[dst]) {
+            if dst.mode == ParameterMode::Relative && dst.value > adjust {
+                arg_stores.entry(dst.value - adjust).or_insert(inst.address);
+            }
+        }
+    }
+    let args = (1..).map_while(|n| arg_stores.get(&n).copied()).collect();
+    let target = jump.operands[1];
+    Some(CallSite {
+        address: jump.address,
+        target: if target.mode == ParameterMode::Immediate && target.value >= 0 { Some(target.value as usize) } else { None },
+        return_address,
+        return_store: run[store].address,
+        args,
+    })
+}
+
+impl ControlFlowGraph {
+    // Recursive traversal from `entry`, functions are found through calls. Constant arguments
+    // pointing at an `arb` instruction are taken as function pointers.
+    pub fn build(memory: &[i64], entry: usize) -> ControlFlowGraph {
+        let mut instructions: BTreeMap<usize, DecodedInstruction> = BTreeMap::new();
+        let mut exits: BTreeMap<usize, Terminator> = BTreeMap::new();
+        let mut invalid = BTreeSet::new();
+        let mut leaders = BTreeSet::new();
+        let mut roots = BTreeSet::new();
+        let mut calls = BTreeMap::new();
+        let mut pending = vec![entry];
+        roots.insert(entry);
+        leaders.insert(entry);
+        while let Some(mut addr) = pending.pop() {
+            let mut run: Vec<DecodedInstruction> = vec![];
+            while !instructions.contains_key(&addr) && !invalid.contains(&addr) {
+                let inst = match decode(memory, addr) {
+                    Some(inst) => inst,
+                    None => {
+                        invalid.insert(addr);
+                        break;
+                    }
+                };
+                instructions.insert(addr, inst.clone());
+                let next = addr + inst.size();
+                if inst.opcode == 99 {
+                    exits.insert(addr, Terminator::Halt);
+                    break;
+                }
+                if !is_jump(&inst) || constant_condition(&inst) == Some(false) {
+                    run.push(inst);
+                    addr = next;
+                    continue;
+                }
+                let target = inst.operands[1];
+                let direct = if target.mode == ParameterMode::Immediate && target.value >= 0 {
+                    Some(target.value as usize)
+                } else {
+                    None
+                };
+                if constant_condition(&inst) == Some(true) {
+                    if let Some(call) = detect_call(&run, &inst) {
+                        pending.extend(call.target);
+                        roots.extend(call.target);
+                        for arg in &call.args {
+                            let pointer = constant_result(&instructions[arg])
+                                .filter(|value| *value >= 0 && memory.get(*value as usize) == Some(&109));
+                            if let Some(pointer) = pointer {
+                                pending.push(pointer as usize);
+                                roots.insert(pointer as usize);
+                            }
+                        }
+                        calls.insert(addr, call);
+                        run.push(inst);
+                        addr = next;
+                        continue;
+                    }
+                    exits.insert(addr, match (direct, target.mode) {
+                        (Some(target), _) => Terminator::Jump(target),
+                        (None, ParameterMode::Relative) => Terminator::Return,
+                        (None, _) => Terminator::IndirectJump,
+                    });
+                    leaders.extend(direct);
+                    pending.extend(direct);
+                    break;
+                }
+                exits.insert(addr, match direct {
+                    Some(taken) => Terminator::Branch { taken, not_taken: next },
+                    None => Terminator::IndirectBranch { not_taken: next },
+                });
+                leaders.extend(direct);
+                pending.extend(direct);
+                leaders.insert(next);
+                pending.push(next);
+                break;
+            }
+        }
+        leaders.extend(roots.iter().copied());
+
+        let mut blocks = BTreeMap::new();
+        for start in leaders.iter().copied().filter(|leader| instructions.contains_key(leader) || invalid.contains(leader)) {
+            let mut block = vec![];
+            let mut addr = start;
+            let terminator = loop {
+                let inst = match instructions.get(&addr) {
+                    Some(inst) => inst,
+                    None => break Terminator::Invalid(addr),
+                };
+                block.push(inst.clone());
+                if let Some(exit) = exits.get(&addr) {
+                    break *exit;
+                }
+                addr += inst.size();
+                if leaders.contains(&addr) {
+                    break Terminator::Fallthrough(addr);
+                }
+            };
+            blocks.insert(start, BasicBlock { start, instructions: block, terminator });
+        }
+
+        let mut cfg = ControlFlowGraph { blocks, calls, functions: BTreeMap::new() };
+        for root in roots {
+            let function = cfg.function_at(root);
+            cfg.functions.insert(root, function);
+        }
+        cfg
+    }
+
+    fn function_at(&self, entry: usize) -> Function {
+        let mut frame_offsets: BTreeMap<usize, Option<i64>> = BTreeMap::new();
+        frame_offsets.insert(entry, Some(0));
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            let block = &self.blocks[&start];
+            let mut offset = frame_offsets[&start];
+            for inst in block.instructions.iter().filter(|inst| inst.opcode == 9) {
+                offset = match inst.operands[0].mode {
+                    ParameterMode::Immediate => offset.map(|offset| offset + inst.operands[0].value),
+                    _ => None,
+                };
+            }
+            for successor in block.successors() {
+                match frame_offsets.get(&successor) {
+                    None => {
+                        frame_offsets.insert(successor, offset);
+                        pending.push(successor);
+                    }
+                    Some(Some(known)) if Some(*known) != offset => {
+                        frame_offsets.insert(successor, None);
+                        pending.push(successor);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let params = self.calls.values()
+            .filter(|call| call.target == Some(entry))
+            .map(|call| call.args.len())
+            .max()
+            .unwrap_or(0);
+        Function { entry, blocks: frame_offsets.keys().copied().collect(), frame_offsets, params }
+    }
+}
+
+impl Function {
+    pub fn successors(&self, cfg: &ControlFlowGraph, block: usize) -> Vec<usize> {
+        cfg.blocks[&block].successors()
+    }
+
+    // Immediate dominator of every block except the entry.
+    pub fn dominators(&self, cfg: &ControlFlowGraph) -> BTreeMap<usize, usize> {
+        immediate_dominators(self.entry, |block| self.successors(cfg, block))
+    }
+
+    // Immediate post-dominator of every block that can leave the function, `None` if that's
+    // the exit itself, i.e. paths only meet after returning.
+    pub fn post_dominators(&self, cfg: &ControlFlowGraph) -> BTreeMap<usize, Option<usize>> {
+        const EXIT: usize = usize::MAX;
+        let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for block in &self.blocks {
+            let successors = self.successors(cfg, *block);
+            if successors.is_empty() {
+                predecessors.entry(EXIT).or_default().push(*block);
+            }
+            for successor in successors {
+                predecessors.entry(successor).or_default().push(*block);
+            }
+        }
+        immediate_dominators(EXIT, |block| predecessors.get(&block).cloned().unwrap_or_default())
+            .into_iter()
+            .map(|(block, dominator)| (block, if dominator == EXIT { None } else { Some(dominator) }))
+            .collect()
+    }
+}
+
+// Cooper, Harvey and Kennedy's iterative algorithm, only nodes reachable from `entry` get
+// a dominator.
+fn immediate_dominators<F: Fn(usize) -> Vec<usize>>(entry: usize, successors: F) -> BTreeMap<usize, usize> {
+    // postorder numbering by an iterative DFS
+    let mut order = BTreeMap::new();
+    let mut postorder = vec![];
+    let mut visited = BTreeSet::new();
+    let mut stack = vec![(entry, successors(entry), 0)];
+    visited.insert(entry);
+    while let Some((node, next, i)) = stack.last_mut() {
+        if let Some(successor) = next.get(*i).copied() {
+            *i += 1;
+            if visited.insert(successor) {
+                let next = successors(successor);
+                stack.push((successor, next, 0));
+            }
+        } else {
+            order.insert(*node, postorder.len());
+            postorder.push(*node);
+            stack.pop();
+        }
+    }
+    let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for node in &postorder {
+        for successor in successors(*node) {
+            predecessors.entry(successor).or_default().push(*node);
+        }
+    }
+    let mut dominators = BTreeMap::new();
+    dominators.insert(entry, entry);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in postorder.iter().rev().filter(|node| **node != entry) {
+            let mut new = None;
+            for predecessor in predecessors.get(node).into_iter().flatten() {
+                if !dominators.contains_key(predecessor) {
+                    continue;
+                }
+                new = Some(match new {
+                    None => *predecessor,
+                    Some(mut a) => {
+                        let mut b = *predecessor;
+                        while a != b {
+                            while order[&a] < order[&b] {
+                                a = dominators[&a];
+                            }
+                            while order[&b] < order[&a] {
+                                b = dominators[&b];
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if let Some(new) = new {
+                if dominators.insert(*node, new) != Some(new) {
+                    changed = true;
+                }
+            }
+        }
+    }
+    dominators.remove(&entry);
+    dominators
+}
+
+#[test]
+fn test_cfg_of_compiled_program() {
+    let program = super::compiler::compile("
+        fn twice(x) { return x + x; }
+        fn main() {
+            var i = input();
+            while (i > 0) {
+                if (i == 3) { output(twice(i)); } else { output(i); }
+                i = i - 1;
+            }
+        }").unwrap();
+    let cfg = ControlFlowGraph::build(&program, 0);
+    // the startup code, main and twice
+    assert_eq!(cfg.functions.len(), 3);
+    let twice = cfg.calls.values().find(|call| call.args.len() == 1).unwrap().target.unwrap();
+    assert_eq!(cfg.functions[&twice].params, 1);
+    assert_eq!(cfg.functions[&twice].blocks.len(), 1);
+    assert_eq!(cfg.blocks[&twice].terminator, Terminator::Return);
+    let main = cfg.calls[&6].target.unwrap();
+    let function = &cfg.functions[&main];
+    assert!(function.frame_offsets.values().all(|offset| *offset == Some(0)));
+    // the loop condition dominates everything in the loop and post-dominates the entry
+    let header = function.blocks.iter().copied()
+        .find(|block| matches!(cfg.blocks[block].terminator, Terminator::Branch { .. }))
+        .unwrap();
+    let dominators = function.dominators(&cfg);
+    assert!(function.blocks.iter().filter(|block| **block > header).all(|block| {
+        let mut dominator = dominators[block];
+        while dominator != header && dominator != main {
+            dominator = dominators[&dominator];
+        }
+        dominator == header
+    }));
+    assert_eq!(function.post_dominators(&cfg)[&main], Some(header));
+}
+
+#[test]
+fn test_cfg_calling_conventions() {
+    // puzzle input style: the caller stores arguments and the return address at [rbp+1] and
+    // [rbp+0], the callee allocates its frame with arb
+    let program = vec![
+        109, 100,           // arb 100
+        21101, 5, 0, 1,     // add 5, 0, [rbp+1]
+        21101, 13, 0, 0,    // add 13, 0, [rbp+0]
+        1105, 1, 16,        // jnz 1, 16
+        4, 101,             // out [101]
+        99,
+        109, 2,             // 16: arb 2
+        22101, 1, -1, -1,   // add 1, [rbp-1], [rbp-1]
+        109, -2,            // arb -2
+        2106, 0, 0,         // jz 0, [rbp+0]
+    ];
+    let cfg = ControlFlowGraph::build(&program, 0);
+    assert_eq!(cfg.calls[&10], CallSite { address: 10, target: Some(16), return_address: 13, return_store: 6, args: vec![2] });
+    assert_eq!(cfg.blocks[&0].terminator, Terminator::Halt);
+    assert_eq!(cfg.blocks[&0].instructions.len(), 6);
+    assert_eq!(cfg.functions[&16].params, 1);
+    assert_eq!(cfg.functions[&16].frame_offsets[&16], Some(0));
+    assert_eq!(cfg.blocks[&16].terminator, Terminator::Return);
+}
@@ -0,0 +1,870 @@
+// Compiler for a tiny structured language targeting Intcode:
+//
+//     fn fib(n) {
+//         if (n < 2) { return n; }
+//         return fib(n - 1) + fib(n - 2);
+//     }
+//
+//     fn main() {
+//         var i = input();
+//         while (i > 0) {
+//             output(fib(i));
+//             i = i - 1;
+//         }
+//     }
+//
+// All values are integers. Operators are + - * < > <= >= == != && || ! and unary -, both
+// operands of && and || are always evaluated. `input()` reads a value, `output(x)` writes one.
+// Comments start with // and run until the end of the line.
+//
+// Every function gets a frame on a stack above the program that is addressed relative to rbp:
+// [rbp+0] holds the return address, followed by the parameters, the locals and temporaries.
+// The caller builds the callee's frame directly above its own live temporaries, moves rbp to
+// it and jumps. The callee returns its value in [rbp+1].
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+fn error<T>(line: usize, message: String) -> Result<T, CompileError> {
+    Err(CompileError { line, message })
+}
+
+pub fn compile(source: &str) -> Result<Vec<i64>, CompileError> {
+    let tokens = tokenize(source)?;
+    let functions = Parser { tokens, pos: 0 }.parse_program()?;
+    CodeGen::new().generate(&functions)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Fn,
+    Var,
+    If,
+    Else,
+    While,
+    Return,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semicolon,
+    Assign,
+    Op(BinOp),
+    Not,
+    Eof,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinOp {
+    // higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Eq | BinOp::Ne => 3,
+            BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => 4,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Mul => 6,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, CompileError> {
+    let mut tokens = vec![];
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_no = line_idx + 1;
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            if c.is_whitespace() {
+                i += 1;
+                continue;
+            }
+            if c == '/' && next == Some('/') {
+                break;
+            }
+            if c.is_ascii_digit() {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                match text.parse() {
+                    Ok(value) => tokens.push((Token::Num(value), line_no)),
+                    Err(_) => return error(line_no, format!("number {} is too large", text)),
+                }
+                continue;
+            }
+            if c.is_alphabetic() || c == '_' {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let token = match text.as_str() {
+                    "fn" => Token::Fn,
+                    "var" => Token::Var,
+                    "if" => Token::If,
+                    "else" => Token::Else,
+                    "while" => Token::While,
+                    "return" => Token::Return,
+                    _ => Token::Ident(text),
+                };
+                tokens.push((token, line_no));
+                continue;
+            }
+            let (token, len) = match (c, next) {
+                ('<', Some('=')) => (Token::Op(BinOp::Le), 2),
+                ('>', Some('=')) => (Token::Op(BinOp::Ge), 2),
+                ('=', Some('=')) => (Token::Op(BinOp::Eq), 2),
+                ('!', Some('=')) => (Token::Op(BinOp::Ne), 2),
+                ('&', Some('&')) => (Token::Op(BinOp::And), 2),
+                ('|', Some('|')) => (Token::Op(BinOp::Or), 2),
+                ('<', _) => (Token::Op(BinOp::Lt), 1),
+                ('>', _) => (Token::Op(BinOp::Gt), 1),
+                ('+', _) => (Token::Op(BinOp::Add), 1),
+                ('-', _) => (Token::Op(BinOp::Sub), 1),
+                ('*', _) => (Token::Op(BinOp::Mul), 1),
+                ('=', _) => (Token::Assign, 1),
+                ('!', _) => (Token::Not, 1),
+                ('(', _) => (Token::LParen, 1),
+                (')', _) => (Token::RParen, 1),
+                ('{', _) => (Token::LBrace, 1),
+                ('}', _) => (Token::RBrace, 1),
+                (',', _) => (Token::Comma, 1),
+                (';', _) => (Token::Semicolon, 1),
+                _ => return error(line_no, format!("unexpected character '{}'", c)),
+            };
+            tokens.push((token, line_no));
+            i += len;
+        }
+    }
+    let last_line = source.lines().count().max(1);
+    tokens.push((Token::Eof, last_line));
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Num(i64),
+    Var { name: String, line: usize },
+    Call { name: String, args: Vec<Expr>, line: usize },
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug)]
+enum Stmt {
+    Var { name: String, value: Expr, line: usize },
+    Assign { name: String, value: Expr, line: usize },
+    If { cond: Expr, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    While { cond: Expr, body: Vec<Stmt> },
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Clone, Debug)]
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    line: usize,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), CompileError> {
+        if *self.peek() == expected {
+            self.next();
+            Ok(())
+        } else {
+            error(self.line(), format!("expected {}, found {}", what, describe(self.peek())))
+        }
+    }
+
+    fn ident(&mut self, what: &str) -> Result<String, CompileError> {
+        match self.peek().clone() {
+            Token::Ident(name) => {
+                self.next();
+                Ok(name)
+            }
+            other => error(self.line(), format!("expected {}, found {}", what, describe(&other))),
+        }
+    }
+
+    fn parse_program(&mut self) -> Result<Vec<Function>, CompileError> {
+        let mut functions = vec![];
+        while *self.peek() != Token::Eof {
+            functions.push(self.parse_function()?);
+        }
+        Ok(functions)
+    }
+
+    fn parse_function(&mut self) -> Result<Function, CompileError> {
+        let line = self.line();
+        self.expect(Token::Fn, "'fn'")?;
+        let name = self.ident("function name")?;
+        self.expect(Token::LParen, "'('")?;
+        let mut params = vec![];
+        if *self.peek() != Token::RParen {
+            loop {
+                params.push(self.ident("parameter name")?);
+                if *self.peek() != Token::Comma {
+                    break;
+                }
+                self.next();
+            }
+        }
+        self.expect(Token::RParen, "')'")?;
+        let body = self.parse_block()?;
+        Ok(Function { name, params, body, line })
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect(Token::LBrace, "'{'")?;
+        let mut statements = vec![];
+        while *self.peek() != Token::RBrace {
+            if *self.peek() == Token::Eof {
+                return error(self.line(), "unexpected end of input, missing '}'".to_string());
+            }
+            statements.push(self.parse_statement()?);
+        }
+        self.next();
+        Ok(statements)
+    }
+
+    fn parse_statement(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        let statement = match self.peek().clone() {
+            Token::Var => {
+                self.next();
+                let name = self.ident("variable name")?;
+                self.expect(Token::Assign, "'='")?;
+                let value = self.parse_expr(0)?;
+                Stmt::Var { name, value, line }
+            }
+            Token::If => {
+                self.next();
+                let cond = self.parse_condition()?;
+                let then = self.parse_block()?;
+                let otherwise = if *self.peek() == Token::Else {
+                    self.next();
+                    if *self.peek() == Token::If {
+                        vec![self.parse_statement()?]
+                    } else {
+                        self.parse_block()?
+                    }
+                } else {
+                    vec![]
+                };
+                return Ok(Stmt::If { cond, then, otherwise });
+            }
+            Token::While => {
+                self.next();
+                let cond = self.parse_condition()?;
+                let body = self.parse_block()?;
+                return Ok(Stmt::While { cond, body });
+            }
+            Token::Return => {
+                self.next();
+                if *self.peek() == Token::Semicolon {
+                    Stmt::Return(None)
+                } else {
+                    Stmt::Return(Some(self.parse_expr(0)?))
+                }
+            }
+            Token::Ident(name) if self.tokens[self.pos + 1].0 == Token::Assign => {
+                self.next();
+                self.next();
+                let value = self.parse_expr(0)?;
+                Stmt::Assign { name, value, line }
+            }
+            _ => Stmt::Expr(self.parse_expr(0)?),
+        };
+        self.expect(Token::Semicolon, "';'")?;
+        Ok(statement)
+    }
+
+    fn parse_condition(&mut self) -> Result<Expr, CompileError> {
+        self.expect(Token::LParen, "'('")?;
+        let cond = self.parse_expr(0)?;
+        self.expect(Token::RParen, "')'")?;
+        Ok(cond)
+    }
+
+    // precedence climbing, all binary operators are left associative
+    fn parse_expr(&mut self, min_precedence: u8) -> Result<Expr, CompileError> {
+        let mut lhs = self.parse_unary()?;
+        while let Token::Op(op) = *self.peek() {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.next();
+            let rhs = self.parse_expr(op.precedence())?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+        match self.next() {
+            Token::Op(BinOp::Sub) => Ok(Expr::Neg(Box::new(self.parse_unary()?))),
+            Token::Not => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            Token::Num(value) => Ok(Expr::Num(value)),
+            Token::LParen => {
+                let expr = self.parse_expr(0)?;
+                self.expect(Token::RParen, "')'")?;
+                Ok(expr)
+            }
+            Token::Ident(name) => {
+                if *self.peek() != Token::LParen {
+                    return Ok(Expr::Var { name, line });
+                }
+                self.next();
+                let mut args = vec![];
+                if *self.peek() != Token::RParen {
+                    loop {
+                        args.push(self.parse_expr(0)?);
+                        if *self.peek() != Token::Comma {
+                            break;
+                        }
+                        self.next();
+                    }
+                }
+                self.expect(Token::RParen, "')'")?;
+                Ok(Expr::Call { name, args, line })
+            }
+            other => error(line, format!("expected an expression, found {}", describe(&other))),
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Num(value) => format!("number {}", value),
+        Token::Ident(name) => format!("'{}'", name),
+        Token::Eof => "end of input".to_string(),
+        Token::Fn => "'fn'".to_string(),
+        Token::Var => "'var'".to_string(),
+        Token::If => "'if'".to_string(),
+        Token::Else => "'else'".to_string(),
+        Token::While => "'while'".to_string(),
+        Token::Return => "'return'".to_string(),
+        Token::LParen => "'('".to_string(),
+        Token::RParen => "')'".to_string(),
+        Token::LBrace => "'{'".to_string(),
+        Token::RBrace => "'}'".to_string(),
+        Token::Comma => "','".to_string(),
+        Token::Semicolon => "';'".to_string(),
+        Token::Assign => "'='".to_string(),
+        Token::Not => "'!'".to_string(),
+        Token::Op(op) => format!("operator {:?}", op),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Word {
+    Value(i64),
+    Label(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    Imm(i64),
+    // [rbp+slot]
+    Slot(i64),
+    Label(usize),
+}
+
+const ADD: i64 = 1;
+const MUL: i64 = 2;
+const IN: i64 = 3;
+const OUT: i64 = 4;
+const JUMP_NOT_ZERO: i64 = 5;
+const JUMP_ZERO: i64 = 6;
+const LESS_THAN: i64 = 7;
+const EQUALS: i64 = 8;
+const ADJUST_RBP: i64 = 9;
+const HALT: i64 = 99;
+
+// slot of the return address and of the return value in every frame
+const RETURN_ADDRESS: i64 = 0;
+const RETURN_VALUE: i64 = 1;
+
+struct CodeGen {
+    code: Vec<Word>,
+    labels: Vec<Option<usize>>,
+    functions: HashMap<String, (usize, usize)>,
+    // state of the function currently being generated
+    slots: HashMap<String, i64>,
+    declared: HashSet<String>,
+    temp_base: i64,
+}
+
+impl CodeGen {
+    fn new() -> CodeGen {
+        CodeGen {
+            code: vec![],
+            labels: vec![],
+            functions: HashMap::new(),
+            slots: HashMap::new(),
+            declared: HashSet::new(),
+            temp_base: 0,
+        }
+    }
+
+    fn new_label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place_label(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn emit(&mut self, opcode: i64, operands: &[Operand]) {
+        let mut inst = opcode;
+        for (i, operand) in operands.iter().enumerate() {
+            let mode = match operand {
+                Operand::Imm(_) | Operand::Label(_) => 1,
+                Operand::Slot(_) => 2,
+            };
+            inst += mode * 10_i64.pow(i as u32 + 2);
+        }
+        self.code.push(Word::Value(inst));
+        for operand in operands {
+            self.code.push(match operand {
+                Operand::Imm(value) | Operand::Slot(value) => Word::Value(*value),
+                Operand::Label(label) => Word::Label(*label),
+            });
+        }
+    }
+
+    fn generate(mut self, functions: &[Function]) -> Result<Vec<i64>, CompileError> {
+        for function in functions {
+            let label = self.new_label();
+            if self.functions.insert(function.name.clone(), (label, function.params.len())).is_some() {
+                return error(function.line, format!("function {} is already defined", function.name));
+            }
+            if function.name == "input" || function.name == "output" {
+                return error(function.line, format!("{} is a builtin function", function.name));
+            }
+        }
+        let main = match self.functions.get("main") {
+            Some((_, params)) if *params > 0 => return error(
+                functions.iter().find(|f| f.name == "main").map_or(1, |f| f.line),
+                "main must not have parameters".to_string(),
+            ),
+            Some((label, _)) => *label,
+            None => return error(1, "no main function".to_string()),
+        };
+        let stack = self.new_label();
+        let halt = self.new_label();
+        self.emit(ADJUST_RBP, &[Operand::Label(stack)]);
+        self.emit(ADD, &[Operand::Label(halt), Operand::Imm(0), Operand::Slot(RETURN_ADDRESS)]);
+        self.emit(JUMP_NOT_ZERO, &[Operand::Imm(1), Operand::Label(main)]);
+        self.place_label(halt);
+        self.emit(HALT, &[]);
+        for function in functions {
+            self.function(function)?;
+        }
+        self.place_label(stack);
+        let labels = self.labels;
+        Ok(self.code.into_iter().map(|word| match word {
+            Word::Value(value) => value,
+            Word::Label(label) => labels[label].expect("unplaced label") as i64,
+        }).collect())
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), CompileError> {
+        self.slots.clear();
+        self.declared.clear();
+        for (i, param) in function.params.iter().enumerate() {
+            if self.slots.insert(param.clone(), 1 + i as i64).is_some() {
+                return error(function.line, format!("duplicate parameter {}", param));
+            }
+            self.declared.insert(param.clone());
+        }
+        self.allocate_locals(&function.body)?;
+        self.temp_base = 1 + self.slots.len() as i64;
+        self.place_label(self.functions[&function.name].0);
+        for statement in &function.body {
+            self.statement(statement)?;
+        }
+        self.emit(ADD, &[Operand::Imm(0), Operand::Imm(0), Operand::Slot(RETURN_VALUE)]);
+        self.emit_return();
+        Ok(())
+    }
+
+    fn allocate_locals(&mut self, statements: &[Stmt]) -> Result<(), CompileError> {
+        for statement in statements {
+            match statement {
+                Stmt::Var { name, line, .. } => {
+                    let slot = 1 + self.slots.len() as i64;
+                    if self.slots.insert(name.clone(), slot).is_some() {
+                        return error(*line, format!("variable {} is already declared", name));
+                    }
+                }
+                Stmt::If { then, otherwise, .. } => {
+                    self.allocate_locals(then)?;
+                    self.allocate_locals(otherwise)?;
+                }
+                Stmt::While { body, .. } => self.allocate_locals(body)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_return(&mut self) {
+        self.emit(JUMP_ZERO, &[Operand::Imm(0), Operand::Slot(RETURN_ADDRESS)]);
+    }
+
+    fn variable(&self, name: &str, line: usize) -> Result<i64, CompileError> {
+        match self.slots.get(name) {
+            Some(slot) if self.declared.contains(name) => Ok(*slot),
+            _ => error(line, format!("undefined variable {}", name)),
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), CompileError> {
+        match statement {
+            Stmt::Var { name, value, .. } => {
+                let slot = self.slots[name];
+                self.expr(value, slot, self.temp_base)?;
+                self.declared.insert(name.clone());
+            }
+            Stmt::Assign { name, value, line } => {
+                let slot = self.variable(name, *line)?;
+                self.expr(value, slot, self.temp_base)?;
+            }
+            Stmt::If { cond, then, otherwise } => {
+                let else_label = self.new_label();
+                let end_label = self.new_label();
+                let (cond, _) = self.operand(cond, self.temp_base)?;
+                self.emit(JUMP_ZERO, &[cond, Operand::Label(else_label)]);
+                for statement in then {
+                    self.statement(statement)?;
+                }
+                self.emit(JUMP_NOT_ZERO, &[Operand::Imm(1), Operand::Label(end_label)]);
+                self.place_label(else_label);
+                for statement in otherwise {
+                    self.statement(statement)?;
+                }
+                self.place_label(end_label);
+            }
+            Stmt::While { cond, body } => {
+                let start_label = self.new_label();
+                let end_label = self.new_label();
+                self.place_label(start_label);
+                let (cond, _) = self.operand(cond, self.temp_base)?;
+                self.emit(JUMP_ZERO, &[cond, Operand::Label(end_label)]);
+                for statement in body {
+                    self.statement(statement)?;
+                }
+                self.emit(JUMP_NOT_ZERO, &[Operand::Imm(1), Operand::Label(start_label)]);
+                self.place_label(end_label);
+            }
+            Stmt::Return(value) => {
+                match value {
+                    Some(value) => self.expr(value, RETURN_VALUE, self.temp_base)?,
+                    None => self.emit(ADD, &[Operand::Imm(0), Operand::Imm(0), Operand::Slot(RETURN_VALUE)]),
+                }
+                self.emit_return();
+            }
+            Stmt::Expr(Expr::Call { name, args, line }) if name == "output" => {
+                if args.len() != 1 {
+                    return error(*line, format!("output expects 1 argument, got {}", args.len()));
+                }
+                let (value, _) = self.operand(&args[0], self.temp_base)?;
+                self.emit(OUT, &[value]);
+            }
+            Stmt::Expr(expr) => self.expr(expr, self.temp_base, self.temp_base + 1)?,
+        }
+        Ok(())
+    }
+
+    // Returns an operand for the value of `expr`, constants and variables are used directly,
+    // everything else is computed into the temporary slot `depth`. Also returns the first
+    // slot that is still free.
+    fn operand(&mut self, expr: &Expr, depth: i64) -> Result<(Operand, i64), CompileError> {
+        match expr {
+            Expr::Num(value) => Ok((Operand::Imm(*value), depth)),
+            Expr::Var { name, line } => Ok((Operand::Slot(self.variable(name, *line)?), depth)),
+            _ => {
+                self.expr(expr, depth, depth + 1)?;
+                Ok((Operand::Slot(depth), depth + 1))
+            }
+        }
+    }
+
+    // Computes `expr` into [rbp+target], slots from `depth` upwards may be used as temporaries.
+    fn expr(&mut self, expr: &Expr, target: i64, depth: i64) -> Result<(), CompileError> {
+        let target_operand = Operand::Slot(target);
+        match expr {
+            Expr::Num(_) | Expr::Var { .. } => {
+                let (value, _) = self.operand(expr, depth)?;
+                self.emit(ADD, &[value, Operand::Imm(0), target_operand]);
+            }
+            Expr::Neg(inner) => {
+                let (value, _) = self.operand(inner, depth)?;
+                self.emit(MUL, &[value, Operand::Imm(-1), target_operand]);
+            }
+            Expr::Not(inner) => {
+                let (value, _) = self.operand(inner, depth)?;
+                self.emit(EQUALS, &[value, Operand::Imm(0), target_operand]);
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, depth) = self.operand(lhs, depth)?;
+                let (b, depth) = self.operand(rhs, depth)?;
+                let (t1, t2) = (Operand::Slot(depth), Operand::Slot(depth + 1));
+                match op {
+                    BinOp::Add => self.emit(ADD, &[a, b, target_operand]),
+                    BinOp::Mul => self.emit(MUL, &[a, b, target_operand]),
+                    BinOp::Sub => match b {
+                        Operand::Imm(value) => self.emit(ADD, &[a, Operand::Imm(-value), target_operand]),
+                        _ => {
+                            self.emit(MUL, &[b, Operand::Imm(-1), t1]);
+                            self.emit(ADD, &[a, t1, target_operand]);
+                        }
+                    },
+                    BinOp::Lt => self.emit(LESS_THAN, &[a, b, target_operand]),
+                    BinOp::Gt => self.emit(LESS_THAN, &[b, a, target_operand]),
+                    BinOp::Eq => self.emit(EQUALS, &[a, b, target_operand]),
+                    BinOp::Ne | BinOp::Le | BinOp::Ge => {
+                        match op {
+                            BinOp::Ne => self.emit(EQUALS, &[a, b, t1]),
+                            BinOp::Le => self.emit(LESS_THAN, &[b, a, t1]),
+                            _ => self.emit(LESS_THAN, &[a, b, t1]),
+                        }
+                        self.emit(EQUALS, &[t1, Operand::Imm(0), target_operand]);
+                    }
+                    BinOp::And | BinOp::Or => {
+                        // count or multiply the "is zero" flags of both operands
+                        self.emit(EQUALS, &[a, Operand::Imm(0), t1]);
+                        self.emit(EQUALS, &[b, Operand::Imm(0), t2]);
+                        self.emit(if *op == BinOp::And { ADD } else { MUL }, &[t1, t2, t1]);
+                        self.emit(EQUALS, &[t1, Operand::Imm(0), target_operand]);
+                    }
+                }
+            }
+            Expr::Call { name, args, line } => match name.as_str() {
+                "input" if args.is_empty() => self.emit(IN, &[target_operand]),
+                "input" => return error(*line, format!("input expects no arguments, got {}", args.len())),
+                "output" => return error(*line, "output() has no value".to_string()),
+                _ => {
+                    let (label, params) = match self.functions.get(name) {
+                        Some(function) => *function,
+                        None => return error(*line, format!("undefined function {}", name)),
+                    };
+                    if params != args.len() {
+                        return error(*line, format!("{} expects {} arguments, got {}", name, params, args.len()));
+                    }
+                    // the callee's frame starts at the first free slot
+                    let base = depth;
+                    let args_end = base + 1 + args.len() as i64;
+                    for (i, arg) in args.iter().enumerate() {
+                        self.expr(arg, base + 1 + i as i64, args_end)?;
+                    }
+                    let return_label = self.new_label();
+                    self.emit(ADD, &[Operand::Label(return_label), Operand::Imm(0), Operand::Slot(base + RETURN_ADDRESS)]);
+                    self.emit(ADJUST_RBP, &[Operand::Imm(base)]);
+                    self.emit(JUMP_NOT_ZERO, &[Operand::Imm(1), Operand::Label(label)]);
+                    self.place_label(return_label);
+                    self.emit(ADJUST_RBP, &[Operand::Imm(-base)]);
+                    self.emit(ADD, &[Operand::Slot(base + RETURN_VALUE), Operand::Imm(0), target_operand]);
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn run(source: &str, input: &[i64]) -> Vec<i64> {
+    let mut cpu = super::IntCodeCpu::from_program(compile(source).unwrap());
+    cpu.input.extend(input);
+    cpu.run();
+    cpu.output.into_iter().collect()
+}
+
+#[test]
+fn test_compile_arithmetic() {
+    let source = "
+        fn main() {
+            var a = input();
+            var b = input();
+            output(a + b);
+            output(a - b);
+            output(a * b);
+            output(-a + 2 * (b - 1));
+            output(1 - 2 - 3);
+            output(2 + 3 * 4);
+        }";
+    assert_eq!(run(source, &[7, 3]), vec![10, 4, 21, -3, -4, 14]);
+}
+
+#[test]
+fn test_compile_comparisons() {
+    let source = "
+        fn main() {
+            var a = input();
+            var b = input();
+            output(a < b);
+            output(a > b);
+            output(a <= b);
+            output(a >= b);
+            output(a == b);
+            output(a != b);
+            output(a < b && b < 10);
+            output(a > b || b < 10);
+            output(!a);
+        }";
+    assert_eq!(run(source, &[3, 5]), vec![1, 0, 1, 0, 0, 1, 1, 1, 0]);
+    assert_eq!(run(source, &[5, 5]), vec![0, 0, 1, 1, 1, 0, 0, 1, 0]);
+    assert_eq!(run(source, &[0, 12]), vec![1, 0, 1, 0, 0, 1, 0, 0, 1]);
+}
+
+#[test]
+fn test_compile_control_flow() {
+    let source = "
+        // prints the collatz sequence of the input, then its length
+        fn main() {
+            var n = input();
+            var steps = 0;
+            while (n != 1) {
+                output(n);
+                if (is_even(n)) {
+                    n = half(n);
+                } else {
+                    n = 3 * n + 1;
+                }
+                steps = steps + 1;
+            }
+            output(steps);
+        }
+
+        fn is_even(n) {
+            while (n > 1) {
+                n = n - 2;
+            }
+            return n == 0;
+        }
+
+        fn half(n) {
+            var result = 0;
+            while (n > 0) {
+                n = n - 2;
+                result = result + 1;
+            }
+            return result;
+        }";
+    assert_eq!(run(source, &[6]), vec![6, 3, 10, 5, 16, 8, 4, 2, 8]);
+}
+
+#[test]
+fn test_compile_recursion() {
+    let source = "
+        fn fib(n) {
+            if (n < 2) {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+
+        fn ackermann(m, n) {
+            if (m == 0) {
+                return n + 1;
+            } else if (n == 0) {
+                return ackermann(m - 1, 1);
+            }
+            return ackermann(m - 1, ackermann(m, n - 1));
+        }
+
+        fn main() {
+            var i = 0;
+            while (i < 10) {
+                output(fib(i));
+                i = i + 1;
+            }
+            output(ackermann(2, 3));
+            noop();
+        }
+
+        fn noop() {
+            return;
+        }";
+    assert_eq!(run(source, &[]), vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34, 9]);
+}
+
+#[test]
+fn test_compile_errors() {
+    fn error_of(source: &str) -> String {
+        compile(source).unwrap_err().to_string()
+    }
+    assert_eq!(error_of("fn main() {\n  x = 1;\n}"), "line 2: undefined variable x");
+    assert_eq!(error_of("fn main() {\n  var x = 1\n}"), "line 3: expected ';', found '}'");
+    assert_eq!(error_of("fn main() {\n\n  f(1);\n}\nfn f() {}"), "line 3: f expects 0 arguments, got 1");
+    assert_eq!(error_of("fn main() {\n  g();\n}"), "line 2: undefined function g");
+    assert_eq!(error_of("fn f() {}"), "line 1: no main function");
+    assert_eq!(error_of("fn main() {\n  var x = 1 $ 2;\n}"), "line 2: unexpected character '$'");
+    assert_eq!(error_of("fn main() {\n  var x = 1;\n  var x = 2;\n}"), "line 3: variable x is already declared");
+    assert_eq!(error_of("fn main() {\n  output(x);\n  var x = 2;\n}"), "line 2: undefined variable x");
+    assert_eq!(error_of("fn main() {\n  var x = (1 + ;\n}"), "line 2: expected an expression, found ';'");
+    assert_eq!(error_of("fn main() {"), "line 1: unexpected end of input, missing '}'");
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use super::IntCodeCpu;
+use super::disasm::disassemble_annotated;
+use super::hooks::Hook;
+use super::symbols::SymbolMap;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BranchCount {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+// Execution counts per instruction address and the directions taken by conditional jumps.
+// Coverage from several runs of the same program can be merged into one report. Collected by
+// a hook, so it needs the `hooks` feature like the other tools observing execution.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub hits: BTreeMap<usize, u64>,
+    pub branches: BTreeMap<usize, BranchCount>,
+    // whether the instruction being executed is a conditional jump
+    jump: bool,
+}
+
+impl Hook for Coverage {
+    fn before_decode(&mut self, _ip: usize, inst: i64) {
+        self.jump = inst % 100 == 5 || inst % 100 == 6;
+    }
+
+    // a jump that continues right after itself counts as not taken, even if its target
+    // happens to be the next instruction
+    fn after_execute(&mut self, ip: usize, next_ip: usize) {
+        *self.hits.entry(ip).or_insert(0) += 1;
+        if !self.jump {
+            return;
+        }
+        let branch = self.branches.entry(ip).or_default();
+        if next_ip == ip + 3 {
+            branch.not_taken += 1;
+        } else {
+            branch.taken += 1;
+        }
+    }
+}
+
+impl Coverage {
+    pub fn merge(&mut self, other: &Coverage) {
+        for (addr, hits) in &other.hits {
+            *self.hits.entry(*addr).or_insert(0) += hits;
+        }
+        for (addr, branch) in &other.branches {
+            let entry = self.branches.entry(*addr).or_default();
+            entry.taken += branch.taken;
+            entry.not_taken += branch.not_taken;
+        }
+    }
+
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.hits.get(&addr).copied().unwrap_or(0)
+    }
+
+    // Annotated disassembly of `range` with the hit count in front of every instruction,
+    // '-' for instructions that never ran, and the branch directions after conditional
+    // jumps. Ends with a summary of covered instructions and branch directions.
+    pub fn report(&self, memory: &[i64], range: Range<usize>, symbols: Option<&SymbolMap>) -> String {
+        let mut instructions = 0;
+        let mut covered_instructions = 0;
+        let mut directions = 0;
+        let mut covered_directions = 0;
+        let mut result = disassemble_annotated(memory, range, symbols, |addr, inst| {
+            let hits = self.hits.get(&addr).map_or_else(|| "-".to_string(), u64::to_string);
+            let inst = match inst {
+                Some(inst) => inst,
+                None => return (format!("{:>8}  ", ""), String::new()),
+            };
+            instructions += 1;
+            if self.hits.contains_key(&addr) {
+                covered_instructions += 1;
+            }
+            let mut suffix = String::new();
+            if inst.is_jump() {
+                let branch = self.branches.get(&addr).copied().unwrap_or_default();
+                directions += 2;
+                covered_directions += (branch.taken > 0) as usize + (branch.not_taken > 0) as usize;
+                suffix = format!("  (taken {}, not taken {})", branch.taken, branch.not_taken);
+            }
+            (format!("{:>8}  ", hits), suffix)
+        });
+        result.push_str(&format!("instructions: {}/{} executed, branch directions: {}/{} taken\n",
+                                 covered_instructions, instructions, covered_directions, directions));
+        result
+    }
+}
+
+impl IntCodeCpu {
+    // starts over if coverage is already collected
+    pub fn start_coverage(&mut self) {
+        self.stop_coverage();
+        self.add_hook(Coverage::default());
+    }
+
+    pub fn stop_coverage(&mut self) -> Option<Coverage> {
+        let id = self.find_hook::<Coverage>()?;
+        self.remove_hook(id)
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.hook(self.find_hook::<Coverage>()?)
+    }
+}
+
+// prints abs(input)
+#[cfg(test)]
+const ABS_PROGRAM: &str = "3,20,1007,20,0,21,1006,21,13,1002,20,-1,20,4,20,99";
+
+#[test]
+fn test_coverage_counts() {
+    // counts down from 3
+    let mut cpu = IntCodeCpu::from_code("1101,0,3,20,1001,20,-1,20,1005,20,4,4,20,99");
+    cpu.start_coverage();
+    cpu.run();
+    let coverage = cpu.stop_coverage().unwrap();
+    assert_eq!(coverage.hits(0), 1);
+    assert_eq!(coverage.hits(4), 3);
+    assert_eq!(coverage.hits(8), 3);
+    assert_eq!(coverage.hits(11), 1);
+    assert_eq!(coverage.hits(5), 0);
+    assert_eq!(coverage.branches[&8], BranchCount { taken: 2, not_taken: 1 });
+    assert!(cpu.coverage().is_none());
+}
+
+#[test]
+fn test_coverage_report_merges_runs() {
+    let run = |input| {
+        let mut cpu = IntCodeCpu::from_code(ABS_PROGRAM);
+        cpu.input.push_back(input);
+        cpu.start_coverage();
+        cpu.run();
+        cpu.stop_coverage().unwrap()
+    };
+    let program = IntCodeCpu::from_code(ABS_PROGRAM).memory;
+    let mut coverage = run(5);
+    assert_eq!(coverage.report(&program, 0..program.len(), None),
+               "       1       0  in [20]
+       1       2  lt [20], 0, [21]
+       1       6  jz [21], 13  (taken 1, not taken 0)
+       -       9  mul [20], -1, [20]
+       1      13  out [20]
+       1      15  halt
+instructions: 5/6 executed, branch directions: 1/2 taken
+");
+    coverage.merge(&run(-5));
+    assert_eq!(coverage.hits(0), 2);
+    assert_eq!(coverage.hits(9), 1);
+    assert_eq!(coverage.branches[&6], BranchCount { taken: 1, not_taken: 1 });
+}
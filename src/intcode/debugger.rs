@@ -0,0 +1,209 @@
+use std::io::{self, BufRead, Write};
+use super::IntCodeCpu;
+use super::symbols::SymbolMap;
+use super::watch::{Expr, StopReason, WatchId};
+
+const PROMPT: &str = "(intcode) ";
+
+const HELP: &str = "\
+step [n]          execute n instructions (default 1), stops at watchpoints
+continue          run until the program halts, waits for input or a watchpoint fires
+watch <spec>      add a watchpoint: read|write|access <address> [if <expr>] or if <expr>
+delete <id>       remove a watchpoint
+watches           list watchpoints
+print <expr>      evaluate an expression like mem[rbp+2] or ip == 1034
+input <values>    queue comma or space separated input values
+line <text>       queue a line of ASCII input
+output            print and clear pending output
+quit              leave the debugger
+";
+
+// Line based debugger for a terminal or a script: every command is answered with a few lines
+// of text, stops print the reason and the next instruction.
+pub struct Debugger {
+    cpu: IntCodeCpu,
+    symbols: Option<SymbolMap>,
+}
+
+enum Reply {
+    Text(String),
+    Quit,
+}
+
+impl Debugger {
+    pub fn new(cpu: IntCodeCpu) -> Debugger {
+        Debugger { cpu, symbols: None }
+    }
+
+    pub fn with_symbols(mut self, symbols: SymbolMap) -> Debugger {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    pub fn cpu(&self) -> &IntCodeCpu {
+        &self.cpu
+    }
+
+    pub fn into_cpu(self) -> IntCodeCpu {
+        self.cpu
+    }
+
+    pub fn interactive(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        self.run(stdin.lock(), io::stdout())
+    }
+
+    // Serves commands until `quit` or the end of the input.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "{}", self.location())?;
+        let mut lines = input.lines();
+        loop {
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+            match self.command(line.trim()) {
+                Reply::Text(text) => write!(output, "{}", text)?,
+                Reply::Quit => return Ok(()),
+            }
+        }
+    }
+
+    fn location(&self) -> String {
+        if self.cpu.running {
+            self.cpu.trace(self.symbols.as_ref())
+        } else {
+            "program halted".to_string()
+        }
+    }
+
+    fn stopped(&self, reason: StopReason) -> String {
+        if self.cpu.running {
+            format!("{}\n{}\n", reason, self.location())
+        } else {
+            format!("{}\n", reason)
+        }
+    }
+
+    fn command(&mut self, line: &str) -> Reply {
+        let mut parts = line.splitn(2, char::is_whitespace);
+        let command = parts.next().unwrap_or("");
+        let args = parts.next().unwrap_or("").trim();
+        let text = match command {
+            "" => String::new(),
+            "s" | "step" => self.step(args),
+            "c" | "continue" => {
+                let reason = self.cpu.run_until_stop();
+                self.stopped(reason)
+            }
+            "w" | "watch" => match args.parse() {
+                Ok(watchpoint) => format!("watchpoint {}: {}\n", self.cpu.add_watchpoint(watchpoint), args),
+                Err(e) => format!("bad watchpoint: {}\n", e),
+            },
+            "d" | "delete" => match args.parse() {
+                Ok(id) if self.cpu.remove_watchpoint(WatchId(id)) => format!("deleted watchpoint {}\n", id),
+                _ => format!("no watchpoint \"{}\"\n", args),
+            },
+            "watches" => self.cpu.watchpoints().iter()
+                .map(|(id, watchpoint)| format!("{:>3}  {}\n", id, watchpoint))
+                .collect(),
+            "p" | "print" => match args.parse::<Expr>() {
+                Ok(expr) => format!("{}\n", expr.eval(&self.cpu, None)),
+                Err(e) => format!("bad expression: {}\n", e),
+            },
+            "input" => {
+                let values = args.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|value| !value.is_empty())
+                    .map(str::parse::<i64>)
+                    .collect::<Result<Vec<i64>, _>>();
+                match values {
+                    Ok(values) => {
+                        self.cpu.input.extend(&values);
+                        format!("queued {} values\n", values.len())
+                    }
+                    Err(e) => format!("bad input: {}\n", e),
+                }
+            }
+            "line" => {
+                self.cpu.send_line(args);
+                format!("queued {} values\n", args.len() + 1)
+            }
+            "output" => {
+                let values = self.cpu.output.drain(..).map(|value| value.to_string()).collect::<Vec<String>>();
+                format!("{}\n", values.join(", "))
+            }
+            "h" | "help" => HELP.to_string(),
+            "q" | "quit" => return Reply::Quit,
+            _ => format!("unknown command \"{}\", try help\n", command),
+        };
+        Reply::Text(text)
+    }
+
+    fn step(&mut self, args: &str) -> String {
+        let count = match args {
+            "" => 1,
+            _ => match args.parse::<usize>() {
+                Ok(count) => count,
+                Err(_) => return format!("bad step count \"{}\"\n", args),
+            },
+        };
+        for _ in 0..count {
+            if let Some(reason) = self.cpu.step_watched() {
+                return self.stopped(reason);
+            }
+        }
+        format!("{}\n", self.location())
+    }
+}
+
+#[test]
+fn test_debugger_session() {
+    // counts [20] down from the input and outputs it
+    let cpu = IntCodeCpu::from_code("3,20,1001,20,-1,20,1005,20,2,4,20,99");
+    let mut debugger = Debugger::new(cpu);
+    let script = "\
+watch write 20 if value == 1
+continue
+input 3
+step 2
+c
+print mem[20] + 10 * ip
+watches
+delete 0
+delete 0
+watch if ip == 9
+watch read
+c
+c
+output
+bogus
+quit
+step
+";
+    let mut transcript = vec![];
+    debugger.run(script.as_bytes(), &mut transcript).unwrap();
+    let transcript = String::from_utf8(transcript).unwrap();
+    assert_eq!(transcript, "     0 rbp=0  in [20]
+(intcode) watchpoint 0: write 20 if value == 1
+(intcode) program is waiting for input
+     0 rbp=0  in [20]
+(intcode) queued 1 values
+(intcode)      6 rbp=0  jnz [20]=2, 2
+(intcode) watchpoint 0: write [20] 2 -> 1 at ip 2
+     6 rbp=0  jnz [20]=1, 2
+(intcode) 61
+(intcode)   0  write 20 if value == 1
+(intcode) deleted watchpoint 0
+(intcode) no watchpoint \"0\"
+(intcode) watchpoint 1: if ip == 9
+(intcode) bad watchpoint: column 6: bad address \"\"
+(intcode) watchpoint 1
+     9 rbp=0  out [20]=0
+(intcode) program halted
+(intcode) 0
+(intcode) unknown command \"bogus\", try help
+(intcode) ");
+    assert!(!debugger.cpu().running);
+}
@@ -0,0 +1,1071 @@
+use std::collections::{BTreeMap, BTreeSet};
+use super::ParameterMode;
+use super::cfg::{CallSite, ControlFlowGraph, Function, Terminator};
+use super::disasm::DecodedInstruction;
+use super::symbols::SymbolMap;
+
+// Turns the functions found by the CFG into C-like pseudo-code. Frame slots are named
+// relative to rbp on function entry: [0] is the return address, the parameters follow as
+// arg1, arg2, ... and everything above is a local. Values only used once in the same block
+// are folded into the expression that uses them and dead stores to slots are dropped, so the
+// temporaries of compiled code disappear. Loops and if/else are recovered from dominators,
+// whatever doesn't fit is left as goto.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Var {
+    // frame slot relative to rbp on function entry
+    Slot(i64),
+    Global(usize),
+    Function(usize),
+    // rbp itself, for frames that can't be tracked
+    Rbp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinOp {
+    Mul,
+    Add,
+    Sub,
+    Lt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Mul => 6,
+            BinOp::Add | BinOp::Sub => 5,
+            BinOp::Lt | BinOp::Ge => 4,
+            BinOp::Eq | BinOp::Ne => 3,
+            BinOp::And => 2,
+            BinOp::Or => 1,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Mul => "*",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Lt => "<",
+            BinOp::Ge => ">=",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Num(i64),
+    Var(Var),
+    // memory at a computed address
+    Mem(Box<Expr>),
+    Input,
+    // the callee is a function or a pointer to one
+    Call(Box<Expr>, Vec<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn binary(op: BinOp, a: Expr, b: Expr) -> Expr {
+        match (op, &a, &b) {
+            (BinOp::Add, Expr::Num(x), Expr::Num(y)) => Expr::Num(x.wrapping_add(*y)),
+            (BinOp::Mul, Expr::Num(x), Expr::Num(y)) => Expr::Num(x.wrapping_mul(*y)),
+            (BinOp::Lt, Expr::Num(x), Expr::Num(y)) => Expr::Num((x < y) as i64),
+            (BinOp::Eq, Expr::Num(x), Expr::Num(y)) => Expr::Num((x == y) as i64),
+            (BinOp::Add, _, Expr::Num(0)) | (BinOp::Mul, _, Expr::Num(1)) => a,
+            (BinOp::Add, Expr::Num(0), _) | (BinOp::Mul, Expr::Num(1), _) => b,
+            (BinOp::Add, _, Expr::Num(y)) if *y < 0 && *y != i64::MIN => Expr::Binary(BinOp::Sub, Box::new(a), Box::new(Expr::Num(-y))),
+            (BinOp::Add, _, Expr::Neg(y)) => Expr::Binary(BinOp::Sub, Box::new(a), y.clone()),
+            (BinOp::Add, Expr::Neg(x), _) => Expr::Binary(BinOp::Sub, Box::new(b), x.clone()),
+            (BinOp::Mul, _, Expr::Num(-1)) => Expr::Neg(Box::new(a)),
+            (BinOp::Mul, Expr::Num(-1), _) => Expr::Neg(Box::new(b)),
+            (BinOp::Eq, _, Expr::Num(0)) => a.not(),
+            (BinOp::Eq, Expr::Num(0), _) => b.not(),
+            _ => Expr::Binary(op, Box::new(a), Box::new(b)),
+        }
+    }
+
+    // logical not, keeps the value 0 or 1
+    fn not(self) -> Expr {
+        match self {
+            Expr::Binary(BinOp::Lt, a, b) => Expr::Binary(BinOp::Ge, a, b),
+            Expr::Binary(BinOp::Ge, a, b) => Expr::Binary(BinOp::Lt, a, b),
+            Expr::Binary(BinOp::Eq, a, b) => Expr::Binary(BinOp::Ne, a, b),
+            Expr::Binary(BinOp::Ne, a, b) => Expr::Binary(BinOp::Eq, a, b),
+            // how the compiler builds && and || from "is zero" flags
+            Expr::Binary(op @ (BinOp::Add | BinOp::Mul), a, b) => match (a.negated(), b.negated()) {
+                (Some(a), Some(b)) => Expr::Binary(if op == BinOp::Add { BinOp::And } else { BinOp::Or }, Box::new(a), Box::new(b)),
+                _ => Expr::Not(Box::new(Expr::Binary(op, a, b))),
+            },
+            expr => Expr::Not(Box::new(expr)),
+        }
+    }
+
+    // `x` if this is the logical not of `x`
+    fn negated(&self) -> Option<Expr> {
+        match self {
+            Expr::Not(expr) => Some((**expr).clone()),
+            Expr::Binary(BinOp::Ge, a, b) => Some(Expr::Binary(BinOp::Lt, a.clone(), b.clone())),
+            Expr::Binary(BinOp::Ne, a, b) => Some(Expr::Binary(BinOp::Eq, a.clone(), b.clone())),
+            _ => None,
+        }
+    }
+
+    // negation where only zero or non-zero matters
+    fn negate_condition(self) -> Expr {
+        match self {
+            Expr::Not(expr) => *expr,
+            expr => expr.not(),
+        }
+    }
+
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Num(_) | Expr::Var(_) | Expr::Input => vec![],
+            Expr::Mem(expr) | Expr::Neg(expr) | Expr::Not(expr) => vec![expr],
+            Expr::Call(callee, args) => std::iter::once(&**callee).chain(args).collect(),
+            Expr::Binary(_, a, b) => vec![a, b],
+        }
+    }
+
+    fn any<F: Fn(&Expr) -> bool + Copy>(&self, f: F) -> bool {
+        f(self) || self.children().into_iter().any(|child| child.any(f))
+    }
+
+    fn slots(&self, slots: &mut BTreeSet<i64>) {
+        if let Expr::Var(Var::Slot(slot)) = self {
+            slots.insert(*slot);
+        }
+        self.children().into_iter().for_each(|child| child.slots(slots));
+    }
+
+    fn reads_slot(&self, slot: i64) -> bool {
+        self.any(|expr| *expr == Expr::Var(Var::Slot(slot)))
+    }
+
+    fn reads_memory(&self) -> bool {
+        self.any(|expr| matches!(expr, Expr::Mem(_) | Expr::Var(Var::Global(_))))
+    }
+
+    fn has_side_effects(&self) -> bool {
+        self.any(|expr| matches!(expr, Expr::Input | Expr::Call(..)))
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, _, _) => op.precedence(),
+            Expr::Neg(_) | Expr::Not(_) => 7,
+            Expr::Num(value) if *value < 0 => 7,
+            _ => 8,
+        }
+    }
+
+    fn render(&self, names: &Names) -> String {
+        match self {
+            Expr::Num(value) => value.to_string(),
+            Expr::Var(var) => names.var(*var),
+            Expr::Mem(addr) => format!("mem[{}]", addr.render(names)),
+            Expr::Input => "input()".to_string(),
+            Expr::Call(callee, args) => {
+                let args = args.iter().map(|arg| arg.render(names)).collect::<Vec<String>>();
+                let callee = match **callee {
+                    Expr::Var(Var::Function(_)) => callee.render(names),
+                    _ => format!("(*{})", callee.render(names)),
+                };
+                format!("{}({})", callee, args.join(", "))
+            }
+            Expr::Neg(expr) => format!("-{}", expr.wrap(7, names)),
+            Expr::Not(expr) => format!("!{}", expr.wrap(7, names)),
+            Expr::Binary(op, a, b) => format!("{} {} {}", a.wrap(op.precedence(), names), op.symbol(), b.wrap(op.precedence() + 1, names)),
+        }
+    }
+
+    fn wrap(&self, precedence: u8, names: &Names) -> String {
+        if self.precedence() < precedence {
+            format!("({})", self.render(names))
+        } else {
+            self.render(names)
+        }
+    }
+}
+
+struct Names<'a> {
+    params: usize,
+    symbols: Option<&'a SymbolMap>,
+    functions: &'a BTreeMap<usize, String>,
+}
+
+impl Names<'_> {
+    fn var(&self, var: Var) -> String {
+        match var {
+            Var::Slot(0) => "return_address".to_string(),
+            Var::Slot(slot) if slot > 0 && slot as usize <= self.params => format!("arg{}", slot),
+            Var::Slot(slot) if slot > 0 => format!("local{}", slot),
+            Var::Slot(slot) => format!("frame[{}]", slot),
+            Var::Global(addr) => match self.symbols.and_then(|symbols| symbols.lookup(addr)) {
+                Some((symbol, 0)) => symbol.name.clone(),
+                Some((symbol, offset)) => format!("{}[{}]", symbol.name, offset),
+                None => format!("g_{}", addr),
+            },
+            Var::Function(addr) => self.functions.get(&addr).cloned().unwrap_or_else(|| format!("fn_{}", addr)),
+            Var::Rbp => "rbp".to_string(),
+        }
+    }
+}
+
+enum Target {
+    Slot(i64),
+    Memory(Expr),
+}
+
+// Statements of a basic block and the expressions its terminator needs.
+#[derive(Default)]
+struct BlockCode {
+    lines: Vec<String>,
+    // jumps to the `taken` successor if non-zero
+    cond: Option<Expr>,
+    jump_target: Option<Expr>,
+    return_value: Option<Expr>,
+}
+
+struct Decompiler<'a> {
+    cfg: &'a ControlFlowGraph,
+    symbols: Option<&'a SymbolMap>,
+    function_names: BTreeMap<usize, String>,
+    // functions whose result is used by some caller
+    returns_value: BTreeSet<usize>,
+    // return address stores, they are part of the call
+    hidden: BTreeSet<usize>,
+    // functions only known from being passed as pointer
+    pointers: BTreeSet<usize>,
+    // operands that some instruction writes to
+    modified_operands: BTreeSet<usize>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(cfg: &'a ControlFlowGraph, symbols: Option<&'a SymbolMap>, entry: usize) -> Decompiler<'a> {
+        let function_names = cfg.functions.keys()
+            .map(|addr| {
+                let name = match symbols.and_then(|symbols| symbols.get(*addr)) {
+                    Some(symbol) => symbol.name.clone(),
+                    None if *addr == entry => "main".to_string(),
+                    None => format!("fn_{}", addr),
+                };
+                (*addr, name)
+            })
+            .collect();
+        let instructions = cfg.blocks.values().flat_map(|block| &block.instructions);
+        let written = instructions.clone()
+            .filter_map(|inst| inst.info.dst.map(|dst| inst.operands[dst]))
+            .filter(|dst| dst.mode == ParameterMode::Position && dst.value >= 0)
+            .map(|dst| dst.value as usize)
+            .collect::<BTreeSet<usize>>();
+        let modified_operands = instructions
+            .flat_map(|inst| inst.address + 1..inst.address + inst.size())
+            .filter(|cell| written.contains(cell))
+            .collect();
+        let called = cfg.calls.values().filter_map(|call| call.target).collect::<BTreeSet<usize>>();
+        let mut decompiler = Decompiler {
+            cfg,
+            symbols,
+            function_names,
+            returns_value: BTreeSet::new(),
+            hidden: cfg.calls.values().map(|call| call.return_store).collect(),
+            pointers: cfg.functions.keys().copied().filter(|addr| *addr != entry && !called.contains(addr)).collect(),
+            modified_operands,
+        };
+        // a function returns a value if a caller reads the result slot after the call, which
+        // depends on which functions return values, so iterate until nothing changes
+        loop {
+            let mut returns_value = decompiler.returns_value.clone();
+            for function in cfg.functions.values() {
+                let writer = FunctionWriter::new(&decompiler, function);
+                for block in &function.blocks {
+                    let live = writer.live_after(*block);
+                    for (i, inst) in cfg.blocks[block].instructions.iter().enumerate() {
+                        let call = match cfg.calls.get(&inst.address) {
+                            Some(call) => call,
+                            None => continue,
+                        };
+                        let offset = writer.offsets(*block)[i];
+                        if let (Some(target), Some(offset)) = (call.target, offset) {
+                            if live[i].contains(&(offset + 1)) {
+                                returns_value.insert(target);
+                            }
+                        }
+                    }
+                }
+            }
+            if returns_value == decompiler.returns_value {
+                break;
+            }
+            decompiler.returns_value = returns_value;
+        }
+        decompiler
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Loop {
+    header: usize,
+    follow: Option<usize>,
+}
+
+struct FunctionWriter<'a, 'd> {
+    decompiler: &'d Decompiler<'a>,
+    cfg: &'a ControlFlowGraph,
+    function: &'a Function,
+    names: Names<'d>,
+    live_out: BTreeMap<usize, BTreeSet<i64>>,
+    // state while writing a block
+    offset: Option<i64>,
+    pending: Vec<(i64, Expr)>,
+    patches: BTreeMap<usize, Expr>,
+    lines: Vec<String>,
+    used_slots: BTreeSet<i64>,
+    // state while structuring
+    code: BTreeMap<usize, BlockCode>,
+    loops: BTreeMap<usize, BTreeSet<usize>>,
+    post_dominators: BTreeMap<usize, Option<usize>>,
+    emitted: BTreeSet<usize>,
+    labels: BTreeSet<usize>,
+    block_lines: BTreeMap<usize, usize>,
+    output: Vec<(usize, String)>,
+}
+
+impl<'a, 'd> FunctionWriter<'a, 'd> {
+    fn new(decompiler: &'d Decompiler<'a>, function: &'a Function) -> FunctionWriter<'a, 'd> {
+        let mut writer = FunctionWriter {
+            decompiler,
+            cfg: decompiler.cfg,
+            function,
+            names: Names { params: function.params, symbols: decompiler.symbols, functions: &decompiler.function_names },
+            live_out: BTreeMap::new(),
+            offset: None,
+            pending: vec![],
+            patches: BTreeMap::new(),
+            lines: vec![],
+            used_slots: BTreeSet::new(),
+            code: BTreeMap::new(),
+            loops: BTreeMap::new(),
+            post_dominators: BTreeMap::new(),
+            emitted: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            block_lines: BTreeMap::new(),
+            output: vec![],
+        };
+        writer.compute_liveness();
+        // slots read before being written are parameters too, e.g. for functions only called
+        // through pointers
+        let read_first = writer.live_in(function.entry).into_iter().filter(|slot| *slot > 0).max();
+        writer.names.params = function.params.max(read_first.map_or(0, |slot| slot as usize));
+        writer
+    }
+
+    // frame offset in front of every instruction of a block
+    fn offsets(&self, block: usize) -> Vec<Option<i64>> {
+        let mut offset = self.function.frame_offsets[&block];
+        self.cfg.blocks[&block].instructions.iter()
+            .map(|inst| {
+                let before = offset;
+                if inst.opcode == 9 {
+                    offset = match inst.operands[0].mode {
+                        ParameterMode::Immediate => offset.map(|offset| offset + inst.operands[0].value),
+                        _ => None,
+                    };
+                }
+                before
+            })
+            .collect()
+    }
+
+    // arguments can also be stored in front of the block with the call, so direct calls
+    // pass as many as the callee takes at any call site
+    fn arg_count(&self, call: &CallSite) -> usize {
+        match call.target.and_then(|target| self.cfg.functions.get(&target)) {
+            Some(callee) => callee.params.max(call.args.len()),
+            None => call.args.len(),
+        }
+    }
+
+    fn is_return(&self, inst: &DecodedInstruction) -> bool {
+        (inst.opcode == 5 || inst.opcode == 6)
+            && inst.operands[0].mode == ParameterMode::Immediate
+            && (inst.operands[0].value != 0) == (inst.opcode == 5)
+            && inst.operands[1].mode == ParameterMode::Relative
+            && !self.cfg.calls.contains_key(&inst.address)
+    }
+
+    // slots read and written by an instruction, must match what `write_block` does
+    fn effects(&self, inst: &DecodedInstruction, offset: Option<i64>) -> (Vec<i64>, Option<i64>) {
+        let offset = match offset {
+            Some(offset) if !self.decompiler.hidden.contains(&inst.address) => offset,
+            _ => return (vec![], None),
+        };
+        let relative = |n: usize| Some(inst.operands[n])
+            .filter(|operand| operand.mode == ParameterMode::Relative)
+            .map(|operand| offset + operand.value);
+        match inst.opcode {
+            5 | 6 => {
+                if let Some(call) = self.cfg.calls.get(&inst.address) {
+                    let mut uses = (1..=self.arg_count(call) as i64).map(|n| offset + n).collect::<Vec<i64>>();
+                    if call.target.is_none() {
+                        uses.extend(relative(1));
+                    }
+                    (uses, Some(offset + 1))
+                } else if self.is_return(inst) {
+                    (if self.decompiler.returns_value.contains(&self.function.entry) { vec![1] } else { vec![] }, None)
+                } else {
+                    (relative(0).into_iter().chain(relative(1)).collect(), None)
+                }
+            }
+            9 | 99 => (vec![], None),
+            _ => {
+                let dst = inst.info.dst;
+                let uses = (0..inst.operands.len()).filter(|n| Some(*n) != dst).filter_map(relative).collect();
+                (uses, dst.and_then(relative))
+            }
+        }
+    }
+
+    fn compute_liveness(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in self.function.blocks.iter().rev() {
+                let live_out = self.cfg.blocks[block].successors().iter()
+                    .flat_map(|successor| self.live_in(*successor))
+                    .collect::<BTreeSet<i64>>();
+                if self.live_out.get(block) != Some(&live_out) {
+                    self.live_out.insert(*block, live_out);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn live_in(&self, block: usize) -> BTreeSet<i64> {
+        let mut live = self.live_out.get(&block).cloned().unwrap_or_default();
+        let offsets = self.offsets(block);
+        for (inst, offset) in self.cfg.blocks[&block].instructions.iter().zip(offsets).rev() {
+            let (uses, def) = self.effects(inst, offset);
+            if let Some(def) = def {
+                live.remove(&def);
+            }
+            live.extend(uses);
+        }
+        live
+    }
+
+    fn live_after(&self, block: usize) -> Vec<BTreeSet<i64>> {
+        let mut live = self.live_out.get(&block).cloned().unwrap_or_default();
+        let offsets = self.offsets(block);
+        let mut result = vec![];
+        for (inst, offset) in self.cfg.blocks[&block].instructions.iter().zip(offsets).rev() {
+            result.push(live.clone());
+            let (uses, def) = self.effects(inst, offset);
+            if let Some(def) = def {
+                live.remove(&def);
+            }
+            live.extend(uses);
+        }
+        result.reverse();
+        result
+    }
+
+    // true if the value written to `slot` by instruction `index` is read exactly once and
+    // only within the block
+    fn single_use(&self, block: usize, index: usize, slot: i64) -> bool {
+        let offsets = self.offsets(block);
+        let mut uses = 0;
+        for (inst, offset) in self.cfg.blocks[&block].instructions.iter().zip(offsets).skip(index + 1) {
+            let (inst_uses, def) = self.effects(inst, offset);
+            uses += inst_uses.iter().filter(|used| **used == slot).count();
+            if def == Some(slot) {
+                return uses == 1;
+            }
+        }
+        uses == 1 && !self.live_out[&block].contains(&slot)
+    }
+
+    fn note(&mut self, expr: &Expr) {
+        expr.slots(&mut self.used_slots);
+    }
+
+    fn emit(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    fn read_slot(&mut self, slot: i64) -> Expr {
+        match self.pending.iter().position(|(pending, _)| *pending == slot) {
+            Some(i) => self.pending.remove(i).1,
+            None => Expr::Var(Var::Slot(slot)),
+        }
+    }
+
+    fn rbp_plus(&self, offset: Expr) -> Expr {
+        Expr::Mem(Box::new(Expr::binary(BinOp::Add, Expr::Var(Var::Rbp), offset)))
+    }
+
+    // value of an operand cell, the code may have changed it
+    fn patch(&mut self, cell: usize) -> Option<Expr> {
+        self.patches.remove(&cell).or_else(|| {
+            Some(Expr::Var(Var::Global(cell))).filter(|_| self.decompiler.modified_operands.contains(&cell))
+        })
+    }
+
+    fn read(&mut self, inst: &DecodedInstruction, n: usize) -> Expr {
+        let operand = inst.operands[n];
+        if let Some(value) = self.patch(inst.address + 1 + n) {
+            return match operand.mode {
+                ParameterMode::Immediate => value,
+                ParameterMode::Position => Expr::Mem(Box::new(value)),
+                ParameterMode::Relative => self.rbp_plus(value),
+            };
+        }
+        match (operand.mode, self.offset) {
+            (ParameterMode::Immediate, _) => Expr::Num(operand.value),
+            (ParameterMode::Position, _) if operand.value >= 0 => Expr::Var(Var::Global(operand.value as usize)),
+            (ParameterMode::Position, _) => Expr::Mem(Box::new(Expr::Num(operand.value))),
+            (ParameterMode::Relative, Some(offset)) => self.read_slot(offset + operand.value),
+            (ParameterMode::Relative, None) => self.rbp_plus(Expr::Num(operand.value)),
+        }
+    }
+
+    fn target(&mut self, inst: &DecodedInstruction) -> Target {
+        let n = inst.info.dst.unwrap();
+        let operand = inst.operands[n];
+        if let Some(value) = self.patch(inst.address + 1 + n) {
+            return Target::Memory(match operand.mode {
+                ParameterMode::Relative => self.rbp_plus(value),
+                _ => Expr::Mem(Box::new(value)),
+            });
+        }
+        match (operand.mode, self.offset) {
+            (ParameterMode::Relative, Some(offset)) => Target::Slot(offset + operand.value),
+            (ParameterMode::Relative, None) => Target::Memory(self.rbp_plus(Expr::Num(operand.value))),
+            _ if operand.value >= 0 => Target::Memory(Expr::Var(Var::Global(operand.value as usize))),
+            _ => Target::Memory(Expr::Mem(Box::new(Expr::Num(operand.value)))),
+        }
+    }
+
+    // writes out pending values that can't be delayed any further
+    fn flush<F: Fn(i64, &Expr) -> bool>(&mut self, f: F) {
+        let (flushed, kept) = self.pending.drain(..).partition::<Vec<_>, _>(|(slot, expr)| f(*slot, expr));
+        self.pending = kept;
+        for (slot, expr) in flushed {
+            self.used_slots.insert(slot);
+            self.note(&expr);
+            let line = format!("{} = {};", self.names.var(Var::Slot(slot)), expr.render(&self.names));
+            self.emit(line);
+        }
+    }
+
+    // `next` are the instructions after the one writing `value` to `addr`. If `addr` is an
+    // operand of one of them, nothing in between changes what `value` reads and the cell isn't
+    // used as data later on, the write patches that operand and is shown as indirection instead.
+    fn patched_operand(&self, addr: usize, value: &Expr, next: &[(DecodedInstruction, Option<i64>)]) -> bool {
+        let mut slots = BTreeSet::new();
+        value.slots(&mut slots);
+        let mut patched = false;
+        for (inst, offset) in next {
+            if inst.operands.iter().any(|operand| operand.mode == ParameterMode::Position && operand.value == addr as i64) {
+                return false;
+            }
+            if patched {
+                continue;
+            }
+            if (inst.address + 1..inst.address + inst.size()).contains(&addr) {
+                patched = true;
+                continue;
+            }
+            let (_, def) = self.effects(inst, *offset);
+            let writes_memory = inst.info.dst.is_some_and(|dst| inst.operands[dst].mode != ParameterMode::Relative || offset.is_none());
+            if inst.info.dst.is_none() && inst.opcode != 4 || writes_memory || def.is_some_and(|def| slots.contains(&def)) {
+                return false;
+            }
+        }
+        patched
+    }
+
+    fn assign(&mut self, target: Target, value: Expr, live_after: &BTreeSet<i64>, block: usize, index: usize) {
+        match target {
+            Target::Slot(slot) => {
+                self.flush(|_, expr| expr.reads_slot(slot));
+                if value.has_side_effects() {
+                    self.flush(|_, expr| expr.has_side_effects());
+                }
+                if !live_after.contains(&slot) {
+                    if value.has_side_effects() {
+                        self.note(&value);
+                        let line = format!("{};", value.render(&self.names));
+                        self.emit(line);
+                    }
+                } else if self.single_use(block, index, slot) {
+                    self.pending.push((slot, value));
+                } else {
+                    self.used_slots.insert(slot);
+                    self.note(&value);
+                    let line = format!("{} = {};", self.names.var(Var::Slot(slot)), value.render(&self.names));
+                    self.emit(line);
+                }
+            }
+            Target::Memory(target) => {
+                self.flush(|_, expr| expr.reads_memory() || expr.has_side_effects());
+                if let Expr::Var(Var::Global(addr)) = target {
+                    let offsets = self.offsets(block);
+                    let next = self.cfg.blocks[&block].instructions.iter().cloned().zip(offsets).skip(index + 1).collect::<Vec<_>>();
+                    if self.patched_operand(addr, &value, &next) {
+                        self.patches.insert(addr, value);
+                        return;
+                    }
+                }
+                self.note(&target);
+                self.note(&value);
+                let line = format!("{} = {};", target.render(&self.names), value.render(&self.names));
+                self.emit(line);
+            }
+        }
+    }
+
+    fn write_block(&mut self, block: usize) -> BlockCode {
+        let instructions = &self.cfg.blocks[&block].instructions;
+        let terminator = self.cfg.blocks[&block].terminator;
+        let offsets = self.offsets(block);
+        let live = self.live_after(block);
+        let mut code = BlockCode::default();
+        for (i, inst) in instructions.iter().enumerate() {
+            if self.decompiler.hidden.contains(&inst.address) {
+                continue;
+            }
+            self.offset = offsets[i];
+            match inst.opcode {
+                1 | 2 | 7 | 8 => {
+                    let a = self.read(inst, 0);
+                    let b = self.read(inst, 1);
+                    let op = match inst.opcode {
+                        1 => BinOp::Add,
+                        2 => BinOp::Mul,
+                        7 => BinOp::Lt,
+                        _ => BinOp::Eq,
+                    };
+                    let target = self.target(inst);
+                    self.assign(target, Expr::binary(op, a, b), &live[i], block, i);
+                }
+                3 => {
+                    let target = self.target(inst);
+                    self.assign(target, Expr::Input, &live[i], block, i);
+                }
+                4 => {
+                    let value = self.read(inst, 0);
+                    self.flush(|_, expr| expr.has_side_effects());
+                    self.note(&value);
+                    let line = format!("output({});", value.render(&self.names));
+                    self.emit(line);
+                }
+                9 if self.offset.is_none() => {
+                    let value = self.read(inst, 0);
+                    let line = format!("rbp += {};", value.render(&self.names));
+                    self.emit(line);
+                }
+                5 | 6 => {
+                    if let Some(call) = self.cfg.calls.get(&inst.address) {
+                        let base = self.offset;
+                        let callee = match call.target {
+                            Some(target) => Expr::Var(Var::Function(target)),
+                            None => self.read(inst, 1),
+                        };
+                        let args = (1..=self.arg_count(call) as i64)
+                            .map(|n| match base {
+                                Some(base) => match self.read_slot(base + n) {
+                                    Expr::Num(value) if value >= 0 && self.decompiler.pointers.contains(&(value as usize)) =>
+                                        Expr::Var(Var::Function(value as usize)),
+                                    arg => arg,
+                                },
+                                None => self.rbp_plus(Expr::Num(n)),
+                            })
+                            .collect();
+                        // the callee may change memory and its own frame
+                        self.flush(|_, expr| expr.has_side_effects() || expr.reads_memory()
+                            || base.is_some_and(|base| expr.any(|expr| matches!(expr, Expr::Var(Var::Slot(slot)) if *slot >= base))));
+                        let call = Expr::Call(Box::new(callee), args);
+                        match base {
+                            Some(base) => self.assign(Target::Slot(base + 1), call, &live[i], block, i),
+                            None => {
+                                self.note(&call);
+                                let line = format!("{};", call.render(&self.names));
+                                self.emit(line);
+                            }
+                        }
+                    } else if i + 1 == instructions.len() {
+                        match terminator {
+                            Terminator::Branch { .. } | Terminator::IndirectBranch { .. } => {
+                                let cond = self.read(inst, 0);
+                                code.cond = Some(if inst.opcode == 5 { cond } else { cond.negate_condition() });
+                                if let Terminator::IndirectBranch { .. } = terminator {
+                                    code.jump_target = Some(self.read(inst, 1));
+                                }
+                            }
+                            Terminator::Return if self.decompiler.returns_value.contains(&self.function.entry) => {
+                                code.return_value = Some(match self.offset {
+                                    Some(_) => self.read_slot(1),
+                                    None => self.rbp_plus(Expr::Num(1)),
+                                });
+                            }
+                            Terminator::IndirectJump => code.jump_target = Some(self.read(inst, 1)),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.flush(|_, _| true);
+        self.patches.clear();
+        for expr in code.cond.iter().chain(&code.jump_target).chain(&code.return_value) {
+            expr.slots(&mut self.used_slots);
+        }
+        code.lines = std::mem::take(&mut self.lines);
+        code
+    }
+
+    fn line(&mut self, indent: usize, text: String) {
+        self.output.push((indent, text));
+    }
+
+    fn render(&self, expr: &Expr) -> String {
+        expr.render(&self.names)
+    }
+
+    // Writes blocks starting at `start` until reaching `stop` or leaving the region.
+    fn chain(&mut self, start: Option<usize>, stop: Option<usize>, lp: Option<Loop>, indent: usize) {
+        let mut current = start;
+        while let Some(block) = current {
+            if Some(block) == stop {
+                return;
+            }
+            if let Some(lp) = lp {
+                if block == lp.header {
+                    self.line(indent, "continue;".to_string());
+                    return;
+                }
+                if Some(block) == lp.follow {
+                    self.line(indent, "break;".to_string());
+                    return;
+                }
+            }
+            if self.emitted.contains(&block) {
+                self.labels.insert(block);
+                self.line(indent, format!("goto L_{};", block));
+                return;
+            }
+            current = if self.loops.contains_key(&block) {
+                self.write_loop(block, indent)
+            } else {
+                self.write_block_statements(block, lp, indent)
+            };
+        }
+    }
+
+    fn write_loop(&mut self, header: usize, indent: usize) -> Option<usize> {
+        let body = self.loops[&header].clone();
+        let exits = body.iter()
+            .flat_map(|block| self.cfg.blocks[block].successors())
+            .filter(|successor| !body.contains(successor))
+            .collect::<BTreeSet<usize>>();
+        let follow = match self.post_dominators.get(&header) {
+            Some(Some(follow)) if !body.contains(follow) => Some(*follow),
+            _ => exits.iter().next().copied(),
+        };
+        let lp = Loop { header, follow };
+        let start_line = self.output.len();
+        self.emitted.insert(header);
+        let code = &self.code[&header];
+        let terminator = self.cfg.blocks[&header].terminator;
+        match terminator {
+            Terminator::Branch { taken, not_taken } if code.lines.is_empty()
+                && (Some(taken) == follow) != (Some(not_taken) == follow)
+                && body.contains(if Some(taken) == follow { &not_taken } else { &taken }) => {
+                let cond = code.cond.clone().unwrap();
+                let (cond, inside) = if Some(taken) == follow { (cond.negate_condition(), not_taken) } else { (cond, taken) };
+                let line = format!("while ({}) {{", self.render(&cond));
+                self.line(indent, line);
+                self.chain(Some(inside), None, Some(lp), indent + 1);
+            }
+            _ => {
+                self.line(indent, "while (true) {".to_string());
+                let next = self.write_block_statements(header, Some(lp), indent + 1);
+                self.chain(next, None, Some(lp), indent + 1);
+            }
+        }
+        self.block_lines.insert(header, start_line);
+        if self.output.last() == Some(&(indent + 1, "continue;".to_string())) {
+            self.output.pop();
+        }
+        self.line(indent, "}".to_string());
+        follow
+    }
+
+    // Number of blocks reachable from `start` without passing `avoid`, to decide which branch
+    // of an if is the short one.
+    fn region_size(&self, start: usize, avoid: usize) -> usize {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![start];
+        while let Some(block) = pending.pop() {
+            if block != avoid && self.function.blocks.contains(&block) && seen.insert(block) {
+                pending.extend(self.cfg.blocks[&block].successors());
+            }
+        }
+        seen.len()
+    }
+
+    // Writes a block and its terminator, returns the block that follows in sequence.
+    fn write_block_statements(&mut self, block: usize, lp: Option<Loop>, indent: usize) -> Option<usize> {
+        self.emitted.insert(block);
+        self.block_lines.insert(block, self.output.len());
+        let code = &self.code[&block];
+        let lines = code.lines.clone();
+        let (cond, jump_target, return_value) = (code.cond.clone(), code.jump_target.clone(), code.return_value.clone());
+        for line in lines {
+            self.line(indent, line);
+        }
+        match self.cfg.blocks[&block].terminator {
+            Terminator::Fallthrough(next) | Terminator::Jump(next) => Some(next),
+            Terminator::Halt => {
+                self.line(indent, "halt();".to_string());
+                None
+            }
+            Terminator::Return => {
+                let line = match return_value {
+                    Some(value) => format!("return {};", self.render(&value)),
+                    None => "return;".to_string(),
+                };
+                self.line(indent, line);
+                None
+            }
+            Terminator::IndirectJump => {
+                let line = format!("goto *{};", self.render(&jump_target.unwrap()));
+                self.line(indent, line);
+                None
+            }
+            Terminator::Invalid(addr) => {
+                self.line(indent, format!("// invalid instruction at {}", addr));
+                None
+            }
+            Terminator::IndirectBranch { not_taken } => {
+                let line = format!("if ({}) goto *{};", self.render(&cond.unwrap()), self.render(&jump_target.unwrap()));
+                self.line(indent, line);
+                Some(not_taken)
+            }
+            Terminator::Branch { taken, not_taken } if taken == not_taken => Some(taken),
+            Terminator::Branch { taken, not_taken } => {
+                let cond = cond.unwrap();
+                if let Some(lp) = lp {
+                    for (target, other, cond) in [(taken, not_taken, cond.clone()), (not_taken, taken, cond.clone().negate_condition())] {
+                        let statement = if target == lp.header {
+                            "continue"
+                        } else if Some(target) == lp.follow {
+                            "break"
+                        } else {
+                            continue;
+                        };
+                        let line = format!("if ({}) {};", self.render(&cond), statement);
+                        self.line(indent, line);
+                        return Some(other);
+                    }
+                }
+                let join = self.post_dominators.get(&block).copied().flatten();
+                let (cond, first, second) = if Some(taken) == join {
+                    (cond.negate_condition(), not_taken, None)
+                } else if Some(not_taken) == join {
+                    (cond, taken, None)
+                } else if join.is_none() {
+                    // the branches never meet again, put the shorter one into the if
+                    if self.region_size(taken, block) <= self.region_size(not_taken, block) {
+                        (cond, taken, None)
+                    } else {
+                        (cond.negate_condition(), not_taken, None)
+                    }
+                } else {
+                    // code usually falls through to the then branch
+                    (cond.negate_condition(), not_taken, Some(taken))
+                };
+                let line = format!("if ({}) {{", self.render(&cond));
+                self.line(indent, line);
+                self.chain(Some(first), join, lp, indent + 1);
+                if let Some(second) = second {
+                    self.line(indent, "} else {".to_string());
+                    self.chain(Some(second), join, lp, indent + 1);
+                }
+                self.line(indent, "}".to_string());
+                match join {
+                    Some(join) => Some(join),
+                    None if first == taken => Some(not_taken),
+                    None => Some(taken),
+                }
+            }
+        }
+    }
+
+    fn write(mut self) -> String {
+        for block in &self.function.blocks {
+            let code = self.write_block(*block);
+            self.code.insert(*block, code);
+        }
+        // natural loops of all back edges
+        let dominators = self.function.dominators(self.cfg);
+        let dominates = |a: usize, mut b: usize| loop {
+            if a == b {
+                return true;
+            }
+            match dominators.get(&b) {
+                Some(dominator) => b = *dominator,
+                None => return false,
+            }
+        };
+        let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for block in &self.function.blocks {
+            for successor in self.cfg.blocks[block].successors() {
+                predecessors.entry(successor).or_default().push(*block);
+            }
+        }
+        for block in &self.function.blocks {
+            for header in self.cfg.blocks[block].successors() {
+                if !dominates(header, *block) {
+                    continue;
+                }
+                let body = self.loops.entry(header).or_default();
+                body.insert(header);
+                let mut pending = vec![*block];
+                while let Some(member) = pending.pop() {
+                    if body.insert(member) {
+                        pending.extend(predecessors[&member].iter().copied().filter(|pred| dominates(header, *pred)));
+                    }
+                }
+            }
+        }
+        self.post_dominators = self.function.post_dominators(self.cfg);
+        self.chain(Some(self.function.entry), None, None, 1);
+        // blocks the structure didn't reach, e.g. jumped into from the middle of a loop
+        while let Some(block) = self.function.blocks.iter().copied().find(|block| !self.emitted.contains(block)) {
+            self.labels.insert(block);
+            self.chain(Some(block), None, None, 1);
+        }
+        let mut output = std::mem::take(&mut self.output);
+        let mut labeled = self.labels.iter().map(|label| (self.block_lines[label], *label)).collect::<Vec<_>>();
+        labeled.sort();
+        for (line, label) in labeled.into_iter().rev() {
+            let indent = output.get(line).map_or(1, |(indent, _)| *indent);
+            output.insert(line, (indent.saturating_sub(1), format!("L_{}:", label)));
+        }
+
+        let params = (1..=self.names.params).map(|n| format!("arg{}", n)).collect::<Vec<String>>();
+        let mut result = format!("fn {}({}) {{\n", self.names.var(Var::Function(self.function.entry)), params.join(", "));
+        let locals = self.used_slots.iter()
+            .filter(|slot| **slot > self.names.params as i64)
+            .map(|slot| self.names.var(Var::Slot(*slot)))
+            .collect::<Vec<String>>();
+        if !locals.is_empty() {
+            result.push_str(&format!("    var {};\n", locals.join(", ")));
+        }
+        for (indent, line) in output {
+            result.push_str(&format!("{}{}\n", "    ".repeat(indent), line));
+        }
+        result.push_str("}\n");
+        result
+    }
+}
+
+// Decompiles everything reachable from `entry`, one function after another.
+pub fn decompile(memory: &[i64], entry: usize, symbols: Option<&SymbolMap>) -> String {
+    let cfg = ControlFlowGraph::build(memory, entry);
+    let decompiler = Decompiler::new(&cfg, symbols, entry);
+    cfg.functions.values()
+        .map(|function| FunctionWriter::new(&decompiler, function).write())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[test]
+fn test_decompile_compiled_program() {
+    let program = super::compiler::compile("
+        fn fib(n) {
+            if (n < 2) { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        fn main() {
+            var i = input();
+            while (i > 0) {
+                if (i == 3 || i == 5) { output(fib(i)); } else { output(i * 2); }
+                i = i - 1;
+            }
+        }").unwrap();
+    let symbols: SymbolMap = "10 fib code\n79 start code".parse().unwrap();
+    assert_eq!(decompile(&program, 0, Some(&symbols)), "\
+fn main() {
+    start();
+    halt();
+}
+
+fn fib(arg1) {
+    var local2;
+    if (arg1 >= 2) {
+        local2 = fib(arg1 - 1);
+        return local2 + fib(arg1 - 2);
+    }
+    return arg1;
+}
+
+fn start() {
+    var local1;
+    local1 = input();
+    while (0 < local1) {
+        if (local1 == 3 || local1 == 5) {
+            output(fib(local1));
+        } else {
+            output(local1 * 2);
+        }
+        local1 = local1 - 1;
+    }
+    return;
+}
+");
+}
+
+#[test]
+fn test_decompile_puzzle_inputs() {
+    for day in &[2, 5, 7, 9, 11, 13, 15, 17, 19, 21, 23, 25] {
+        let program = super::loader::load_program(format!("./input/day{}.txt", day)).unwrap();
+        let code = decompile(&program, 0, None);
+        for (i, _) in code.match_indices("goto L_") {
+            let label = &code[i + 5..i + code[i..].find(';').unwrap()];
+            assert!(code.contains(&format!("{}:\n", label)), "day {}: {} is missing", day, label);
+        }
+    }
+    // strings are printed through a callback that decodes every character, the self-modifying
+    // pointer reads show up as memory accesses
+    let program = super::loader::load_program("./input/day25.txt").unwrap();
+    let code = decompile(&program, 0, None);
+    assert!(code.contains("\
+fn fn_1234(arg1) {
+    fn_1174(arg1, fn_1256);
+    return;
+}
+
+fn fn_1256(arg1, arg2, arg3) {
+    output(arg3 + (arg1 + arg2));
+    return;
+}
+"));
+    assert!(code.contains("    local2 = mem[g_1128 + 2];\n    if (local2) {\n        (*local2)();\n    }\n"));
+}
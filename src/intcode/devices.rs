@@ -0,0 +1,343 @@
+use std::any::Any;
+use std::ops::Range;
+use super::IntCodeCpu;
+
+// A peripheral that can be mapped into a range of memory and/or be attached to the CPU's
+// input and output channels. Memory-mapped devices only see operand reads and writes,
+// instruction fetches always come from memory.
+pub trait Device: Any + Send + Sync + CloneDevice {
+    // `offset` is relative to the start of the mapped range
+    fn read(&mut self, _offset: usize) -> i64 {
+        0
+    }
+
+    fn write(&mut self, _offset: usize, _value: i64) {}
+
+    // what `read` would return, without its side effects, reported to hooks as the old value
+    // of a written cell
+    fn peek(&self, _offset: usize) -> i64 {
+        0
+    }
+
+    // value for an `in` instruction once the input queue is empty, `None` reads -1 as usual
+    fn input(&mut self) -> Option<i64> {
+        None
+    }
+
+    // whether `input` would return a value right now, the CPU only waits for input if not
+    fn has_input(&self) -> bool {
+        false
+    }
+
+    fn output(&mut self, _value: i64) {}
+
+    // called after every executed instruction
+    fn tick(&mut self) {}
+}
+
+pub trait CloneDevice {
+    fn clone_device(&self) -> Box<dyn Device>;
+}
+
+impl<T: Device + Clone> CloneDevice for T {
+    fn clone_device(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceId(usize);
+
+#[derive(Default)]
+pub(super) struct Devices {
+    devices: Vec<Box<dyn Device>>,
+    mappings: Vec<(Range<usize>, DeviceId)>,
+    input: Option<DeviceId>,
+    output: Option<DeviceId>,
+}
+
+impl Clone for Devices {
+    fn clone(&self) -> Self {
+        Devices {
+            devices: self.devices.iter().map(|device| device.clone_device()).collect(),
+            mappings: self.mappings.clone(),
+            input: self.input,
+            output: self.output,
+        }
+    }
+}
+
+impl Devices {
+    fn add<D: Device>(&mut self, device: D) -> DeviceId {
+        self.devices.push(Box::new(device));
+        DeviceId(self.devices.len() - 1)
+    }
+
+    fn mapping(&self, addr: usize) -> Option<(usize, DeviceId)> {
+        self.mappings.iter()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|(range, id)| (addr - range.start, *id))
+    }
+
+    pub(super) fn read(&mut self, addr: usize) -> Option<i64> {
+        let (offset, id) = self.mapping(addr)?;
+        Some(self.devices[id.0].read(offset))
+    }
+
+    #[cfg(feature = "hooks")]
+    pub(super) fn peek(&self, addr: usize) -> Option<i64> {
+        let (offset, id) = self.mapping(addr)?;
+        Some(self.devices[id.0].peek(offset))
+    }
+
+    // returns false if the address isn't mapped
+    pub(super) fn write(&mut self, addr: usize, value: i64) -> bool {
+        match self.mapping(addr) {
+            Some((offset, id)) => {
+                self.devices[id.0].write(offset, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(super) fn input(&mut self) -> Option<i64> {
+        self.input.and_then(|id| self.devices[id.0].input())
+    }
+
+    pub(super) fn has_input(&self) -> bool {
+        self.input.is_some_and(|id| self.devices[id.0].has_input())
+    }
+
+    // returns false if no output device is attached
+    pub(super) fn output(&mut self, value: i64) -> bool {
+        match self.output {
+            Some(id) => {
+                self.devices[id.0].output(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(super) fn tick(&mut self) {
+        self.devices.iter_mut().for_each(|device| device.tick());
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+}
+
+impl IntCodeCpu {
+    // Routes operand reads and writes within `range` to the device instead of memory.
+    pub fn map_device<D: Device>(&mut self, range: Range<usize>, device: D) -> DeviceId {
+        if let Some((other, _)) = self.devices.mappings.iter().find(|(other, _)| other.start < range.end && range.start < other.end) {
+            panic!("device range {:?} overlaps {:?}", range, other);
+        }
+        let id = self.devices.add(device);
+        self.devices.mappings.push((range, id));
+        id
+    }
+
+    // The device is asked for input whenever the input queue is empty.
+    pub fn attach_input<D: Device>(&mut self, device: D) -> DeviceId {
+        let id = self.devices.add(device);
+        self.devices.input = Some(id);
+        id
+    }
+
+    // Outputs go to the device instead of the output queue.
+    pub fn attach_output<D: Device>(&mut self, device: D) -> DeviceId {
+        let id = self.devices.add(device);
+        self.devices.output = Some(id);
+        id
+    }
+
+    pub fn attach_io<D: Device>(&mut self, device: D) -> DeviceId {
+        let id = self.devices.add(device);
+        self.devices.input = Some(id);
+        self.devices.output = Some(id);
+        id
+    }
+
+    pub fn device<D: Device>(&self, id: DeviceId) -> Option<&D> {
+        let device: &dyn Any = self.devices.devices.get(id.0)?.as_ref();
+        device.downcast_ref()
+    }
+
+    pub fn device_mut<D: Device>(&mut self, id: DeviceId) -> Option<&mut D> {
+        let device: &mut dyn Any = self.devices.devices.get_mut(id.0)?.as_mut();
+        device.downcast_mut()
+    }
+}
+
+// Input device that reports the current stick position, e.g. -1, 0 or 1 for left, neutral
+// and right. Also readable when mapped into memory.
+#[derive(Clone, Debug, Default)]
+pub struct Joystick {
+    pub position: i64,
+}
+
+impl Device for Joystick {
+    fn read(&mut self, _offset: usize) -> i64 {
+        self.position
+    }
+
+    fn peek(&self, _offset: usize) -> i64 {
+        self.position
+    }
+
+    fn input(&mut self) -> Option<i64> {
+        Some(self.position)
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+}
+
+// Counts executed instructions, reads return the count, writes set it.
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+    pub ticks: i64,
+}
+
+impl Device for Clock {
+    fn read(&mut self, _offset: usize) -> i64 {
+        self.ticks
+    }
+
+    fn peek(&self, _offset: usize) -> i64 {
+        self.ticks
+    }
+
+    fn write(&mut self, _offset: usize, value: i64) {
+        self.ticks = value;
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+}
+
+// Deterministic xorshift64* generator, every read or input returns a new non-negative
+// number, writing reseeds it.
+#[derive(Clone, Debug)]
+pub struct RandomSource {
+    state: u64,
+}
+
+impl RandomSource {
+    pub fn new(seed: u64) -> RandomSource {
+        // xorshift gets stuck at 0
+        RandomSource { state: seed.max(1) }
+    }
+
+    fn next(&mut self) -> i64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 1) as i64
+    }
+}
+
+impl Device for RandomSource {
+    fn read(&mut self, _offset: usize) -> i64 {
+        self.next()
+    }
+
+    fn peek(&self, _offset: usize) -> i64 {
+        self.clone().next()
+    }
+
+    fn write(&mut self, _offset: usize, value: i64) {
+        *self = RandomSource::new(value as u64);
+    }
+
+    fn input(&mut self) -> Option<i64> {
+        Some(self.next())
+    }
+
+    fn has_input(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct Recorder {
+    values: Vec<i64>,
+}
+
+#[cfg(test)]
+impl Device for Recorder {
+    fn write(&mut self, offset: usize, value: i64) {
+        self.values.push(offset as i64 * 1000 + value);
+    }
+
+    fn output(&mut self, value: i64) {
+        self.values.push(value);
+    }
+}
+
+#[test]
+fn test_memory_mapped_devices() {
+    // copies the clock to the recorder at 1001, writes to plain memory, copies the clock to
+    // the recorder's second cell and outputs it
+    let mut cpu = IntCodeCpu::from_code("1001,1000,0,1001,1101,0,0,20,1001,1000,0,1002,4,1000,99");
+    let clock = cpu.map_device(1000..1001, Clock::default());
+    let recorder = cpu.map_device(1001..1003, Recorder::default());
+    cpu.run();
+    assert_eq!(cpu.device::<Recorder>(recorder).unwrap().values, vec![0, 1002]);
+    assert_eq!(cpu.output.pop_front(), Some(3));
+    assert_eq!(cpu.device::<Clock>(clock).unwrap().ticks, 5);
+    // mapped addresses don't grow memory
+    assert_eq!(cpu.memory.len(), 21);
+    assert!(cpu.device::<Joystick>(clock).is_none());
+}
+
+#[test]
+fn test_io_devices() {
+    let mut cpu = IntCodeCpu::from_code("3,20,3,21,1,20,21,22,4,22,99");
+    cpu.input.push_back(10);
+    let joystick = cpu.attach_input(Joystick { position: -1 });
+    let recorder = cpu.attach_output(Recorder::default());
+    let mut copy = cpu.clone();
+    cpu.run();
+    assert_eq!(cpu.device::<Recorder>(recorder).unwrap().values, vec![9]);
+    assert!(cpu.output.is_empty());
+    copy.device_mut::<Joystick>(joystick).unwrap().position = 5;
+    copy.run();
+    assert_eq!(copy.device::<Recorder>(recorder).unwrap().values, vec![15]);
+}
+
+#[test]
+fn test_input_device_supplies_waiting_input() {
+    let mut cpu = IntCodeCpu::from_code("3,20,3,21,99");
+    cpu.attach_output(Recorder::default());
+    assert!(cpu.waiting_for_input());
+    let mut cpu = IntCodeCpu::from_code("3,20,3,21,99");
+    cpu.attach_input(Joystick { position: 1 });
+    assert!(!cpu.waiting_for_input());
+    assert_eq!(cpu.collect_screen().unwrap().answer, None);
+    assert_eq!(&cpu.memory[20..22], &[1, 1]);
+}
+
+#[test]
+fn test_random_source() {
+    let mut a = RandomSource::new(42);
+    let mut b = RandomSource::new(42);
+    let values = (0..100).map(|_| a.read(0)).collect::<Vec<i64>>();
+    assert!(values.iter().all(|value| *value >= 0));
+    assert_eq!(values, (0..100).map(|_| b.read(0)).collect::<Vec<i64>>());
+    assert_ne!(values[0], values[1]);
+}
+
+#[test]
+#[should_panic(expected = "device range 5..10 overlaps 0..6")]
+fn test_overlapping_devices() {
+    let mut cpu = IntCodeCpu::from_code("99");
+    cpu.map_device(0..6, Clock::default());
+    cpu.map_device(5..10, Clock::default());
+}
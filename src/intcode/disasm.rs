@@ -0,0 +1,226 @@
+use std::ops::Range;
+use super::{IntCodeCpu, ParameterMode};
+use super::symbols::{SymbolKind, SymbolMap};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    pub operand_count: usize,
+    // index of the operand that is written to, if any
+    pub dst: Option<usize>,
+}
+
+pub fn opcode_info(opcode: i64) -> Option<OpcodeInfo> {
+    let (mnemonic, operand_count, dst) = match opcode {
+        1 => ("add", 3, Some(2)),
+        2 => ("mul", 3, Some(2)),
+        3 => ("in", 1, Some(0)),
+        4 => ("out", 1, None),
+        5 => ("jnz", 2, None),
+        6 => ("jz", 2, None),
+        7 => ("lt", 3, Some(2)),
+        8 => ("eq", 3, Some(2)),
+        9 => ("arb", 1, None),
+        99 => ("halt", 0, None),
+        _ => return None,
+    };
+    Some(OpcodeInfo { mnemonic, operand_count, dst })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Operand {
+    pub mode: ParameterMode,
+    pub value: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub address: usize,
+    pub opcode: i64,
+    pub info: OpcodeInfo,
+    pub operands: Vec<Operand>,
+}
+
+// Decodes the instruction at `address` without executing it. Returns `None` for anything the
+// CPU would fault on: unknown opcodes, immediate destinations and truncated instructions.
+pub fn decode(memory: &[i64], address: usize) -> Option<DecodedInstruction> {
+    let inst = *memory.get(address)?;
+    if inst < 0 {
+        return None;
+    }
+    let opcode = inst % 100;
+    let info = opcode_info(opcode)?;
+    let operands = (0..info.operand_count)
+        .map(|n| memory.get(address + 1 + n).map(|value| Operand {
+            mode: ParameterMode::of_operand(inst, n as u32),
+            value: *value,
+        }))
+        .collect::<Option<Vec<Operand>>>()?;
+    if let Some(dst) = info.dst {
+        if operands[dst].mode == ParameterMode::Immediate {
+            return None;
+        }
+    }
+    Some(DecodedInstruction { address, opcode, info, operands })
+}
+
+impl DecodedInstruction {
+    // number of memory cells the encoded instruction occupies
+    pub fn size(&self) -> usize {
+        1 + self.operands.len()
+    }
+
+    pub fn is_jump(&self) -> bool {
+        self.opcode == 5 || self.opcode == 6
+    }
+
+    pub fn format(&self, symbols: Option<&SymbolMap>) -> String {
+        self.format_with(symbols, |_| None)
+    }
+
+    // `value_of` can supply the current value of a source operand, it is shown next to it
+    fn format_with<F>(&self, symbols: Option<&SymbolMap>, value_of: F) -> String
+        where F: Fn(&Operand) -> Option<i64> {
+        let operands = self.operands.iter().enumerate().map(|(n, operand)| {
+            let is_jump_target = self.is_jump() && n == 1;
+            let mut formatted = format_operand(operand, is_jump_target, symbols);
+            if operand.mode != ParameterMode::Immediate && Some(n) != self.info.dst {
+                if let Some(value) = value_of(operand) {
+                    formatted.push_str(&format!("={}", value));
+                }
+            }
+            formatted
+        }).collect::<Vec<String>>();
+        if operands.is_empty() {
+            self.info.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.info.mnemonic, operands.join(", "))
+        }
+    }
+}
+
+fn format_operand(operand: &Operand, is_jump_target: bool, symbols: Option<&SymbolMap>) -> String {
+    let name_of = |addr: i64| if addr < 0 {
+        None
+    } else {
+        symbols.and_then(|symbols| symbols.name_of(addr as usize))
+    };
+    match operand.mode {
+        ParameterMode::Position => format!("[{}]", name_of(operand.value).unwrap_or_else(|| operand.value.to_string())),
+        ParameterMode::Immediate if is_jump_target => name_of(operand.value).unwrap_or_else(|| operand.value.to_string()),
+        ParameterMode::Immediate => operand.value.to_string(),
+        ParameterMode::Relative if operand.value < 0 => format!("[rbp{}]", operand.value),
+        ParameterMode::Relative => format!("[rbp+{}]", operand.value),
+    }
+}
+
+// Linear sweep over `range`. Words that don't decode and regions covered by data symbols
+// are printed as `.data`.
+pub fn disassemble(memory: &[i64], range: Range<usize>, symbols: Option<&SymbolMap>) -> String {
+    disassemble_annotated(memory, range, symbols, |_, _| (String::new(), String::new()))
+}
+
+// Like `disassemble`, `annotate` returns a prefix and a suffix for every instruction or data
+// line given its address and the decoded instruction, if any.
+pub fn disassemble_annotated<F>(memory: &[i64], range: Range<usize>, symbols: Option<&SymbolMap>, mut annotate: F) -> String
+    where F: FnMut(usize, Option<&DecodedInstruction>) -> (String, String) {
+    let mut result = String::new();
+    let mut addr = range.start;
+    let end = range.end.min(memory.len());
+    while addr < end {
+        let symbol = symbols.and_then(|symbols| symbols.get(addr));
+        if let Some(symbol) = symbol {
+            result.push_str(&format!("{}:\n", symbol.name));
+        }
+        let data_len = symbols.and_then(|symbols| symbols.lookup(addr))
+            .filter(|(symbol, _)| symbol.kind != SymbolKind::Code)
+            .map(|(symbol, offset)| symbol.length - offset);
+        let len = match (data_len, decode(memory, addr)) {
+            (None, Some(inst)) if addr + inst.size() <= end => {
+                let (prefix, suffix) = annotate(addr, Some(&inst));
+                result.push_str(&format!("{}{:>6}  {}{}\n", prefix, addr, inst.format(symbols), suffix));
+                inst.size()
+            }
+            (data_len, _) => {
+                let len = data_len.unwrap_or(1).min(end - addr);
+                let words = memory[addr..addr + len].iter().map(i64::to_string).collect::<Vec<String>>();
+                let (prefix, suffix) = annotate(addr, None);
+                result.push_str(&format!("{}{:>6}  .data {}{}\n", prefix, addr, words.join(", "), suffix));
+                len
+            }
+        };
+        addr += len;
+    }
+    result
+}
+
+impl IntCodeCpu {
+    // Describes the instruction at ip together with the values of its memory operands.
+    pub fn trace(&self, symbols: Option<&SymbolMap>) -> String {
+        let inst = match decode(&self.memory, self.ip) {
+            Some(inst) => inst.format_with(symbols, |operand| {
+                let addr = match operand.mode {
+                    ParameterMode::Position => operand.value,
+                    ParameterMode::Relative => self.rbp.checked_add(operand.value)?,
+                    ParameterMode::Immediate => return None,
+                };
+                Some(if addr < 0 { 0 } else { self.memory.get(addr as usize).copied().unwrap_or(0) })
+            }),
+            None => format!(".data {}", self.memory.get(self.ip).copied().unwrap_or(0)),
+        };
+        let location = symbols.and_then(|symbols| symbols.name_of(self.ip))
+            .map_or_else(String::new, |name| format!(" <{}>", name));
+        format!("{:>6}{} rbp={}  {}", self.ip, location, self.rbp, inst)
+    }
+
+    // Executes a single instruction and returns its trace line.
+    pub fn trace_step(&mut self, symbols: Option<&SymbolMap>) -> String {
+        let trace = self.trace(symbols);
+        self.step();
+        trace
+    }
+}
+
+#[test]
+fn test_decode() {
+    let memory = vec![1002, 4, 3, 4, 33, 21101, 1, 2, -3, 99];
+    let inst = decode(&memory, 0).unwrap();
+    assert_eq!(inst.info.mnemonic, "mul");
+    assert_eq!(inst.operands, vec![
+        Operand { mode: ParameterMode::Position, value: 4 },
+        Operand { mode: ParameterMode::Immediate, value: 3 },
+        Operand { mode: ParameterMode::Position, value: 4 },
+    ]);
+    assert_eq!(inst.format(None), "mul [4], 3, [4]");
+    assert_eq!(decode(&memory, 4), None);
+    assert_eq!(decode(&memory, 5).unwrap().format(None), "add 1, 2, [rbp-3]");
+    assert_eq!(decode(&memory, 9).unwrap().size(), 1);
+    // immediate destination
+    assert_eq!(decode(&[11101, 1, 2, 3], 0), None);
+    // truncated
+    assert_eq!(decode(&[1, 0, 0], 0), None);
+}
+
+#[test]
+fn test_disassemble_with_symbols() {
+    let symbols: SymbolMap = "0 main code\n7 counter\n8 message string 2".parse().unwrap();
+    let memory = vec![1001, 7, 1, 7, 1105, 1, 0, 5, 72, 105];
+    assert_eq!(disassemble(&memory, 0..memory.len(), Some(&symbols)), "\
+main:
+     0  add [counter], 1, [counter]
+     4  jnz 1, main
+counter:
+     7  .data 5
+message:
+     8  .data 72, 105
+");
+}
+
+#[test]
+fn test_trace() {
+    let symbols: SymbolMap = "7 counter".parse().unwrap();
+    let mut cpu = IntCodeCpu::from_code("1001,7,1,7,1105,1,0,5");
+    assert_eq!(cpu.trace_step(Some(&symbols)), "     0 rbp=0  add [counter]=5, 1, [counter]");
+    assert_eq!(cpu.trace_step(None), "     4 rbp=0  jnz 1, 0");
+    assert_eq!(cpu.trace(Some(&symbols)), "     0 rbp=0  add [counter]=6, 1, [counter]");
+}
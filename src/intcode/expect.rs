@@ -0,0 +1,207 @@
+use std::fmt;
+use super::IntCodeCpu;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExpectError {
+    Halted { transcript: String },
+    WaitingForInput { transcript: String },
+    Timeout { transcript: String },
+}
+
+impl ExpectError {
+    pub fn transcript(&self) -> &str {
+        match self {
+            ExpectError::Halted { transcript } => transcript,
+            ExpectError::WaitingForInput { transcript } => transcript,
+            ExpectError::Timeout { transcript } => transcript,
+        }
+    }
+}
+
+impl fmt::Display for ExpectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpectError::Halted { .. } => write!(f, "program halted")?,
+            ExpectError::WaitingForInput { .. } => write!(f, "program is waiting for input")?,
+            ExpectError::Timeout { .. } => write!(f, "step budget exhausted")?,
+        }
+        write!(f, " after printing {:?}", self.transcript())
+    }
+}
+
+impl std::error::Error for ExpectError {}
+
+// Everything a program printed, with a trailing non-ASCII value (usually the puzzle answer)
+// split off from the text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AsciiOutput {
+    pub text: String,
+    pub answer: Option<i64>,
+}
+
+impl AsciiOutput {
+    pub fn from_values<I: IntoIterator<Item = i64>>(values: I) -> AsciiOutput {
+        let mut result = AsciiOutput::default();
+        for value in values {
+            if let Some(answer) = result.answer.take() {
+                result.text.push_str(&answer.to_string());
+            }
+            if is_ascii(value) {
+                result.text.push(value as u8 as char);
+            } else {
+                result.answer = Some(value);
+            }
+        }
+        result
+    }
+}
+
+enum Blocked {
+    Halted,
+    WaitingForInput,
+    Timeout,
+}
+
+fn is_ascii(value: i64) -> bool {
+    (0..=255).contains(&value)
+}
+
+impl IntCodeCpu {
+    // Limits how many instructions a single expect call may execute, `None` means no limit.
+    pub fn set_step_budget(&mut self, budget: Option<u64>) {
+        self.step_budget = budget;
+    }
+
+    // true if the next instruction reads input that neither the queue nor an input device has
+    pub fn waiting_for_input(&self) -> bool {
+        self.running && self.input.is_empty() && !self.devices.has_input()
+            && self.memory.get(self.ip).is_some_and(|i| i % 100 == 3)
+    }
+
+    pub fn send_line(&mut self, text: &str) {
+        self.input.extend(text.bytes().map(i64::from));
+        self.input.push_back(i64::from(b'\n'));
+    }
+
+    // Runs until the output ends with `prompt` and returns everything printed before it.
+    pub fn read_until_prompt(&mut self, prompt: &str) -> Result<String, ExpectError> {
+        let deadline = self.deadline();
+        let mut transcript = String::new();
+        while !transcript.ends_with(prompt) {
+            let c = self.next_output(deadline).map_err(|e| e.into_error(&transcript))?;
+            transcript.push(c as u8 as char);
+        }
+        transcript.truncate(transcript.len() - prompt.len());
+        Ok(transcript)
+    }
+
+    // Skips lines until one contains `pattern` and returns that line.
+    pub fn expect_line(&mut self, pattern: &str) -> Result<String, ExpectError> {
+        let deadline = self.deadline();
+        let mut transcript = String::new();
+        let mut line = String::new();
+        loop {
+            let c = self.next_output(deadline).map_err(|e| e.into_error(&transcript))? as u8 as char;
+            transcript.push(c);
+            if c == '\n' {
+                if line.contains(pattern) {
+                    return Ok(line);
+                }
+                line.clear();
+            } else {
+                line.push(c);
+            }
+        }
+    }
+
+    // Runs until the program halts or needs more input and returns everything it printed.
+    pub fn collect_screen(&mut self) -> Result<AsciiOutput, ExpectError> {
+        let deadline = self.deadline();
+        let mut values = vec![];
+        loop {
+            match self.next_output(deadline) {
+                Ok(value) => values.push(value),
+                Err(Blocked::Timeout) => {
+                    let transcript = AsciiOutput::from_values(values).text;
+                    return Err(ExpectError::Timeout { transcript });
+                }
+                Err(_) => return Ok(AsciiOutput::from_values(values)),
+            }
+        }
+    }
+
+    fn deadline(&self) -> Option<u64> {
+        self.step_budget.map(|budget| self.steps + budget)
+    }
+
+    fn next_output(&mut self, deadline: Option<u64>) -> Result<i64, Blocked> {
+        loop {
+            if let Some(value) = self.output.pop_front() {
+                return Ok(value);
+            }
+            if !self.running {
+                return Err(Blocked::Halted);
+            }
+            if self.waiting_for_input() {
+                return Err(Blocked::WaitingForInput);
+            }
+            if deadline.is_some_and(|deadline| self.steps >= deadline) {
+                return Err(Blocked::Timeout);
+            }
+            self.step();
+        }
+    }
+}
+
+impl Blocked {
+    fn into_error(self, transcript: &str) -> ExpectError {
+        let transcript = transcript.to_string();
+        match self {
+            Blocked::Halted => ExpectError::Halted { transcript },
+            Blocked::WaitingForInput => ExpectError::WaitingForInput { transcript },
+            Blocked::Timeout => ExpectError::Timeout { transcript },
+        }
+    }
+}
+
+// prints "Name?\n", echoes a line from the input, then prints 1000 and halts
+#[cfg(test)]
+const GREETER: &str = "104,78,104,97,104,109,104,101,104,63,104,10,\
+                       3,200,1008,200,10,201,1005,201,30,4,200,1105,1,12,\
+                       99,99,99,99,\
+                       104,10,104,1000,99";
+
+#[test]
+fn test_read_until_prompt() {
+    let mut cpu = IntCodeCpu::from_code(GREETER);
+    assert_eq!(cpu.read_until_prompt("?"), Ok("Name".to_string()));
+    assert_eq!(cpu.expect_line("missing"), Err(ExpectError::WaitingForInput { transcript: "\n".to_string() }));
+    cpu.send_line("Bob");
+    assert_eq!(cpu.expect_line("Bob"), Ok("Bob".to_string()));
+    assert!(matches!(cpu.read_until_prompt("?"), Err(ExpectError::Halted { .. })));
+}
+
+#[test]
+fn test_collect_screen() {
+    let mut cpu = IntCodeCpu::from_code(GREETER);
+    assert_eq!(cpu.collect_screen(), Ok(AsciiOutput { text: "Name?\n".to_string(), answer: None }));
+    cpu.send_line("Al");
+    assert_eq!(cpu.collect_screen(), Ok(AsciiOutput { text: "Al\n".to_string(), answer: Some(1000) }));
+    assert!(!cpu.running);
+}
+
+#[test]
+fn test_step_budget() {
+    let mut cpu = IntCodeCpu::from_code("1105,1,0");
+    cpu.set_step_budget(Some(100));
+    assert_eq!(cpu.collect_screen(), Err(ExpectError::Timeout { transcript: String::new() }));
+    assert_eq!(cpu.steps(), 100);
+}
+
+#[test]
+fn test_ascii_output_answer() {
+    let output = AsciiOutput::from_values(vec![79, 75, 10, 19_357_180]);
+    assert_eq!(output, AsciiOutput { text: "OK\n".to_string(), answer: Some(19_357_180) });
+    let output = AsciiOutput::from_values(vec![1000, 10]);
+    assert_eq!(output, AsciiOutput { text: "1000\n".to_string(), answer: None });
+}
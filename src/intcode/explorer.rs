@@ -0,0 +1,156 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use super::IntCodeCpu;
+
+// What happened when a move was tried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Blocked,
+    Open,
+    // open and one of the positions the search is looking for
+    Goal,
+}
+
+// Searches the positions a program can reach, like the droid of day 15. Every move is tried
+// on a clone of the CPU that reached a position, `step` makes the move on the CPU and reports
+// the outcome and `position` tracks where a move leads to. Every position is only explored
+// once, from the CPU that reached it first.
+pub struct Explorer<M, F, G> {
+    moves: Vec<M>,
+    step: F,
+    position: G,
+    depth_first: bool,
+}
+
+// Result of a search. With breadth-first order the distances are the shortest ones, depth-first
+// search only finds some path.
+#[derive(Clone)]
+pub struct Exploration<P, M> {
+    // every position tried, including blocked ones
+    pub map: HashMap<P, Outcome>,
+    // moves from the start to every reachable position
+    pub distances: HashMap<P, usize>,
+    // goals in the order they were found, with the CPU that reached them
+    pub goals: Vec<(P, IntCodeCpu)>,
+    came_from: HashMap<P, (P, M)>,
+}
+
+impl<M: Clone, F, G> Explorer<M, F, G> {
+    pub fn new(moves: Vec<M>, step: F, position: G) -> Explorer<M, F, G> {
+        Explorer { moves, step, position, depth_first: false }
+    }
+
+    pub fn depth_first(mut self) -> Explorer<M, F, G> {
+        self.depth_first = true;
+        self
+    }
+
+    pub fn explore<P>(&self, cpu: &IntCodeCpu, start: P) -> Exploration<P, M>
+        where P: Clone + Eq + Hash, F: Fn(&mut IntCodeCpu, &M) -> Outcome, G: Fn(&P, &M) -> P {
+        let mut exploration = Exploration {
+            map: HashMap::new(),
+            distances: HashMap::new(),
+            goals: vec![],
+            came_from: HashMap::new(),
+        };
+        exploration.map.insert(start.clone(), Outcome::Open);
+        exploration.distances.insert(start.clone(), 0);
+        let mut todo = VecDeque::new();
+        todo.push_back((cpu.clone(), start));
+        loop {
+            let next = if self.depth_first { todo.pop_back() } else { todo.pop_front() };
+            let (cpu, position) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            let distance = exploration.distances[&position];
+            for mv in &self.moves {
+                let next = (self.position)(&position, mv);
+                if exploration.map.contains_key(&next) {
+                    continue;
+                }
+                let mut clone = cpu.clone();
+                let outcome = (self.step)(&mut clone, mv);
+                exploration.map.insert(next.clone(), outcome);
+                if outcome == Outcome::Blocked {
+                    continue;
+                }
+                exploration.distances.insert(next.clone(), distance + 1);
+                exploration.came_from.insert(next.clone(), (position.clone(), mv.clone()));
+                if outcome == Outcome::Goal {
+                    exploration.goals.push((next.clone(), clone.clone()));
+                }
+                todo.push_back((clone, next));
+            }
+        }
+        exploration
+    }
+}
+
+impl<P: Clone + Eq + Hash, M: Clone> Exploration<P, M> {
+    // Moves leading from the start to `position`, `None` if it wasn't reached.
+    pub fn path_to(&self, position: &P) -> Option<Vec<M>> {
+        self.distances.get(position)?;
+        let mut path = vec![];
+        let mut position = position;
+        while let Some((previous, mv)) = self.came_from.get(position) {
+            path.push(mv.clone());
+            position = previous;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.distances.values().copied().max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+const MAZE: &[&str] = &[
+    "#########",
+    "#S..#...#",
+    "#.#.#.#.#",
+    "#.#...#G#",
+    "#########",
+];
+
+// the CPU keeps its position in memory and asks the maze above whether it can move
+#[cfg(test)]
+fn maze_step(cpu: &mut IntCodeCpu, (dx, dy): &(i64, i64)) -> Outcome {
+    let (x, y) = (cpu.memory[0] + dx, cpu.memory[1] + dy);
+    let tile = MAZE[y as usize].as_bytes()[x as usize];
+    if tile == b'#' {
+        return Outcome::Blocked;
+    }
+    cpu.write_memory(0, x);
+    cpu.write_memory(1, y);
+    if tile == b'G' { Outcome::Goal } else { Outcome::Open }
+}
+
+#[cfg(test)]
+fn maze_position((x, y): &(i64, i64), (dx, dy): &(i64, i64)) -> (i64, i64) {
+    (x + dx, y + dy)
+}
+
+#[test]
+fn test_explore_maze() {
+    let cpu = IntCodeCpu::from_program(vec![1, 1]);
+    let explorer = Explorer::new(vec![(0, -1), (0, 1), (-1, 0), (1, 0)], maze_step, maze_position);
+    let exploration = explorer.explore(&cpu, (1, 1));
+    assert_eq!(exploration.goals.len(), 1);
+    let (goal, goal_cpu) = &exploration.goals[0];
+    assert_eq!((*goal, &goal_cpu.memory[..2]), ((7, 3), &[7, 3][..]));
+    assert_eq!(exploration.distances[goal], 12);
+    let (right, down, up) = ((1, 0), (0, 1), (0, -1));
+    assert_eq!(exploration.path_to(goal).unwrap(), vec![right, right, down, down, right, right, up, up, right, right, down, down]);
+    assert_eq!(exploration.map[&(2, 2)], Outcome::Blocked);
+    assert_eq!(exploration.distances.len(), 15);
+    assert_eq!(exploration.max_distance(), 12);
+    assert_eq!(exploration.path_to(&(0, 0)), None);
+
+    // depth-first finds the same places but not necessarily the shortest paths
+    let exploration = explorer.depth_first().explore(&cpu, (1, 1));
+    assert_eq!(exploration.distances.len(), 15);
+    assert_eq!(exploration.path_to(&(7, 3)).unwrap().len(), exploration.distances[&(7, 3)]);
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::sync::Arc;
+use super::IntCodeCpu;
+#[cfg(test)]
+use std::sync::Mutex;
+
+pub type ExtensionHandler = dyn Fn(&mut IntCodeCpu, &[i64]) + Send + Sync;
+
+// An additional instruction. The handler gets the values of source operands and the
+// addresses of destination operands, which it writes with `store`. ip is advanced past the
+// instruction afterwards unless the handler stopped the CPU or jumped.
+#[derive(Clone)]
+pub struct Extension {
+    pub opcode: i64,
+    pub operand_count: usize,
+    pub dst_operands: Vec<usize>,
+    handler: Arc<ExtensionHandler>,
+}
+
+impl Extension {
+    pub fn new<F>(opcode: i64, operand_count: usize, dst_operands: &[usize], handler: F) -> Extension
+        where F: Fn(&mut IntCodeCpu, &[i64]) + Send + Sync + 'static {
+        Extension {
+            opcode,
+            operand_count,
+            dst_operands: dst_operands.to_vec(),
+            handler: Arc::new(handler),
+        }
+    }
+
+    pub(super) fn handler(&self) -> Arc<ExtensionHandler> {
+        self.handler.clone()
+    }
+}
+
+impl fmt::Debug for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extension")
+            .field("opcode", &self.opcode)
+            .field("operand_count", &self.operand_count)
+            .field("dst_operands", &self.dst_operands)
+            .finish()
+    }
+}
+
+impl IntCodeCpu {
+    pub fn register_extension(&mut self, extension: Extension) {
+        if super::disasm::opcode_info(extension.opcode).is_some() {
+            panic!("opcode {} is a builtin instruction", extension.opcode);
+        }
+        if extension.opcode <= 0 || extension.opcode >= 100 {
+            panic!("opcode {} can't be encoded", extension.opcode);
+        }
+        if let Some(dst) = extension.dst_operands.iter().find(|dst| **dst >= extension.operand_count) {
+            panic!("destination operand {} out of range for opcode {}", dst, extension.opcode);
+        }
+        self.extensions.insert(extension.opcode, extension);
+    }
+
+    // Writes to the address of a destination operand, negative ones go through the address
+    // policy like they do for builtin instructions.
+    pub fn store(&mut self, addr: i64, val: i64) {
+        self.store_and_resize_memory(addr, val);
+    }
+
+    pub fn jump(&mut self, target: usize) {
+        self.ip = target;
+    }
+
+    // In strict mode extension opcodes fault just like unknown ones, so official puzzle
+    // inputs can be run without accidentally relying on them.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+}
+
+#[test]
+fn test_extension_debug_print() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let log_clone = log.clone();
+    let mut cpu = IntCodeCpu::from_code("50,5,1150,7,99,42");
+    cpu.register_extension(Extension::new(50, 1, &[], move |_, args| log_clone.lock().unwrap().push(args[0])));
+    cpu.run();
+    assert_eq!(*log.lock().unwrap(), vec![42, 7]);
+}
+
+#[test]
+fn test_extension_dst_operand() {
+    // squares the input into [rbp+1]
+    let mut cpu = IntCodeCpu::from_code("109,10,3,20,2053,20,1,204,1,99");
+    cpu.register_extension(Extension::new(53, 2, &[1], |cpu, args| cpu.store(args[1], args[0] * args[0])));
+    cpu.input.push_back(-12);
+    cpu.run();
+    assert_eq!(cpu.output.pop_front(), Some(144));
+}
+
+#[test]
+#[should_panic(expected = "negative address -1 at ip 0 (rbp=0)")]
+fn test_extension_dst_operand_is_checked() {
+    let mut cpu = IntCodeCpu::from_code("53,7,-1,99");
+    cpu.register_extension(Extension::new(53, 2, &[1], |cpu, args| cpu.store(args[1], args[0])));
+    cpu.run();
+}
+
+#[test]
+fn test_extension_dst_operand_ignored() {
+    let mut cpu = IntCodeCpu::from_code("153,7,-1,99");
+    cpu.register_extension(Extension::new(53, 2, &[1], |cpu, args| cpu.store(args[1], args[0])));
+    cpu.set_address_policy(super::AddressPolicy::Ignore);
+    cpu.run();
+    assert_eq!(cpu.memory, vec![153, 7, -1, 99]);
+}
+
+#[test]
+fn test_extension_jump() {
+    // jumps to [1] if [2] is negative, outputs 1 if it didn't and 2 if it did
+    let jump_if_negative = Extension::new(54, 2, &[], |cpu, args| {
+        if args[1] < 0 {
+            cpu.jump(args[0] as usize);
+        }
+    });
+    for (cond, output) in [(-1, 2), (1, 1)] {
+        let mut cpu = IntCodeCpu::from_code(&format!("1154,6,{},104,1,99,104,2,99", cond));
+        cpu.register_extension(jump_if_negative.clone());
+        cpu.run();
+        assert_eq!(cpu.output.pop_front(), Some(output));
+    }
+}
+
+#[test]
+fn test_extension_halt_with_code() {
+    let mut cpu = IntCodeCpu::from_code("1101,1,2,6,51,6,0");
+    cpu.register_extension(Extension::new(51, 1, &[], |cpu, args| {
+        cpu.output.push_back(args[0]);
+        cpu.running = false;
+    }));
+    cpu.run();
+    assert_eq!(cpu.output.pop_front(), Some(3));
+    assert_eq!(cpu.ip(), 4);
+}
+
+#[test]
+#[should_panic(expected = "bad opcode 52")]
+fn test_unknown_opcode_still_faults() {
+    let mut cpu = IntCodeCpu::from_code("52,99");
+    cpu.register_extension(Extension::new(50, 0, &[], |_, _| ()));
+    cpu.run();
+}
+
+#[test]
+#[should_panic(expected = "extension opcode 50 used in strict mode")]
+fn test_strict_mode_rejects_extensions() {
+    let mut cpu = IntCodeCpu::from_code("50,99");
+    cpu.register_extension(Extension::new(50, 0, &[], |_, _| ()));
+    cpu.set_strict(true);
+    cpu.run();
+}
+
+#[test]
+#[should_panic(expected = "opcode 1 is a builtin instruction")]
+fn test_cannot_override_builtin() {
+    IntCodeCpu::from_code("99").register_extension(Extension::new(1, 0, &[], |_, _| ()));
+}
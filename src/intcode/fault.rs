@@ -0,0 +1,43 @@
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+// Runs `f` and turns a panic (a VM fault like a bad opcode) into its message. The panic isn't
+// printed, panics outside of `catch_fault` still go to the previous panic hook.
+pub fn catch_fault<R, F: FnOnce() -> R>(f: F) -> Result<R, String> {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !QUIET.with(Cell::get) {
+                previous(info);
+            }
+        }));
+    });
+    let quiet = QUIET.with(|quiet| quiet.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    QUIET.with(|q| q.set(quiet));
+    result.map_err(|payload| {
+        if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown fault".to_string()
+        }
+    })
+}
+
+#[test]
+fn test_catch_fault() {
+    use super::IntCodeCpu;
+    assert_eq!(catch_fault(|| 42), Ok(42));
+    let mut cpu = IntCodeCpu::from_code("1101,1,1,-1,99");
+    assert_eq!(catch_fault(|| cpu.run()), Err("negative address -1 at ip 0 (rbp=0)".to_string()));
+    assert_eq!(catch_fault(|| IntCodeCpu::from_code("50").run()), Err("bad opcode 50".to_string()));
+}
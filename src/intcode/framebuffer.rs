@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use image::{ImageError, ImageResult};
+use image::gif::{Encoder, Frame};
+use super::devices::Device;
+
+// Characters and colors for the tiles of day 13's arcade cabinet.
+pub const ARCADE_PALETTE: &[(i64, char, [u8; 3])] = &[
+    (0, ' ', [0, 0, 0]),
+    (1, '#', [128, 128, 128]),
+    (2, '=', [0, 128, 255]),
+    (3, '-', [255, 255, 255]),
+    (4, 'o', [255, 64, 64]),
+];
+
+// largest GIF frame `write_gif` allocates, in pixels after scaling
+const MAX_GIF_PIXELS: i128 = 1 << 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    // (x, y, value) triples
+    Triples,
+    // characters, lines end with '\n' and an empty line ends the screen
+    Ascii,
+}
+
+// Sparse 2D screen fed by program output, either directly or attached as output device.
+// Positions registered as special registers (like day 13's score at (-1, 0)) are kept
+// separately and aren't drawn. Frames for an animation can be captured while drawing.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    mode: Mode,
+    pixels: HashMap<(i64, i64), i64>,
+    registers: BTreeMap<(i64, i64), Option<i64>>,
+    palette: HashMap<i64, (char, [u8; 3])>,
+    pending: Vec<i64>,
+    cursor: (i64, i64),
+    screen_complete: bool,
+    non_ascii: Vec<i64>,
+    capture: Option<Option<i64>>,
+    frames: Vec<HashMap<(i64, i64), i64>>,
+}
+
+impl Framebuffer {
+    fn new(mode: Mode) -> Framebuffer {
+        Framebuffer {
+            mode,
+            pixels: HashMap::new(),
+            registers: BTreeMap::new(),
+            palette: HashMap::new(),
+            pending: vec![],
+            cursor: (0, 0),
+            screen_complete: false,
+            non_ascii: vec![],
+            capture: None,
+            frames: vec![],
+        }
+    }
+
+    pub fn triples() -> Framebuffer {
+        Framebuffer::new(Mode::Triples)
+    }
+
+    pub fn ascii() -> Framebuffer {
+        Framebuffer::new(Mode::Ascii)
+    }
+
+    pub fn with_register(mut self, x: i64, y: i64) -> Framebuffer {
+        self.registers.insert((x, y), None);
+        self
+    }
+
+    pub fn with_palette(mut self, palette: &[(i64, char, [u8; 3])]) -> Framebuffer {
+        self.palette = palette.iter().map(|(value, c, color)| (*value, (*c, *color))).collect();
+        self
+    }
+
+    // Captures a frame whenever a pixel with value `on_value` is drawn, or for every drawn
+    // pixel if it's `None`. ASCII screens are captured once they are complete instead.
+    pub fn capture_frames(mut self, on_value: Option<i64>) -> Framebuffer {
+        self.capture = Some(on_value);
+        self
+    }
+
+    pub fn push(&mut self, value: i64) {
+        match self.mode {
+            Mode::Triples => {
+                self.pending.push(value);
+                if let [x, y, value] = self.pending[..] {
+                    self.pending.clear();
+                    self.draw(x, y, value);
+                }
+            }
+            Mode::Ascii if value == i64::from(b'\n') => {
+                if self.cursor.0 == 0 && !self.pixels.is_empty() && !self.screen_complete {
+                    self.screen_complete = true;
+                    if self.capture.is_some() {
+                        self.frames.push(self.pixels.clone());
+                    }
+                }
+                self.cursor = (0, self.cursor.1 + 1);
+            }
+            Mode::Ascii if (0..=255).contains(&value) => {
+                if self.screen_complete {
+                    self.screen_complete = false;
+                    self.pixels.clear();
+                    self.cursor = (0, 0);
+                }
+                self.pixels.insert(self.cursor, value);
+                self.cursor.0 += 1;
+            }
+            Mode::Ascii => self.non_ascii.push(value),
+        }
+    }
+
+    pub fn extend<I: IntoIterator<Item = i64>>(&mut self, values: I) {
+        values.into_iter().for_each(|value| self.push(value));
+    }
+
+    fn draw(&mut self, x: i64, y: i64, value: i64) {
+        if let Some(register) = self.registers.get_mut(&(x, y)) {
+            *register = Some(value);
+            return;
+        }
+        self.pixels.insert((x, y), value);
+        if let Some(on_value) = self.capture {
+            if on_value.is_none_or(|on_value| on_value == value) {
+                self.frames.push(self.pixels.clone());
+            }
+        }
+    }
+
+    pub fn get(&self, x: i64, y: i64) -> i64 {
+        self.pixels.get(&(x, y)).copied().unwrap_or(0)
+    }
+
+    pub fn register(&self, x: i64, y: i64) -> Option<i64> {
+        self.registers.get(&(x, y)).copied().flatten()
+    }
+
+    pub fn find(&self, value: i64) -> Option<(i64, i64)> {
+        self.pixels.iter().find(|(_, v)| **v == value).map(|(pos, _)| *pos)
+    }
+
+    pub fn count(&self, value: i64) -> usize {
+        self.pixels.values().filter(|v| **v == value).count()
+    }
+
+    // values that didn't fit an ASCII screen, usually the puzzle answer
+    pub fn non_ascii(&self) -> &[i64] {
+        &self.non_ascii
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    // inclusive (min_x, min_y, max_x, max_y)
+    pub fn bounds(&self) -> Option<(i64, i64, i64, i64)> {
+        bounds(self.pixels.keys())
+    }
+
+    fn char_of(&self, value: i64) -> char {
+        match self.palette.get(&value) {
+            Some((c, _)) => *c,
+            None if self.mode == Mode::Ascii => value as u8 as char,
+            None => '?',
+        }
+    }
+
+    fn color_of(&self, value: i64) -> [u8; 3] {
+        match self.palette.get(&value) {
+            Some((_, color)) => *color,
+            None if self.mode == Mode::Ascii && value as u8 != b' ' && value as u8 != b'.' => [255, 255, 255],
+            None if self.mode == Mode::Ascii => [0, 0, 0],
+            None => [255, 0, 255],
+        }
+    }
+
+    // Text snapshot of the screen, missing pixels are drawn like 0 in triples mode and as
+    // spaces in ASCII mode.
+    pub fn render_text(&self) -> String {
+        let (min_x, min_y, max_x, max_y) = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return String::new(),
+        };
+        let mut result = String::new();
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                result.push(match (self.pixels.get(&(x, y)), self.mode) {
+                    (Some(value), _) => self.char_of(*value),
+                    (None, Mode::Triples) => self.char_of(0),
+                    (None, Mode::Ascii) => ' ',
+                });
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    // Clears the terminal and draws the screen followed by the registers.
+    pub fn render_terminal(&self) -> String {
+        let mut result = String::from("\x1b[H\x1b[2J");
+        result.push_str(&self.render_text());
+        for ((x, y), value) in &self.registers {
+            if let Some(value) = value {
+                result.push_str(&format!("({}, {}): {}\n", x, y, value));
+            }
+        }
+        result
+    }
+
+    // Writes the captured frames as animated GIF, or just the current screen if no frames
+    // were captured. Every pixel is scaled to `scale`x`scale`, `delay` is in 1/100 s. Fails
+    // with `DimensionError` if the scaled screen is too large for a GIF or `MAX_GIF_PIXELS`.
+    pub fn write_gif<W: Write>(&self, writer: W, scale: u16, delay: u16) -> ImageResult<()> {
+        let current = [self.pixels.clone()];
+        let frames = if self.frames.is_empty() { &current[..] } else { &self.frames[..] };
+        let (min_x, min_y, max_x, max_y) = bounds(frames.iter().flat_map(|frame| frame.keys())).unwrap_or((0, 0, 0, 0));
+        let scaled = |min: i64, max: i64| (i128::from(max) - i128::from(min) + 1) * i128::from(scale.max(1));
+        let (width, height) = (scaled(min_x, max_x), scaled(min_y, max_y));
+        if width > i128::from(u16::MAX) || height > i128::from(u16::MAX) || width * height > MAX_GIF_PIXELS {
+            return Err(ImageError::DimensionError);
+        }
+        let (scale, width, height) = (i64::from(scale.max(1)), width as i64, height as i64);
+        let mut encoder = Encoder::new(writer);
+        for frame in frames {
+            let mut rgb = vec![0; (width * height * 3) as usize];
+            for ((x, y), value) in frame {
+                let color = self.color_of(*value);
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let offset = (((y - min_y) * scale + dy) * width + (x - min_x) * scale + dx) as usize * 3;
+                        rgb[offset..offset + 3].copy_from_slice(&color);
+                    }
+                }
+            }
+            let mut frame = Frame::from_rgb(width as u16, height as u16, &rgb);
+            frame.delay = delay;
+            encoder.encode(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+fn bounds<'a, I: Iterator<Item = &'a (i64, i64)>>(positions: I) -> Option<(i64, i64, i64, i64)> {
+    positions.fold(None, |bounds, (x, y)| Some(match bounds {
+        None => (*x, *y, *x, *y),
+        Some((min_x, min_y, max_x, max_y)) => (min_x.min(*x), min_y.min(*y), max_x.max(*x), max_y.max(*y)),
+    }))
+}
+
+impl Device for Framebuffer {
+    fn output(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+#[test]
+fn test_triples() {
+    let mut screen = Framebuffer::triples().with_register(-1, 0).with_palette(ARCADE_PALETTE);
+    screen.extend(vec![0, 0, 1, 1, 0, 1, 2, 0, 1, 0, 1, 1, 2, 1, 1, 1, 1, 4, -1, 0, 12345, 0, 2, 1, 2, 2, 1, 1, 2, 3]);
+    assert_eq!(screen.render_text(), "###\n#o#\n#-#\n");
+    assert_eq!(screen.register(-1, 0), Some(12345));
+    assert_eq!(screen.find(4), Some((1, 1)));
+    assert_eq!(screen.count(1), 7);
+    assert_eq!(screen.get(-1, 0), 0);
+    assert_eq!(screen.bounds(), Some((0, 0, 2, 2)));
+    assert!(screen.render_terminal().ends_with("#-#\n(-1, 0): 12345\n"));
+}
+
+#[test]
+fn test_ascii_screens() {
+    let mut screen = Framebuffer::ascii().capture_frames(None);
+    screen.extend("#.\n.#\n\n##\n".bytes().map(i64::from));
+    assert_eq!(screen.frame_count(), 1);
+    assert_eq!(screen.render_text(), "##\n");
+    screen.extend(vec![i64::from(b'\n'), 1234]);
+    assert_eq!(screen.frame_count(), 2);
+    assert_eq!(screen.non_ascii(), &[1234]);
+}
+
+#[test]
+fn test_write_gif() {
+    let mut screen = Framebuffer::triples().with_palette(ARCADE_PALETTE).capture_frames(Some(4));
+    screen.extend(vec![0, 0, 1, 1, 0, 4, 1, 0, 0, 2, 1, 4]);
+    assert_eq!(screen.frame_count(), 2);
+    let mut gif = vec![];
+    screen.write_gif(&mut gif, 4, 10).unwrap();
+    assert_eq!(&gif[..6], b"GIF89a");
+    // logical screen size of 3x2 pixels scaled by 4
+    assert_eq!(&gif[6..10], &[12, 0, 8, 0]);
+}
+
+#[test]
+fn test_write_gif_rejects_large_screens() {
+    let mut gif = vec![];
+    let mut screen = Framebuffer::triples();
+    screen.extend(vec![0, 0, 1, 70_000, 0, 1]);
+    assert!(matches!(screen.write_gif(&mut gif, 1, 10), Err(ImageError::DimensionError)));
+    let mut screen = Framebuffer::triples();
+    screen.extend(vec![i64::MIN, 0, 1, i64::MAX, 0, 1]);
+    assert!(matches!(screen.write_gif(&mut gif, 1, 10), Err(ImageError::DimensionError)));
+    // fits a GIF but not the pixel limit
+    let mut screen = Framebuffer::triples();
+    screen.extend(vec![0, 0, 1, 5000, 5000, 1]);
+    assert!(matches!(screen.write_gif(&mut gif, 1, 10), Err(ImageError::DimensionError)));
+    assert!(gif.is_empty());
+}
@@ -0,0 +1,390 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use super::IntCodeCpu;
+use super::fault::catch_fault;
+#[cfg(test)]
+use std::io::Read;
+
+// Memory is presented to the debugger as bytes, every intcode word occupies 8 bytes in little
+// endian at address `8 * word`. Register 0 is ip (the pc), register 1 is rbp, both 64 bit.
+const WORD_SIZE: usize = 8;
+const REGISTER_COUNT: usize = 2;
+// upper limit for a single memory read, so a bogus request can't allocate gigabytes
+const MAX_READ: usize = 0x10000;
+
+// GDB remote serial protocol stub backed by an `IntCodeCpu`. Supports reading and writing
+// registers and memory, single stepping, continuing and software breakpoints. Continuing
+// stops at breakpoints, when the program halts and when it waits for input that isn't
+// queued, the VM would otherwise read -1. A fault (bad opcode etc.) ends the program as if it
+// was killed by SIGILL.
+pub struct GdbStub {
+    cpu: IntCodeCpu,
+    breakpoints: BTreeSet<usize>,
+    faulted: bool,
+}
+
+enum Reply {
+    Packet(String),
+    Close(String),
+}
+
+impl GdbStub {
+    pub fn new(cpu: IntCodeCpu) -> GdbStub {
+        GdbStub { cpu, breakpoints: BTreeSet::new(), faulted: false }
+    }
+
+    pub fn cpu(&self) -> &IntCodeCpu {
+        &self.cpu
+    }
+
+    pub fn into_cpu(self) -> IntCodeCpu {
+        self.cpu
+    }
+
+    // Accepts a single debugger connection and serves it until it detaches or disconnects.
+    pub fn serve(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        self.serve_connection(stream)
+    }
+
+    pub fn serve_connection(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        while let Some(packet) = read_packet(&mut reader, &mut writer)? {
+            match self.handle_packet(&packet) {
+                Reply::Packet(reply) => write_packet(&mut writer, &reply)?,
+                Reply::Close(reply) => return write_packet(&mut writer, &reply),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, packet: &str) -> Reply {
+        let reply = match packet.chars().next() {
+            Some('?') => self.stop_reply(),
+            Some('g') => (0..REGISTER_COUNT).map(|n| encode_word(self.register(n))).collect(),
+            Some('G') => self.write_registers(&packet[1..]),
+            Some('p') => parse_hex(&packet[1..]).filter(|n| *n < REGISTER_COUNT)
+                .map_or_else(error, |n| encode_word(self.register(n))),
+            Some('P') => self.write_register(&packet[1..]),
+            Some('m') => self.read_memory(&packet[1..]),
+            Some('M') => self.write_memory(&packet[1..]),
+            Some('s') => {
+                self.step();
+                self.stop_reply()
+            }
+            Some('c') => {
+                self.resume();
+                self.stop_reply()
+            }
+            Some('Z') => self.breakpoint(&packet[1..], true),
+            Some('z') => self.breakpoint(&packet[1..], false),
+            Some('H') => "OK".to_string(),
+            Some('D') => return Reply::Close("OK".to_string()),
+            Some('k') => return Reply::Close(String::new()),
+            _ if packet.starts_with("qSupported") => format!("PacketSize={:x}", MAX_READ),
+            _ if packet == "qAttached" => "1".to_string(),
+            // unsupported packets get an empty reply
+            _ => String::new(),
+        };
+        Reply::Packet(reply)
+    }
+
+    fn stop_reply(&self) -> String {
+        if self.faulted {
+            "X04".to_string()
+        } else if self.cpu.running {
+            "S05".to_string()
+        } else {
+            "W00".to_string()
+        }
+    }
+
+    fn step(&mut self) {
+        if !self.cpu.running {
+            return;
+        }
+        let cpu = &mut self.cpu;
+        if catch_fault(|| { cpu.step(); }).is_err() {
+            self.cpu.running = false;
+            self.faulted = true;
+        }
+    }
+
+    fn resume(&mut self) {
+        // the instruction at a breakpoint we're already stopped at must run first
+        if self.breakpoints.contains(&self.cpu.ip) && !self.cpu.waiting_for_input() {
+            self.step();
+        }
+        while self.cpu.running && !self.breakpoints.contains(&self.cpu.ip) && !self.cpu.waiting_for_input() {
+            self.step();
+        }
+    }
+
+    fn register(&self, n: usize) -> i64 {
+        match n {
+            0 => self.cpu.ip as i64,
+            _ => self.cpu.rbp,
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: i64) {
+        match n {
+            0 => self.cpu.ip = value as usize,
+            _ => self.cpu.rbp = value,
+        }
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        let values = match decode_bytes(data) {
+            Some(bytes) if bytes.len() == REGISTER_COUNT * WORD_SIZE => bytes,
+            _ => return error(),
+        };
+        for (n, word) in values.chunks(WORD_SIZE).enumerate() {
+            self.set_register(n, word_from_bytes(word));
+        }
+        "OK".to_string()
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, '=');
+        let n = parts.next().and_then(parse_hex).filter(|n| *n < REGISTER_COUNT);
+        let value = parts.next().and_then(decode_bytes).filter(|bytes| bytes.len() == WORD_SIZE);
+        match (n, value) {
+            (Some(n), Some(value)) => {
+                self.set_register(n, word_from_bytes(&value));
+                "OK".to_string()
+            }
+            _ => error(),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, end) = match parse_range(args) {
+            Some((addr, len)) if len <= MAX_READ => match addr.checked_add(len) {
+                Some(end) => (addr, end),
+                None => return error(),
+            },
+            _ => return error(),
+        };
+        (addr..end)
+            .map(|byte| {
+                let word = self.cpu.memory.get(byte / WORD_SIZE).copied().unwrap_or(0);
+                format!("{:02x}", word.to_le_bytes()[byte % WORD_SIZE])
+            })
+            .collect()
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let mut parts = args.splitn(2, ':');
+        let range = parts.next().and_then(parse_range);
+        let data = parts.next().and_then(decode_bytes);
+        let (addr, data) = match (range, data) {
+            (Some((addr, len)), Some(data)) if data.len() == len => (addr, data),
+            _ => return error(),
+        };
+        // memory grows to fit the write, don't let a bogus address allocate gigabytes
+        let limit = (self.cpu.memory.len() + MAX_READ) * WORD_SIZE;
+        if addr.checked_add(data.len()).is_none_or(|end| end > limit) {
+            return error();
+        }
+        for (byte, value) in (addr..).zip(data) {
+            let mut word = self.cpu.memory.get(byte / WORD_SIZE).copied().unwrap_or(0).to_le_bytes();
+            word[byte % WORD_SIZE] = value;
+            self.cpu.write_memory(byte / WORD_SIZE, i64::from_le_bytes(word));
+        }
+        "OK".to_string()
+    }
+
+    fn breakpoint(&mut self, args: &str, insert: bool) -> String {
+        let fields = args.split(',').collect::<Vec<&str>>();
+        // only software breakpoints, they must be on a word boundary
+        let addr = match fields.as_slice() {
+            ["0", addr, _] => parse_hex(addr).filter(|addr| addr % WORD_SIZE == 0),
+            [_, _, _] => return String::new(),
+            _ => None,
+        };
+        match addr {
+            Some(addr) => {
+                if insert {
+                    self.breakpoints.insert(addr / WORD_SIZE);
+                } else {
+                    self.breakpoints.remove(&(addr / WORD_SIZE));
+                }
+                "OK".to_string()
+            }
+            None => error(),
+        }
+    }
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let mut parts = s.splitn(2, ',');
+    Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+fn decode_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+fn encode_word(word: i64) -> String {
+    word.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn word_from_bytes(bytes: &[u8]) -> i64 {
+    let mut word = [0; WORD_SIZE];
+    word.copy_from_slice(bytes);
+    i64::from_le_bytes(word)
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, u8::wrapping_add)
+}
+
+// Reads the next `$<data>#<checksum>` packet and acknowledges it, packets with a bad checksum
+// are nacked so the client retransmits. Returns `None` once the connection is closed.
+fn read_packet<R: BufRead, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<Option<String>> {
+    loop {
+        let mut skipped = vec![];
+        // acks and interrupt requests in front of a packet are ignored
+        if reader.read_until(b'$', &mut skipped)? == 0 || skipped.last() != Some(&b'$') {
+            return Ok(None);
+        }
+        let mut data = vec![];
+        reader.read_until(b'#', &mut data)?;
+        if data.pop() != Some(b'#') {
+            return Ok(None);
+        }
+        let mut sum = [0; 2];
+        reader.read_exact(&mut sum)?;
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let valid = std::str::from_utf8(&sum).ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+            == Some(checksum(&data));
+        if valid {
+            writer.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+        writer.write_all(b"-")?;
+    }
+}
+
+fn write_packet<W: Write>(writer: &mut W, data: &str) -> io::Result<()> {
+    write!(writer, "${}#{:02x}", data, checksum(data))?;
+    writer.flush()
+}
+
+#[cfg(test)]
+fn client_request(stream: &mut TcpStream, data: &str) -> String {
+    write_packet(stream, data).unwrap();
+    let mut ack = [0; 1];
+    stream.read_exact(&mut ack).unwrap();
+    assert_eq!(&ack, b"+");
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    read_packet(&mut reader, stream).unwrap().unwrap()
+}
+
+#[test]
+fn test_gdb_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let mut stub = GdbStub::new(IntCodeCpu::from_code("1101,1,2,10,1001,10,1,10,99,0,0"));
+        stub.serve(&listener).unwrap();
+        stub.into_cpu()
+    });
+    let mut client = TcpStream::connect(addr).unwrap();
+    let script = [
+        ("qSupported:multiprocess+", "PacketSize=10000"),
+        ("?", "S05"),
+        ("g", "00000000000000000000000000000000"),
+        ("m50,8", "0000000000000000"),
+        ("s", "S05"),
+        ("p0", "0400000000000000"),
+        ("m50,8", "0300000000000000"),
+        ("Z0,40,1", "OK"),
+        ("c", "S05"),
+        ("p0", "0800000000000000"),
+        ("m50,2", "0400"),
+        ("M50,8:2a00000000000000", "OK"),
+        ("m50,8", "2a00000000000000"),
+        ("P1=0500000000000000", "OK"),
+        ("g", "08000000000000000500000000000000"),
+        ("Z0,41,1", "E01"),
+        ("vMustReplyEmpty", ""),
+        ("z0,40,1", "OK"),
+        ("c", "W00"),
+        ("D", "OK"),
+    ];
+    for (request, reply) in script.iter() {
+        assert_eq!(client_request(&mut client, request), *reply, "reply to {}", request);
+    }
+    let cpu = server.join().unwrap();
+    assert_eq!(cpu.memory[10], 42);
+    assert_eq!(cpu.rbp(), 5);
+    assert!(!cpu.running);
+}
+
+#[test]
+fn test_gdb_bad_checksum_is_nacked() {
+    let mut input = &b"+$?#00$?#3f"[..];
+    let mut acks = vec![];
+    assert_eq!(read_packet(&mut input, &mut acks).unwrap(), Some("?".to_string()));
+    assert_eq!(acks, b"-+");
+    assert_eq!(read_packet(&mut input, &mut acks).unwrap(), None);
+}
+
+#[cfg(test)]
+fn reply_to(stub: &mut GdbStub, packet: &str) -> String {
+    match stub.handle_packet(packet) {
+        Reply::Packet(reply) | Reply::Close(reply) => reply,
+    }
+}
+
+#[test]
+fn test_gdb_rejects_bad_memory_requests() {
+    let mut stub = GdbStub::new(IntCodeCpu::from_code("99"));
+    assert_eq!(reply_to(&mut stub, "mffffffffffffffff,10"), "E01");
+    assert_eq!(reply_to(&mut stub, "Mffffffffffff0,8:2a00000000000000"), "E01");
+    assert_eq!(reply_to(&mut stub, "Mffffffffffffffff,1:2a"), "E01");
+    assert_eq!(stub.cpu().memory.len(), 1);
+    // growing the memory a bit is fine
+    assert_eq!(reply_to(&mut stub, "M400,8:2a00000000000000"), "OK");
+    assert_eq!(stub.cpu().memory[0x80], 42);
+}
+
+#[test]
+fn test_gdb_continue_doesnt_consume_missing_input() {
+    let mut stub = GdbStub::new(IntCodeCpu::from_code("3,5,4,5,99,0"));
+    assert_eq!(reply_to(&mut stub, "c"), "S05");
+    assert_eq!(stub.cpu().ip(), 0);
+    assert_eq!(reply_to(&mut stub, "c"), "S05");
+    assert_eq!(stub.cpu().ip(), 0);
+    stub.cpu.input.push_back(7);
+    assert_eq!(reply_to(&mut stub, "c"), "W00");
+    assert_eq!(stub.into_cpu().output, vec![7]);
+}
+
+#[test]
+fn test_gdb_fault_ends_program() {
+    // the second instruction writes to a negative address
+    let mut stub = GdbStub::new(IntCodeCpu::from_code("1101,1,2,9,1101,1,2,-1,99,0"));
+    assert_eq!(reply_to(&mut stub, "s"), "S05");
+    assert_eq!(reply_to(&mut stub, "c"), "X04");
+    assert_eq!(reply_to(&mut stub, "?"), "X04");
+    assert_eq!(reply_to(&mut stub, "s"), "X04");
+    assert_eq!(stub.cpu().memory[9], 3);
+}
@@ -0,0 +1,243 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use super::IntCodeCpu;
+
+// Observes execution, e.g. for tracing or profiling. All callbacks default to doing nothing.
+// Memory callbacks see operand accesses and every `write_memory`, e.g. from extension handlers,
+// but not instruction fetches. None of this is compiled without the `hooks` feature.
+pub trait Hook: Any + Send + Sync + CloneHook {
+    // `inst` is the encoded instruction about to run at `ip`
+    fn before_decode(&mut self, _ip: usize, _inst: i64) {}
+
+    // `ip` is where the instruction started, `next_ip` where execution continues
+    fn after_execute(&mut self, _ip: usize, _next_ip: usize) {}
+
+    fn memory_read(&mut self, _addr: usize, _value: i64) {}
+
+    // called once the value was written, `old` comes from the device for mapped cells
+    fn memory_write(&mut self, _addr: usize, _old: i64, _new: i64) {}
+
+    fn input(&mut self, _value: i64) {}
+
+    fn output(&mut self, _value: i64) {}
+}
+
+pub trait CloneHook {
+    fn clone_hook(&self) -> Box<dyn Hook>;
+}
+
+impl<T: Hook + Clone> CloneHook for T {
+    fn clone_hook(&self) -> Box<dyn Hook> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HookId(usize);
+
+// Removed hooks leave a hole so the ids of the others stay valid.
+#[derive(Default)]
+pub(super) struct Hooks {
+    hooks: Vec<Option<Box<dyn Hook>>>,
+    active: usize,
+}
+
+impl Clone for Hooks {
+    fn clone(&self) -> Self {
+        Hooks {
+            hooks: self.hooks.iter().map(|hook| hook.as_ref().map(|hook| hook.clone_hook())).collect(),
+            active: self.active,
+        }
+    }
+}
+
+impl Hooks {
+    pub(super) fn is_empty(&self) -> bool {
+        self.active == 0
+    }
+
+    pub(super) fn each<F: FnMut(&mut dyn Hook)>(&mut self, mut f: F) {
+        self.hooks.iter_mut().flatten().for_each(|hook| f(hook.as_mut()));
+    }
+}
+
+impl IntCodeCpu {
+    pub fn add_hook<H: Hook>(&mut self, hook: H) -> HookId {
+        self.hooks.hooks.push(Some(Box::new(hook)));
+        self.hooks.active += 1;
+        HookId(self.hooks.hooks.len() - 1)
+    }
+
+    pub fn hook<H: Hook>(&self, id: HookId) -> Option<&H> {
+        let hook: &dyn Any = self.hooks.hooks.get(id.0)?.as_deref()?;
+        hook.downcast_ref()
+    }
+
+    pub fn hook_mut<H: Hook>(&mut self, id: HookId) -> Option<&mut H> {
+        let hook: &mut dyn Any = self.hooks.hooks.get_mut(id.0)?.as_deref_mut()?;
+        hook.downcast_mut()
+    }
+
+    // Unregisters the hook and hands it back, if it is an `H`.
+    pub fn remove_hook<H: Hook>(&mut self, id: HookId) -> Option<H> {
+        self.hook::<H>(id)?;
+        let hook: Box<dyn Any> = self.hooks.hooks[id.0].take()?;
+        self.hooks.active -= 1;
+        hook.downcast().ok().map(|hook| *hook)
+    }
+
+    // first hook of type `H`
+    pub fn find_hook<H: Hook>(&self) -> Option<HookId> {
+        self.hooks.hooks.iter()
+            .position(|hook| hook.as_deref().is_some_and(|hook| {
+                let hook: &dyn Any = hook;
+                hook.is::<H>()
+            }))
+            .map(HookId)
+    }
+
+    // skips the bookkeeping entirely if no hook is registered
+    pub(super) fn notify<F: FnMut(&mut dyn Hook)>(&mut self, f: F) {
+        if !self.hooks.is_empty() {
+            self.hooks.each(f);
+        }
+    }
+}
+
+// Counts operand reads and writes per address, to find the hot variables of a program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryProfile {
+    pub reads: BTreeMap<usize, u64>,
+    pub writes: BTreeMap<usize, u64>,
+}
+
+impl MemoryProfile {
+    // addresses by number of accesses, most accessed first
+    pub fn hottest(&self, count: usize) -> Vec<(usize, u64)> {
+        let mut accesses = self.reads.clone();
+        for (addr, writes) in &self.writes {
+            *accesses.entry(*addr).or_insert(0) += writes;
+        }
+        let mut accesses = accesses.into_iter().collect::<Vec<_>>();
+        accesses.sort_by_key(|(addr, count)| (std::cmp::Reverse(*count), *addr));
+        accesses.truncate(count);
+        accesses
+    }
+}
+
+impl Hook for MemoryProfile {
+    fn memory_read(&mut self, addr: usize, _value: i64) {
+        *self.reads.entry(addr).or_insert(0) += 1;
+    }
+
+    fn memory_write(&mut self, addr: usize, _old: i64, _new: i64) {
+        *self.writes.entry(addr).or_insert(0) += 1;
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct EventLog {
+    events: Vec<String>,
+}
+
+#[cfg(test)]
+impl Hook for EventLog {
+    fn before_decode(&mut self, ip: usize, inst: i64) {
+        self.events.push(format!("decode {} at {}", inst, ip));
+    }
+
+    fn after_execute(&mut self, ip: usize, next_ip: usize) {
+        self.events.push(format!("executed {} -> {}", ip, next_ip));
+    }
+
+    fn memory_read(&mut self, addr: usize, value: i64) {
+        self.events.push(format!("read [{}] = {}", addr, value));
+    }
+
+    fn memory_write(&mut self, addr: usize, old: i64, new: i64) {
+        self.events.push(format!("write [{}] {} -> {}", addr, old, new));
+    }
+
+    fn input(&mut self, value: i64) {
+        self.events.push(format!("input {}", value));
+    }
+
+    fn output(&mut self, value: i64) {
+        self.events.push(format!("output {}", value));
+    }
+}
+
+#[test]
+fn test_hook_events() {
+    // in [9], add [9], 1, [9], out [9] with rbp moved to 2
+    let mut cpu = IntCodeCpu::from_code("3,9,1001,9,1,9,204,7,99");
+    cpu.input.push_back(41);
+    let log = cpu.add_hook(EventLog::default());
+    cpu.single_step();
+    cpu.single_step();
+    cpu.rbp = 2;
+    cpu.run();
+    assert_eq!(cpu.hook::<EventLog>(log).unwrap().events, vec![
+        "decode 3 at 0",
+        "input 41",
+        "write [9] 0 -> 41",
+        "executed 0 -> 2",
+        "decode 1001 at 2",
+        "read [9] = 41",
+        "write [9] 41 -> 42",
+        "executed 2 -> 6",
+        "decode 204 at 6",
+        "read [9] = 42",
+        "output 42",
+        "executed 6 -> 8",
+        "decode 99 at 8",
+        "executed 8 -> 8",
+    ]);
+    assert!(cpu.hook::<MemoryProfile>(log).is_none());
+    assert_eq!(cpu.find_hook::<EventLog>(), Some(log));
+    assert_eq!(cpu.find_hook::<MemoryProfile>(), None);
+}
+
+#[test]
+fn test_hooks_see_device_and_outside_writes() {
+    use super::devices::Clock;
+    let mut cpu = IntCodeCpu::from_code("1101,5,0,20,99");
+    cpu.map_device(20..21, Clock { ticks: 3 });
+    let log = cpu.add_hook(EventLog::default());
+    cpu.single_step();
+    cpu.write_memory(3, 7);
+    let events = &cpu.hook::<EventLog>(log).unwrap().events;
+    assert_eq!(&events[1..], &["write [20] 3 -> 5", "executed 0 -> 4", "write [3] 20 -> 7"]);
+}
+
+#[test]
+fn test_memory_profile() {
+    // counts [20] down from 3
+    let mut cpu = IntCodeCpu::from_code("1101,3,0,20,1001,20,-1,20,1005,20,4,99");
+    let profile = cpu.add_hook(MemoryProfile::default());
+    cpu.run();
+    let profile = cpu.hook::<MemoryProfile>(profile).unwrap();
+    assert_eq!(profile.reads[&20], 6);
+    assert_eq!(profile.writes[&20], 4);
+    assert_eq!(profile.hottest(1), vec![(20, 10)]);
+    // hooks are cloned with the CPU
+    let mut clone = cpu.clone();
+    clone.hook_mut::<MemoryProfile>(HookId(0)).unwrap().reads.clear();
+    assert_eq!(cpu.hook::<MemoryProfile>(HookId(0)).unwrap().reads.len(), 1);
+}
+
+#[test]
+fn test_remove_hook() {
+    let mut cpu = IntCodeCpu::from_code("1101,1,1,20,99");
+    let log = cpu.add_hook(EventLog::default());
+    let profile = cpu.add_hook(MemoryProfile::default());
+    assert!(cpu.remove_hook::<MemoryProfile>(log).is_none());
+    assert!(cpu.remove_hook::<EventLog>(log).is_some());
+    assert!(cpu.remove_hook::<EventLog>(log).is_none());
+    assert_eq!(cpu.find_hook::<EventLog>(), None);
+    cpu.run();
+    assert_eq!(cpu.hook::<MemoryProfile>(profile).unwrap().writes[&20], 1);
+    cpu.remove_hook::<MemoryProfile>(profile).unwrap();
+    assert!(cpu.hooks.is_empty());
+}
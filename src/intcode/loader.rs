@@ -0,0 +1,219 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use super::IntCodeCpu;
+
+// Packed programs start with this magic followed by a format version, the number of words
+// as a varint and the zigzag-encoded words as varints.
+const PACKED_MAGIC: &[u8; 4] = b"ICPK";
+const PACKED_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(ParseError),
+    Format(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "I/O error: {}", e),
+            LoadError::Parse(e) => write!(f, "parse error at {}", e),
+            LoadError::Format(e) => write!(f, "bad packed program: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<ParseError> for LoadError {
+    fn from(e: ParseError) -> Self {
+        LoadError::Parse(e)
+    }
+}
+
+// Parses comma-separated values. Whitespace and newlines are allowed anywhere between
+// values, '#' starts a comment that runs until the end of the line and a single trailing
+// comma is tolerated.
+pub fn parse_program(text: &str) -> Result<Vec<i64>, ParseError> {
+    let mut program = vec![];
+    // start position and text of the value currently being read
+    let mut token: Option<(usize, usize, String)> = None;
+    let mut token_done = false;
+    let mut last_comma = None;
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_no = line_idx + 1;
+        for (col_idx, c) in line.chars().enumerate() {
+            let col_no = col_idx + 1;
+            let error = |message: String| ParseError { line: line_no, column: col_no, message };
+            match c {
+                '#' => break,
+                ',' => {
+                    match token.take() {
+                        Some((line, column, text)) => program.push(parse_value(line, column, &text)?),
+                        None => return Err(error("expected a value before ','".to_string())),
+                    }
+                    token_done = false;
+                    last_comma = Some((line_no, col_no));
+                }
+                c if c.is_whitespace() => token_done = token.is_some(),
+                c => match &mut token {
+                    Some(_) if token_done => return Err(error(format!("expected ',' before '{}'", c))),
+                    Some((_, _, text)) => text.push(c),
+                    None => token = Some((line_no, col_no, c.to_string())),
+                },
+            }
+        }
+        token_done = token.is_some();
+    }
+    match token {
+        Some((line, column, text)) => program.push(parse_value(line, column, &text)?),
+        None if program.is_empty() => return Err(ParseError {
+            line: last_comma.map_or(1, |(line, _)| line),
+            column: last_comma.map_or(1, |(_, column)| column),
+            message: "empty program".to_string(),
+        }),
+        None => {}
+    }
+    Ok(program)
+}
+
+fn parse_value(line: usize, column: usize, text: &str) -> Result<i64, ParseError> {
+    text.parse::<i64>().map_err(|e| ParseError { line, column, message: format!("bad value \"{}\": {}", text, e) })
+}
+
+// Reads a program in either text or packed format.
+pub fn read_program<R: Read>(mut reader: R) -> Result<Vec<i64>, LoadError> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    if data.starts_with(PACKED_MAGIC) {
+        unpack(&data[PACKED_MAGIC.len()..])
+    } else {
+        let text = String::from_utf8(data).map_err(|e| LoadError::Format(e.to_string()))?;
+        Ok(parse_program(&text)?)
+    }
+}
+
+pub fn load_program<P: AsRef<Path>>(path: P) -> Result<Vec<i64>, LoadError> {
+    read_program(File::open(path)?)
+}
+
+pub fn write_packed<W: Write>(program: &[i64], mut writer: W) -> io::Result<()> {
+    let mut data = PACKED_MAGIC.to_vec();
+    data.push(PACKED_VERSION);
+    write_varint(&mut data, program.len() as u64);
+    for word in program {
+        write_varint(&mut data, ((word << 1) ^ (word >> 63)) as u64);
+    }
+    writer.write_all(&data)
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn unpack(data: &[u8]) -> Result<Vec<i64>, LoadError> {
+    let mut bytes = data.iter();
+    match bytes.next() {
+        Some(&PACKED_VERSION) => {}
+        Some(version) => return Err(LoadError::Format(format!("unsupported version {}", version))),
+        None => return Err(LoadError::Format("missing version".to_string())),
+    }
+    let len = read_varint(&mut bytes)?;
+    let program = (0..len)
+        .map(|_| read_varint(&mut bytes).map(|zigzag| (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64)))
+        .collect::<Result<Vec<i64>, LoadError>>()?;
+    if bytes.next().is_some() {
+        return Err(LoadError::Format("trailing data after last word".to_string()));
+    }
+    Ok(program)
+}
+
+fn read_varint<'a, I: Iterator<Item = &'a u8>>(bytes: &mut I) -> Result<u64, LoadError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.next().ok_or_else(|| LoadError::Format("truncated varint".to_string()))?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(LoadError::Format("varint too long".to_string()))
+}
+
+impl IntCodeCpu {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<IntCodeCpu, LoadError> {
+        load_program(path).map(IntCodeCpu::from_program)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<IntCodeCpu, LoadError> {
+        read_program(reader).map(IntCodeCpu::from_program)
+    }
+}
+
+#[test]
+fn test_parse_program() {
+    assert_eq!(parse_program("1,2,3"), Ok(vec![1, 2, 3]));
+    assert_eq!(parse_program(" 1 ,\n-2,\r\n3,\n"), Ok(vec![1, -2, 3]));
+    assert_eq!(parse_program("# header\n1,9,10,3, # add\n2,3,11,0, # mul\n99\n"), Ok(vec![1, 9, 10, 3, 2, 3, 11, 0, 99]));
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(parse_program("1,2,\n3,x4"), Err(ParseError {
+        line: 2,
+        column: 3,
+        message: "bad value \"x4\": invalid digit found in string".to_string(),
+    }));
+    assert_eq!(parse_program("1,,2").unwrap_err().column, 3);
+    assert_eq!(parse_program("1 2").unwrap_err().message, "expected ',' before '2'");
+    assert_eq!(parse_program("1\n2").unwrap_err().line, 2);
+    assert_eq!(parse_program("# nothing\n").unwrap_err().message, "empty program");
+}
+
+#[test]
+fn test_packed_roundtrip() {
+    let program = vec![0, 1, -1, 99, 109, i64::MAX, i64::MIN, 34_915_192 * 34_915_192];
+    let mut packed = vec![];
+    write_packed(&program, &mut packed).unwrap();
+    assert_eq!(&packed[..5], b"ICPK\x01");
+    assert_eq!(read_program(&packed[..]).unwrap(), program);
+    assert!(matches!(read_program(&packed[..packed.len() - 1]), Err(LoadError::Format(_))));
+    assert_eq!(IntCodeCpu::from_reader(&b"1,0,0,0,99\n"[..]).unwrap().memory, vec![1, 0, 0, 0, 99]);
+}
+
+#[test]
+fn test_load_puzzle_inputs() {
+    let cpu = IntCodeCpu::from_file("./input/day9.txt").unwrap();
+    let mut packed = vec![];
+    write_packed(&cpu.memory, &mut packed).unwrap();
+    assert!(IntCodeCpu::from_reader(&packed[..]).unwrap() == cpu);
+    assert!(matches!(IntCodeCpu::from_file("./input/missing.txt"), Err(LoadError::Io(_))));
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::ops::Deref;
+use super::IntCodeCpu;
+use super::symbols::SymbolMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub address: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+// Run of changed cells at consecutive addresses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangedRange {
+    pub start: usize,
+    pub old: Vec<i64>,
+    pub new: Vec<i64>,
+}
+
+// Cells that differ between two memory snapshots, ordered by address. Cells past the end of
+// the shorter snapshot read as zero like they do for the CPU.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryDiff {
+    pub changes: Vec<Change>,
+}
+
+impl MemoryDiff {
+    pub fn between(before: &[i64], after: &[i64]) -> MemoryDiff {
+        let changes = (0..before.len().max(after.len()))
+            .map(|address| Change {
+                address,
+                old: before.get(address).copied().unwrap_or(0),
+                new: after.get(address).copied().unwrap_or(0),
+            })
+            .filter(|change| change.old != change.new)
+            .collect();
+        MemoryDiff { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn ranges(&self) -> Vec<ChangedRange> {
+        let mut ranges: Vec<ChangedRange> = vec![];
+        for change in &self.changes {
+            match ranges.last_mut() {
+                Some(range) if range.start + range.old.len() == change.address => {
+                    range.old.push(change.old);
+                    range.new.push(change.new);
+                }
+                _ => ranges.push(ChangedRange { start: change.address, old: vec![change.old], new: vec![change.new] }),
+            }
+        }
+        ranges
+    }
+
+    // One line per range of changed cells, labeled with the symbol covering its start.
+    pub fn render(&self, symbols: Option<&SymbolMap>) -> String {
+        let mut result = String::new();
+        for range in self.ranges() {
+            let end = range.start + range.old.len() - 1;
+            if end == range.start {
+                result.push_str(&range.start.to_string());
+            } else {
+                result.push_str(&format!("{}..={}", range.start, end));
+            }
+            if let Some(name) = symbols.and_then(|symbols| symbols.name_of(range.start)) {
+                result.push_str(&format!(" <{}>", name));
+            }
+            result.push_str(&format!(": {} -> {}\n", join(&range.old), join(&range.new)));
+        }
+        result
+    }
+}
+
+fn join(values: &[i64]) -> String {
+    values.iter().map(i64::to_string).collect::<Vec<String>>().join(", ")
+}
+
+impl fmt::Display for MemoryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(None))
+    }
+}
+
+impl IntCodeCpu {
+    // Memory cells that differ in `later`, usually a clone of this CPU after some action.
+    pub fn diff(&self, later: &IntCodeCpu) -> MemoryDiff {
+        MemoryDiff::between(&self.memory, &later.memory)
+    }
+}
+
+// Cell that changes exactly when a known value does. `offset` is the constant difference
+// between the cell and the value if there is one, `Some(0)` means the cell holds the value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lockstep {
+    pub address: usize,
+    pub offset: Option<i64>,
+}
+
+// Finds the cells that change between consecutive snapshots if and only if `known` changes,
+// e.g. snapshots taken after every frame of a game together with the ball position read
+// from the screen. There must be one known value per snapshot.
+pub fn find_lockstep<S: Deref<Target = [i64]>>(snapshots: &[S], known: &[i64]) -> Vec<Lockstep> {
+    assert_eq!(snapshots.len(), known.len(), "need one known value per snapshot");
+    let len = snapshots.iter().map(|snapshot| snapshot.len()).max().unwrap_or(0);
+    let cell = |snapshot: &S, address: usize| snapshot.get(address).copied().unwrap_or(0);
+    (0..len)
+        .filter(|address| snapshots.windows(2)
+            .zip(known.windows(2))
+            .all(|(snapshots, known)| (cell(&snapshots[0], *address) != cell(&snapshots[1], *address)) == (known[0] != known[1])))
+        .map(|address| {
+            let offsets = snapshots.iter().zip(known).map(|(snapshot, known)| cell(snapshot, address) - known);
+            let first = cell(&snapshots[0], address) - known[0];
+            Lockstep { address, offset: Some(first).filter(|first| offsets.clone().all(|offset| offset == *first)) }
+        })
+        .collect()
+}
+
+#[test]
+fn test_memory_diff() {
+    let diff = MemoryDiff::between(&[1, 2, 3, 4, 5, 6], &[1, 7, 8, 4, 9, 6, 0, 1]);
+    assert_eq!(diff.len(), 4);
+    assert_eq!(diff.changes[0], Change { address: 1, old: 2, new: 7 });
+    assert_eq!(diff.ranges(), vec![
+        ChangedRange { start: 1, old: vec![2, 3], new: vec![7, 8] },
+        ChangedRange { start: 4, old: vec![5], new: vec![9] },
+        ChangedRange { start: 7, old: vec![0], new: vec![1] },
+    ]);
+    let symbols: SymbolMap = "1 pair int 2".parse().unwrap();
+    assert_eq!(diff.render(Some(&symbols)), "1..=2 <pair>: 2, 3 -> 7, 8\n4: 5 -> 9\n7: 0 -> 1\n");
+    assert!(MemoryDiff::between(&[1, 0, 0], &[1]).is_empty());
+
+    // counts down [9] and accumulates into [10]
+    let mut cpu = IntCodeCpu::from_code("1,9,10,10,1001,9,-1,9,99,3,0");
+    let before = cpu.clone();
+    cpu.run();
+    assert_eq!(before.diff(&cpu).to_string(), "9..=10: 3, 0 -> 2, 3\n");
+}
+
+#[test]
+fn test_find_lockstep() {
+    let snapshots = vec![
+        vec![5, 0, 7, 1],
+        vec![6, 0, 8, 2],
+        vec![6, 1, 8, 2],
+        vec![9, 1, 8, 4],
+    ];
+    let found = find_lockstep(&snapshots, &[5, 6, 6, 9]);
+    assert_eq!(found, vec![Lockstep { address: 0, offset: Some(0) }, Lockstep { address: 3, offset: None }]);
+}
+
+#[test]
+fn test_find_lockstep_in_arcade_game() {
+    use super::framebuffer::Framebuffer;
+    let mut cpu = IntCodeCpu::from_file("./input/day13.txt").unwrap();
+    cpu.write_memory(0, 2);
+    let mut screen = Framebuffer::triples().with_register(-1, 0);
+    let mut snapshots = vec![];
+    let mut ball_x = vec![];
+    while snapshots.len() < 50 {
+        while !cpu.waiting_for_input() {
+            assert!(cpu.running, "game over after {} frames", snapshots.len());
+            cpu.step();
+        }
+        screen.extend(cpu.output.drain(..));
+        let ball = screen.find(4).unwrap();
+        let paddle = screen.find(3).unwrap();
+        snapshots.push(cpu.memory.clone());
+        ball_x.push(ball.0);
+        cpu.input.push_back((ball.0 - paddle.0).signum());
+    }
+    let found = find_lockstep(&snapshots, &ball_x);
+    assert!(found.iter().any(|cell| cell.offset == Some(0)), "ball position not found: {:?}", found);
+}
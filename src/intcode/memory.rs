@@ -0,0 +1,173 @@
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::ops::{Deref, Index, IndexMut};
+use std::slice::SliceIndex;
+
+// Intcode memory with a fingerprint that is updated on every write, so hashing a CPU state
+// is O(1). The fingerprint is the XOR of a hash of every non-zero cell, zero cells don't
+// contribute, so growing the memory doesn't change it. Reads go through `Deref<[i64]>`.
+// `write` keeps the fingerprint in sync, `memory[addr] = val` can't see the new value and
+// marks it stale instead, it is recomputed in full on the next `write`.
+#[derive(Clone, Debug, Default)]
+pub struct Memory {
+    cells: Vec<i64>,
+    fingerprint: u64,
+    stale: bool,
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn cell_hash(addr: usize, val: i64) -> u64 {
+    if val == 0 {
+        0
+    } else {
+        splitmix64(splitmix64(addr as u64) ^ val as u64)
+    }
+}
+
+fn full_fingerprint(cells: &[i64]) -> u64 {
+    cells.iter()
+        .enumerate()
+        .fold(0, |fingerprint, (addr, val)| fingerprint ^ cell_hash(addr, *val))
+}
+
+impl Memory {
+    pub fn new(cells: Vec<i64>) -> Memory {
+        let fingerprint = full_fingerprint(&cells);
+        Memory { cells, fingerprint, stale: false }
+    }
+
+    pub fn write(&mut self, addr: usize, val: i64) {
+        if self.stale {
+            self.fingerprint = full_fingerprint(&self.cells);
+            self.stale = false;
+        }
+        if addr >= self.cells.len() {
+            self.resize(addr + 1);
+        }
+        self.fingerprint ^= cell_hash(addr, self.cells[addr]) ^ cell_hash(addr, val);
+        self.cells[addr] = val;
+    }
+
+    // only grows, shrinking could drop non-zero cells without updating the fingerprint
+    pub fn resize(&mut self, len: usize) {
+        if len > self.cells.len() {
+            self.cells.resize(len, 0);
+        }
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        if self.stale {
+            full_fingerprint(&self.cells)
+        } else {
+            self.fingerprint
+        }
+    }
+
+    pub fn as_slice(&self) -> &[i64] {
+        &self.cells
+    }
+
+    pub fn into_vec(self) -> Vec<i64> {
+        self.cells
+    }
+
+    fn trimmed(&self) -> &[i64] {
+        let len = self.cells.iter().rposition(|val| *val != 0).map_or(0, |last| last + 1);
+        &self.cells[..len]
+    }
+}
+
+impl Deref for Memory {
+    type Target = [i64];
+
+    fn deref(&self) -> &[i64] {
+        &self.cells
+    }
+}
+
+impl<I: SliceIndex<[i64]>> Index<I> for Memory {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &I::Output {
+        &self.cells[index]
+    }
+}
+
+impl<I: SliceIndex<[i64]>> IndexMut<I> for Memory {
+    fn index_mut(&mut self, index: I) -> &mut I::Output {
+        self.stale = true;
+        &mut self.cells[index]
+    }
+}
+
+impl From<Vec<i64>> for Memory {
+    fn from(cells: Vec<i64>) -> Self {
+        Memory::new(cells)
+    }
+}
+
+impl FromIterator<i64> for Memory {
+    fn from_iter<I: IntoIterator<Item = i64>>(iter: I) -> Self {
+        Memory::new(iter.into_iter().collect())
+    }
+}
+
+// Memories that only differ by trailing zeros are equal, the CPU grows memory on demand and
+// the missing cells read as zero.
+impl PartialEq for Memory {
+    fn eq(&self, other: &Self) -> bool {
+        self.fingerprint() == other.fingerprint() && self.trimmed() == other.trimmed()
+    }
+}
+
+impl Eq for Memory {}
+
+impl Hash for Memory {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fingerprint().hash(state);
+    }
+}
+
+impl PartialEq<Vec<i64>> for Memory {
+    fn eq(&self, other: &Vec<i64>) -> bool {
+        &self.cells == other
+    }
+}
+
+#[test]
+fn test_fingerprint_is_incremental() {
+    let mut memory = Memory::new(vec![1, 2, 3]);
+    let original = memory.fingerprint();
+    memory.write(1, 5);
+    assert_ne!(memory.fingerprint(), original);
+    memory.write(1, 2);
+    assert_eq!(memory.fingerprint(), original);
+    memory.write(10, 7);
+    assert_eq!(memory.fingerprint(), Memory::new(vec![1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 7]).fingerprint());
+}
+
+#[test]
+fn test_memory_equality_ignores_trailing_zeros() {
+    let mut memory = Memory::new(vec![1, 2, 3]);
+    memory.resize(100);
+    assert_eq!(memory, Memory::new(vec![1, 2, 3]));
+    assert_eq!(memory.fingerprint(), Memory::new(vec![1, 2, 3]).fingerprint());
+    assert_ne!(memory, Memory::new(vec![1, 2, 4]));
+    assert_ne!(Memory::new(vec![1, 2]), Memory::new(vec![2, 1]));
+}
+
+#[test]
+fn test_index_mut_keeps_fingerprint() {
+    let mut memory = Memory::new(vec![1, 2, 3]);
+    memory[1] = 5;
+    assert_eq!(memory.fingerprint(), Memory::new(vec![1, 5, 3]).fingerprint());
+    memory.write(2, 4);
+    assert_eq!(memory, Memory::new(vec![1, 5, 4]));
+    assert_eq!(memory.fingerprint(), Memory::new(vec![1, 5, 4]).fingerprint());
+}
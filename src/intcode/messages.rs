@@ -0,0 +1,195 @@
+use std::fmt;
+use std::marker::PhantomData;
+use super::IntCodeCpu;
+
+// A value made of a fixed number of consecutive outputs, like day 23's `dst, x, y` packets.
+pub trait FromOutputs: Sized {
+    const LEN: usize;
+
+    // `values` holds exactly `LEN` outputs
+    fn from_outputs(values: &[i64]) -> Self;
+}
+
+impl FromOutputs for i64 {
+    const LEN: usize = 1;
+
+    fn from_outputs(values: &[i64]) -> Self {
+        values[0]
+    }
+}
+
+impl FromOutputs for (i64, i64) {
+    const LEN: usize = 2;
+
+    fn from_outputs(values: &[i64]) -> Self {
+        (values[0], values[1])
+    }
+}
+
+impl FromOutputs for (i64, i64, i64) {
+    const LEN: usize = 3;
+
+    fn from_outputs(values: &[i64]) -> Self {
+        (values[0], values[1], values[2])
+    }
+}
+
+impl<const N: usize> FromOutputs for [i64; N] {
+    const LEN: usize = N;
+
+    fn from_outputs(values: &[i64]) -> Self {
+        let mut result = [0; N];
+        result.copy_from_slice(values);
+        result
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interruption {
+    Halted,
+    // the values received so far are back in the output queue, so the message can be read
+    // again once there is input
+    WaitingForInput,
+}
+
+// The program stopped before it output a whole message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncompleteMessage {
+    pub expected: usize,
+    pub received: Vec<i64>,
+    pub reason: Interruption,
+}
+
+impl fmt::Display for IncompleteMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stopped = match self.reason {
+            Interruption::Halted => "program halted",
+            Interruption::WaitingForInput => "program is waiting for input",
+        };
+        if self.received.is_empty() {
+            return write!(f, "{} before a message of {} values", stopped, self.expected);
+        }
+        let received = self.received.iter().map(i64::to_string).collect::<Vec<String>>().join(", ");
+        write!(f, "{} after {} of {} values of a message: {}", stopped, self.received.len(), self.expected, received)
+    }
+}
+
+impl std::error::Error for IncompleteMessage {}
+
+// Messages until the program halts or waits for input, see `IntCodeCpu::messages`.
+pub struct Messages<'a, T> {
+    cpu: &'a mut IntCodeCpu,
+    done: bool,
+    message: PhantomData<T>,
+}
+
+impl<T: FromOutputs> Iterator for Messages<'_, T> {
+    type Item = Result<T, IncompleteMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.cpu.next_message() {
+            Ok(message) => Some(Ok(message)),
+            Err(error) => {
+                self.done = true;
+                if error.received.is_empty() { None } else { Some(Err(error)) }
+            }
+        }
+    }
+}
+
+impl IntCodeCpu {
+    // Takes pending output first and runs the program for the rest of the message. Stops
+    // when the program halts or reads input that isn't there.
+    pub fn next_message<T: FromOutputs>(&mut self) -> Result<T, IncompleteMessage> {
+        assert!(T::LEN > 0, "messages need at least one value");
+        let mut values = Vec::with_capacity(T::LEN);
+        while values.len() < T::LEN {
+            if let Some(value) = self.output.pop_front() {
+                values.push(value);
+            } else if self.waiting_for_input() {
+                values.iter().rev().for_each(|value| self.output.push_front(*value));
+                return Err(IncompleteMessage { expected: T::LEN, received: values, reason: Interruption::WaitingForInput });
+            } else if self.running {
+                self.step();
+            } else {
+                return Err(IncompleteMessage { expected: T::LEN, received: values, reason: Interruption::Halted });
+            }
+        }
+        Ok(T::from_outputs(&values))
+    }
+
+    // Iterates over messages until the program halts or waits for input. Stopping in the
+    // middle of a message yields an error as the last item.
+    pub fn messages<T: FromOutputs>(&mut self) -> Messages<'_, T> {
+        assert!(T::LEN > 0, "messages need at least one value");
+        Messages { cpu: self, done: false, message: PhantomData }
+    }
+
+    pub fn outputs_chunked<const N: usize>(&mut self) -> Messages<'_, [i64; N]> {
+        self.messages()
+    }
+}
+
+#[test]
+fn test_next_message() {
+    // outputs 1 to 5
+    let mut cpu = IntCodeCpu::from_code("104,1,104,2,104,3,104,4,104,5,99");
+    assert_eq!(cpu.next_message::<(i64, i64)>(), Ok((1, 2)));
+    assert_eq!(cpu.next_message::<[i64; 2]>(), Ok([3, 4]));
+    let error = cpu.next_message::<(i64, i64, i64)>().unwrap_err();
+    assert_eq!(error, IncompleteMessage { expected: 3, received: vec![5], reason: Interruption::Halted });
+    assert_eq!(error.to_string(), "program halted after 1 of 3 values of a message: 5");
+    assert_eq!(cpu.next_message::<i64>().unwrap_err().to_string(), "program halted before a message of 1 values");
+}
+
+#[test]
+fn test_messages() {
+    #[derive(Debug, PartialEq, Eq)]
+    struct Packet {
+        dst: usize,
+        x: i64,
+        y: i64,
+    }
+
+    impl FromOutputs for Packet {
+        const LEN: usize = 3;
+
+        fn from_outputs(values: &[i64]) -> Self {
+            Packet { dst: values[0] as usize, x: values[1], y: values[2] }
+        }
+    }
+
+    let mut cpu = IntCodeCpu::from_code("104,1,104,2,104,3,104,4,104,5,104,6,99");
+    cpu.output.push_back(0);
+    let packets = cpu.messages::<Packet>().collect::<Vec<_>>();
+    assert_eq!(packets, vec![
+        Ok(Packet { dst: 0, x: 1, y: 2 }),
+        Ok(Packet { dst: 3, x: 4, y: 5 }),
+        Err(IncompleteMessage { expected: 3, received: vec![6], reason: Interruption::Halted }),
+    ]);
+    let mut cpu = IntCodeCpu::from_code("104,1,104,2,104,3,104,4,99");
+    assert_eq!(cpu.outputs_chunked::<2>().collect::<Result<Vec<_>, _>>(), Ok(vec![[1, 2], [3, 4]]));
+}
+
+#[test]
+fn test_messages_stop_waiting_for_input() {
+    // outputs 7, then polls [20] until it isn't -1 and outputs it
+    let mut cpu = IntCodeCpu::from_code("104,7,3,20,1008,20,-1,21,1005,21,2,4,20,99");
+    let error = cpu.next_message::<(i64, i64)>().unwrap_err();
+    assert_eq!(error.reason, Interruption::WaitingForInput);
+    assert_eq!(error.to_string(), "program is waiting for input after 1 of 2 values of a message: 7");
+    assert_eq!(cpu.messages::<(i64, i64)>().count(), 1);
+    cpu.input.push_back(5);
+    assert_eq!(cpu.next_message::<(i64, i64)>(), Ok((7, 5)));
+    assert_eq!(cpu.messages::<i64>().count(), 0);
+}
+
+#[test]
+#[should_panic(expected = "messages need at least one value")]
+fn test_empty_messages_are_rejected() {
+    let mut cpu = IntCodeCpu::from_code("99");
+    cpu.messages::<[i64; 0]>();
+}
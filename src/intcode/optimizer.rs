@@ -0,0 +1,669 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use super::ParameterMode;
+use super::cfg::{ControlFlowGraph, Terminator};
+use super::disasm::{DecodedInstruction, Operand};
+
+// Peephole optimizer that rewrites a program in place into one that produces the same output
+// in fewer steps. Instructions keep their addresses, removed instructions are skipped with a
+// jump over them.
+//
+// Rewrites are only applied to instructions whose cells provably are never read or written as
+// data. The proof takes every position mode operand as accessed and bounds rbp from below by
+// following the calls from the entry, every access relative to rbp may reach anything above
+// that bound. It relies on the control flow graph being complete: programs with computed
+// jumps other than returns, calls through pointers or rbp adjusted by computed amounts are
+// rejected, and called functions are assumed to return to their return address with rbp
+// restored. Memory mapped devices aren't taken into account.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptimizeError {
+    // a computed jump that isn't a return, or a call through a pointer
+    ComputedJump(usize),
+    InvalidInstruction(usize),
+    // rbp isn't known relative to the start of the function at this block
+    UnknownFrame(usize),
+    // the lower bound of rbp keeps decreasing through recursive calls
+    UnboundedStack,
+}
+
+impl fmt::Display for OptimizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptimizeError::ComputedJump(addr) => write!(f, "computed jump at {}", addr),
+            OptimizeError::InvalidInstruction(addr) => write!(f, "invalid instruction at {}", addr),
+            OptimizeError::UnknownFrame(addr) => write!(f, "unknown rbp offset in block {}", addr),
+            OptimizeError::UnboundedStack => write!(f, "rbp has no lower bound"),
+        }
+    }
+}
+
+impl std::error::Error for OptimizeError {}
+
+// Number of instructions changed by each kind of rewrite.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OptimizeStats {
+    // arithmetic and comparisons of immediates whose result replaced later reads of the cell
+    // it's stored to, which can leave a dead store or a jump that is never taken
+    pub folded: usize,
+    // identities like `add x, 0, x` and jumps that are never taken, skipped
+    pub simplified: usize,
+    // stores that are overwritten before they're read, skipped
+    pub dead_stores: usize,
+    // jumps retargeted past the unconditional jumps they land on
+    pub threaded: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Optimized {
+    pub program: Vec<i64>,
+    pub stats: OptimizeStats,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Removal {
+    Simplified,
+    DeadStore,
+}
+
+// What the program may touch as data.
+struct Accesses {
+    // cells accessed in position mode
+    cells: BTreeSet<usize>,
+    // lowest cell that may be accessed relative to rbp
+    stack_floor: i64,
+    // instructions that are executed
+    code: BTreeMap<usize, DecodedInstruction>,
+}
+
+impl Accesses {
+    fn is_untouched(&self, inst: &DecodedInstruction) -> bool {
+        (inst.address..inst.address + inst.size())
+            .all(|addr| (addr as i64) < self.stack_floor && !self.cells.contains(&addr))
+    }
+}
+
+// rbp at every instruction of a block and after it, relative to the start of its function
+fn offsets_in_block(cfg: &ControlFlowGraph, block: usize, start: i64) -> Result<(Vec<i64>, i64), OptimizeError> {
+    let mut offset = start;
+    let mut offsets = vec![];
+    for inst in &cfg.blocks[&block].instructions {
+        offsets.push(offset);
+        if inst.opcode == 9 {
+            if inst.operands[0].mode != ParameterMode::Immediate {
+                return Err(OptimizeError::UnknownFrame(block));
+            }
+            offset += inst.operands[0].value;
+        }
+    }
+    Ok((offsets, offset))
+}
+
+fn analyze(cfg: &ControlFlowGraph, entry: usize) -> Result<Accesses, OptimizeError> {
+    for (start, block) in &cfg.blocks {
+        match block.terminator {
+            Terminator::IndirectJump | Terminator::IndirectBranch { .. } => return Err(OptimizeError::ComputedJump(*start)),
+            Terminator::Invalid(addr) => return Err(OptimizeError::InvalidInstruction(addr)),
+            _ => {}
+        }
+    }
+    if let Some(call) = cfg.calls.values().find(|call| call.target.is_none()) {
+        return Err(OptimizeError::ComputedJump(call.address));
+    }
+    let mut offsets: BTreeMap<(usize, usize), Vec<i64>> = BTreeMap::new();
+    for function in cfg.functions.values() {
+        for (block, offset) in &function.frame_offsets {
+            let offset = offset.ok_or(OptimizeError::UnknownFrame(*block))?;
+            let (in_block, at_end) = offsets_in_block(cfg, *block, offset)?;
+            // returns must restore rbp
+            if cfg.blocks[block].terminator == Terminator::Return && at_end != 0 {
+                return Err(OptimizeError::UnknownFrame(*block));
+            }
+            offsets.insert((function.entry, *block), in_block);
+        }
+    }
+
+    // lowest rbp on entry of every function reached from `entry`
+    let mut lowest: BTreeMap<usize, i64> = BTreeMap::new();
+    lowest.insert(entry, 0);
+    let mut changed = true;
+    let mut rounds = 0;
+    while changed {
+        changed = false;
+        rounds += 1;
+        if rounds > cfg.functions.len() + 1 {
+            return Err(OptimizeError::UnboundedStack);
+        }
+        for function in cfg.functions.values() {
+            let base = match lowest.get(&function.entry) {
+                Some(base) => *base,
+                None => continue,
+            };
+            for block in &function.blocks {
+                for (inst, offset) in cfg.blocks[block].instructions.iter().zip(&offsets[&(function.entry, *block)]) {
+                    let target = match cfg.calls.get(&inst.address) {
+                        Some(call) => call.target.unwrap(),
+                        None => continue,
+                    };
+                    if lowest.get(&target).is_none_or(|lowest| base + offset < *lowest) {
+                        lowest.insert(target, base + offset);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut accesses = Accesses { cells: BTreeSet::new(), stack_floor: i64::MAX, code: BTreeMap::new() };
+    for function in cfg.functions.values() {
+        let base = match lowest.get(&function.entry) {
+            Some(base) => *base,
+            None => continue,
+        };
+        for block in &function.blocks {
+            for (inst, offset) in cfg.blocks[block].instructions.iter().zip(&offsets[&(function.entry, *block)]) {
+                for operand in &inst.operands {
+                    match operand.mode {
+                        ParameterMode::Position if operand.value >= 0 => { accesses.cells.insert(operand.value as usize); }
+                        ParameterMode::Position => {}
+                        ParameterMode::Relative => accesses.stack_floor = accesses.stack_floor.min(base + offset + operand.value),
+                        ParameterMode::Immediate => {}
+                    }
+                }
+                accesses.code.insert(inst.address, inst.clone());
+            }
+        }
+    }
+    Ok(accesses)
+}
+
+fn encode(opcode: i64, operands: &[Operand]) -> Vec<i64> {
+    let modes = operands.iter().enumerate().map(|(n, operand)| {
+        let mode = match operand.mode {
+            ParameterMode::Position => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+        };
+        mode * 10_i64.pow(n as u32 + 2)
+    }).sum::<i64>();
+    std::iter::once(opcode + modes).chain(operands.iter().map(|operand| operand.value)).collect()
+}
+
+fn immediate(value: i64) -> Operand {
+    Operand { mode: ParameterMode::Immediate, value }
+}
+
+// `add x, 0, x`, `mul x, 1, x` and jumps that never leave the straight line
+fn is_identity(inst: &DecodedInstruction) -> bool {
+    match (inst.opcode, &inst.operands[..]) {
+        (1, [a, b, dst]) => (*b == immediate(0) && a == dst) || (*a == immediate(0) && b == dst),
+        (2, [a, b, dst]) => (*b == immediate(1) && a == dst) || (*a == immediate(1) && b == dst),
+        (5, [cond, target]) | (6, [cond, target]) => {
+            let never = cond.mode == ParameterMode::Immediate && (cond.value != 0) != (inst.opcode == 5);
+            never || *target == immediate((inst.address + inst.size()) as i64)
+        }
+        _ => false,
+    }
+}
+
+// Whether `operand` refers to the cell `dst` after rbp moved by `moved`.
+fn same_cell(dst: &Operand, operand: &Operand, moved: i64) -> bool {
+    operand.mode == dst.mode && match dst.mode {
+        ParameterMode::Relative => operand.value + moved == dst.value,
+        _ => operand.value == dst.value,
+    }
+}
+
+// Like `same_cell`, but a cell in position mode may be any cell on the stack.
+fn may_alias(accesses: &Accesses, dst: &Operand, operand: &Operand, moved: i64) -> bool {
+    same_cell(dst, operand, moved) || match (dst.mode, operand.mode) {
+        (ParameterMode::Position, ParameterMode::Relative) => dst.value >= accesses.stack_floor,
+        (ParameterMode::Relative, ParameterMode::Position) => operand.value >= accesses.stack_floor,
+        _ => false,
+    }
+}
+
+// A store that is overwritten later in the same straight line of code without being read in
+// between. Cells relative to rbp are compared taking `arb`s in between into account.
+fn is_dead_store(accesses: &Accesses, following: &[DecodedInstruction], inst: &DecodedInstruction) -> bool {
+    if ![1, 2, 7, 8].contains(&inst.opcode) {
+        return false;
+    }
+    let dst = inst.operands[2];
+    // how far rbp moved since the store
+    let mut moved = 0;
+    for next in following {
+        if next.opcode == 5 || next.opcode == 6 || next.opcode == 99 || !accesses.is_untouched(next) {
+            return false;
+        }
+        if next.opcode == 9 {
+            moved += next.operands[0].value;
+            continue;
+        }
+        let sources = next.operands.iter().enumerate().filter(|(n, _)| Some(*n) != next.info.dst);
+        if sources.map(|(_, operand)| operand).any(|operand| may_alias(accesses, &dst, operand, moved)) {
+            return false;
+        }
+        if next.info.dst.is_some_and(|n| same_cell(&dst, &next.operands[n], moved)) {
+            return true;
+        }
+    }
+    false
+}
+
+// value stored by arithmetic or a comparison that doesn't depend on memory
+fn constant(inst: &DecodedInstruction) -> Option<i64> {
+    let (a, b) = match &inst.operands[..] {
+        [a, b, _] if a.mode == ParameterMode::Immediate && b.mode == ParameterMode::Immediate => (a.value, b.value),
+        [a, b, _] if inst.opcode == 2 && (*a == immediate(0) || *b == immediate(0)) => return Some(0),
+        _ => return None,
+    };
+    match inst.opcode {
+        1 => Some(a.wrapping_add(b)),
+        2 => Some(a.wrapping_mul(b)),
+        7 => Some((a < b) as i64),
+        8 => Some((a == b) as i64),
+        _ => None,
+    }
+}
+
+// Replaces reads of `dst` in the following instructions by `value` until something may write
+// to it or control leaves the straight line, the jump ending it still reads the value. Returns
+// the number of replaced operands.
+fn propagate(accesses: &Accesses, following: &mut [DecodedInstruction], dst: Operand, value: i64) -> usize {
+    let mut moved = 0;
+    let mut replaced = 0;
+    for next in following {
+        if !accesses.is_untouched(next) {
+            break;
+        }
+        let written = next.info.dst;
+        for (n, operand) in next.operands.iter_mut().enumerate() {
+            if Some(n) != written && same_cell(&dst, operand, moved) {
+                *operand = immediate(value);
+                replaced += 1;
+            }
+        }
+        if next.opcode == 9 {
+            moved += next.operands[0].value;
+        }
+        let overwritten = written.is_some_and(|n| may_alias(accesses, &dst, &next.operands[n], moved));
+        if overwritten || next.opcode == 5 || next.opcode == 6 || next.opcode == 99 {
+            break;
+        }
+    }
+    replaced
+}
+
+// Final destination of a jump to `target`, following unconditional jumps.
+fn thread(accesses: &Accesses, target: usize) -> usize {
+    let mut target = target;
+    let mut seen = BTreeSet::new();
+    while let Some(inst) = accesses.code.get(&target) {
+        let unconditional = (inst.opcode == 5 || inst.opcode == 6)
+            && inst.operands[0].mode == ParameterMode::Immediate
+            && (inst.operands[0].value != 0) == (inst.opcode == 5);
+        let next = inst.operands.get(1).filter(|next| next.mode == ParameterMode::Immediate && next.value >= 0);
+        match next {
+            Some(next) if unconditional && accesses.is_untouched(inst) && seen.insert(target) => target = next.value as usize,
+            _ => break,
+        }
+    }
+    target
+}
+
+// Optimizes the program starting at `entry`, see the top of this file for what is rewritten
+// and when. Fails for programs whose accesses can't be bounded.
+pub fn optimize(program: &[i64], entry: usize) -> Result<Optimized, OptimizeError> {
+    let cfg = ControlFlowGraph::build(program, entry);
+    let mut accesses = analyze(&cfg, entry)?;
+    let mut result = program.to_vec();
+    let mut stats = OptimizeStats::default();
+    let return_addresses = cfg.calls.values().map(|call| call.return_address).collect::<BTreeSet<usize>>();
+
+    // folds constants into the reads of the stored cell, the store may become dead and
+    // conditions constant
+    let mut blocks = cfg.blocks.iter()
+        .map(|(start, block)| (*start, block.instructions.clone()))
+        .collect::<BTreeMap<usize, Vec<DecodedInstruction>>>();
+    for instructions in blocks.values_mut() {
+        for i in 0..instructions.len() {
+            let (inst, following) = instructions[i..].split_first_mut().unwrap();
+            if !accesses.code.contains_key(&inst.address) || !accesses.is_untouched(inst) {
+                continue;
+            }
+            if let Some(value) = constant(inst) {
+                if propagate(&accesses, following, inst.operands[2], value) > 0 {
+                    stats.folded += 1;
+                }
+            }
+        }
+    }
+    for inst in blocks.values().flatten() {
+        if accesses.code.get(&inst.address).is_some_and(|original| original != inst) {
+            result[inst.address..inst.address + inst.size()].copy_from_slice(&encode(inst.opcode, &inst.operands));
+            accesses.code.insert(inst.address, inst.clone());
+        }
+    }
+
+    let mut removals: BTreeMap<usize, Removal> = BTreeMap::new();
+    for instructions in blocks.values() {
+        for (i, inst) in instructions.iter().enumerate() {
+            if !accesses.code.contains_key(&inst.address) || !accesses.is_untouched(inst) {
+                continue;
+            }
+            if is_identity(inst) {
+                removals.insert(inst.address, Removal::Simplified);
+            } else if is_dead_store(&accesses, &instructions[i + 1..], inst) {
+                removals.insert(inst.address, Removal::DeadStore);
+            } else if inst.opcode == 5 || inst.opcode == 6 {
+                let target = inst.operands[1];
+                if target.mode == ParameterMode::Immediate && target.value >= 0 {
+                    let threaded = thread(&accesses, target.value as usize);
+                    if threaded != target.value as usize {
+                        result[inst.address + 2] = threaded as i64;
+                        stats.threaded += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // replaces runs of removed instructions with a jump over them, if that saves steps
+    for instructions in blocks.values() {
+        let mut instructions = instructions.iter().peekable();
+        while let Some(first) = instructions.next() {
+            if !removals.contains_key(&first.address) {
+                continue;
+            }
+            let mut run = vec![first];
+            while let Some(next) = instructions.peek() {
+                if !removals.contains_key(&next.address) || return_addresses.contains(&next.address) {
+                    break;
+                }
+                run.push(instructions.next().unwrap());
+            }
+            let last = run[run.len() - 1];
+            let after = last.address + last.size();
+            let destination = thread(&accesses, after);
+            if after - first.address < 3 || (run.len() < 2 && destination == after) {
+                continue;
+            }
+            result[first.address..first.address + 3].copy_from_slice(&encode(5, &[immediate(1), immediate(destination as i64)]));
+            for inst in run {
+                match removals[&inst.address] {
+                    Removal::Simplified => stats.simplified += 1,
+                    Removal::DeadStore => stats.dead_stores += 1,
+                }
+            }
+        }
+    }
+    Ok(Optimized { program: result, stats })
+}
+
+// steps taken by the original and the optimized program
+#[cfg(test)]
+fn run_both(original: &[i64], optimized: &[i64], input: &[i64], max_steps: u64) -> (u64, u64) {
+    use super::IntCodeCpu;
+    let run = |program: &[i64]| {
+        let mut cpu = IntCodeCpu::from_program(program.to_vec());
+        cpu.input.extend(input);
+        while cpu.running && cpu.steps() < max_steps {
+            cpu.single_step();
+        }
+        (cpu.output.iter().copied().collect::<Vec<i64>>(), cpu.running, cpu.steps())
+    };
+    let (expected, running, steps) = run(original);
+    let (actual, optimized_running, optimized_steps) = run(optimized);
+    // the optimized program never takes more steps, within the same budget it gets at least as far
+    if running {
+        assert!(actual.starts_with(&expected), "input {:?}: {:?} doesn't start with {:?}", input, actual, expected);
+    } else {
+        assert_eq!((actual, optimized_running), (expected, false), "input {:?}", input);
+        assert!(optimized_steps <= steps);
+    }
+    (steps, optimized_steps)
+}
+
+#[test]
+fn test_optimize_rewrites() {
+    let program = vec![
+        3, 50,              // 0: in [50]
+        1102, 2, 3, 51,     // 2: mul 2, 3, [51]
+        1001, 50, 0, 50,    // 6: add [50], 0, [50]
+        1001, 50, 1, 52,    // 10: add [50], 1, [52]
+        1, 50, 51, 52,      // 14: add [50], [51], [52]
+        1106, 1, 0,         // 18: jz 1, 0
+        4, 52,              // 21: out [52]
+        1005, 50, 29,       // 23: jnz [50], 29
+        4, 51,              // 26: out [51]
+        99,                 // 28: halt
+        1105, 1, 33,        // 29: jnz 1, 33
+        99,                 // 32: halt
+        4, 50,              // 33: out [50]
+        99,                 // 35: halt
+    ];
+    let optimized = optimize(&program, 0).unwrap();
+    assert_eq!(optimized.stats, OptimizeStats { folded: 1, simplified: 1, dead_stores: 1, threaded: 1 });
+    assert_eq!(optimized.program, vec![
+        3, 50,
+        1102, 2, 3, 51,
+        // the identity and the dead store are skipped
+        1105, 1, 14, 50,
+        1001, 50, 1, 52,
+        // the product is folded into the read
+        1001, 50, 6, 52,
+        // a single removed instruction isn't worth a jump
+        1106, 1, 0,
+        4, 52,
+        1005, 50, 33,
+        4, 51,
+        99,
+        1105, 1, 33,
+        99,
+        4, 50,
+        99,
+    ]);
+    for input in -2..3 {
+        let (steps, optimized_steps) = run_both(&program, &optimized.program, &[input], 1000);
+        // the skipped instructions save a step, the threaded jump another one if it's taken
+        assert_eq!(optimized_steps, steps - if input == 0 { 1 } else { 2 });
+    }
+
+    // the same code printing a cell of the skipped instructions
+    let mut reads_code = program.clone();
+    reads_code[27] = 8;
+    let optimized = optimize(&reads_code, 0).unwrap();
+    assert_eq!(optimized.program[6..10], reads_code[6..10]);
+    assert_eq!(optimize(&[3, 8, 1001, 8, 10, 8, 105, 1, 0], 0), Err(OptimizeError::ComputedJump(0)));
+}
+
+#[test]
+fn test_optimize_folding() {
+    let program = vec![
+        3, 50,              // 0: in [50]
+        1102, 2, 3, 51,     // 2: mul 2, 3, [51]
+        101, 4, 51, 52,     // 6: add 4, [51], [52]
+        1007, 52, 20, 53,   // 10: lt [52], 20, [53]
+        1, 50, 51, 51,      // 14: add [50], [51], [51]
+        1, 50, 52, 52,      // 18: add [50], [52], [52]
+        4, 51,              // 22: out [51]
+        4, 52,              // 24: out [52]
+        1006, 53, 0,        // 26: jz [53], 0
+        99,                 // 29: halt
+    ];
+    let optimized = optimize(&program, 0).unwrap();
+    assert_eq!(optimized.stats, OptimizeStats { folded: 3, simplified: 0, dead_stores: 2, threaded: 0 });
+    assert_eq!(optimized.program, vec![
+        3, 50,
+        // both constants are only read through their folded values and overwritten
+        1105, 1, 10, 51,
+        1101, 4, 6, 52,
+        1107, 10, 20, 53,
+        1001, 50, 6, 51,
+        1001, 50, 10, 52,
+        4, 51,
+        4, 52,
+        1106, 1, 0,
+        99,
+    ]);
+    for input in -2..3 {
+        let (steps, optimized_steps) = run_both(&program, &optimized.program, &[input], 1000);
+        assert_eq!(optimized_steps, steps - 1);
+    }
+}
+
+#[test]
+fn test_optimize_compiled_programs_on_random_inputs() {
+    let sources = [
+        "fn main() {
+            var a = 1;
+            a = input();
+            var b = input();
+            while (a > 0) {
+                if (a > 5) {
+                    if (b == 1) { output(1); } else { output(2); }
+                } else {
+                    output(3);
+                }
+                a = a - 1;
+                b = a * 1 + 2 * 3;
+            }
+        }",
+        "fn fib(n) {
+            if (n < 2) { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        fn main() {
+            var i = input();
+            while (i > 0) {
+                if (i == 3 || i == 5) { output(fib(i)); } else { output(i * 2); }
+                i = i - 1;
+            }
+        }",
+        "fn gcd(a, b) {
+            if (a == 0 || b == 0) { return a + b; }
+            while (a != b) {
+                if (a > b) { a = a - b; } else { b = b - a; }
+            }
+            return a;
+        }
+        fn main() { output(gcd(input(), input())); }",
+    ];
+    let mut threaded = 0;
+    // xorshift, the seed is arbitrary
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    for source in sources.iter() {
+        let program = super::compiler::compile(source).unwrap();
+        let optimized = optimize(&program, 0).unwrap();
+        threaded += optimized.stats.threaded;
+        for _ in 0..50 {
+            let input = (0..2).map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state % 40) as i64
+            }).collect::<Vec<i64>>();
+            run_both(&program, &optimized.program, &input, 1_000_000);
+        }
+    }
+    assert!(threaded > 0);
+}
+
+// Loads a puzzle input, applies the patches its driver needs and optimizes the result.
+#[cfg(test)]
+fn optimized_input(day: u32, patches: &[(usize, i64)]) -> super::IntCodeCpu {
+    let mut program = super::loader::load_program(format!("./input/day{}.txt", day)).unwrap();
+    for (addr, value) in patches {
+        program[*addr] = *value;
+    }
+    super::IntCodeCpu::from_program(optimize(&program, 0).unwrap().program)
+}
+
+#[test]
+fn test_optimize_puzzle_inputs() {
+    use super::explorer::{Explorer, Outcome};
+    use super::framebuffer::Framebuffer;
+
+    // day 2, the noun and verb are patched into the program
+    for (noun, verb, result) in [(12, 2, 4_023_471), (80, 51, 19_690_720)] {
+        let mut cpu = optimized_input(2, &[(1, noun), (2, verb)]);
+        cpu.run();
+        assert_eq!(cpu.memory[0], result);
+    }
+
+    // day 13, counts the blocks and then plays the game with the joystick following the ball
+    let mut cpu = optimized_input(13, &[]);
+    cpu.run();
+    let mut screen = Framebuffer::triples();
+    screen.extend(cpu.output.drain(..));
+    assert_eq!(screen.count(2), 284);
+    let mut cpu = optimized_input(13, &[(0, 2)]);
+    let mut screen = Framebuffer::triples().with_register(-1, 0);
+    while cpu.running {
+        while cpu.running && !cpu.waiting_for_input() {
+            cpu.step();
+        }
+        screen.extend(cpu.output.drain(..));
+        if let (Some(ball), Some(paddle)) = (screen.find(4), screen.find(3)) {
+            cpu.input.push_back((ball.0 - paddle.0).signum());
+        }
+    }
+    assert_eq!(screen.register(-1, 0), Some(13581));
+
+    // day 15, maps the maze with the repair droid
+    let moves = vec![(1, (0, -1)), (2, (0, 1)), (3, (-1, 0)), (4, (1, 0))];
+    let explorer = Explorer::new(
+        moves,
+        |cpu: &mut super::IntCodeCpu, (command, _): &(i64, (i64, i64))| {
+            cpu.input.push_back(*command);
+            match cpu.run_until_out().unwrap() {
+                0 => Outcome::Blocked,
+                1 => Outcome::Open,
+                _ => Outcome::Goal,
+            }
+        },
+        |(x, y): &(i64, i64), (_, (dx, dy)): &(i64, (i64, i64))| (x + dx, y + dy),
+    );
+    let maze = explorer.explore(&optimized_input(15, &[]), (0, 0));
+    let (oxygen, oxygen_cpu) = &maze.goals[0];
+    assert_eq!(maze.distances[oxygen], 354);
+    assert_eq!(explorer.explore(oxygen_cpu, *oxygen).max_distance(), 370);
+
+    // day 17, finds the scaffold intersections and then walks the robot along the scaffold
+    let mut cpu = optimized_input(17, &[]);
+    cpu.run();
+    let mut screen = Framebuffer::ascii();
+    screen.extend(cpu.output.drain(..));
+    let is_scaffold = |x, y| screen.get(x, y) == i64::from(b'#');
+    let (min_x, min_y, max_x, max_y) = screen.bounds().unwrap();
+    let alignment = (min_y..=max_y)
+        .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+        .filter(|(x, y)| [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)].iter().all(|(dx, dy)| is_scaffold(x + dx, y + dy)))
+        .map(|(x, y)| x * y)
+        .sum::<i64>();
+    assert_eq!(alignment, 8928);
+    let mut cpu = optimized_input(17, &[(0, 2)]);
+    for line in ["A,B,A,B,A,C,B,C,A,C", "L,6,R,12,L,6", "R,12,L,10,L,4,L,6", "L,10,L,10,L,4,L,6", "n"] {
+        cpu.send_line(line);
+    }
+    assert_eq!(cpu.collect_screen().unwrap().answer, Some(880_360));
+
+    // The other inputs can't be analyzed as a whole:
+    let rejected = |day| optimize(&super::loader::load_program(format!("./input/day{}.txt", day)).unwrap(), 0);
+    // day 5 patches the instruction at 6 before running it, as loaded it's invalid
+    assert_eq!(rejected(5), Err(OptimizeError::InvalidInstruction(6)));
+    // days 7 and 23 jump through a table by patching the input into the operand of `jnz 1, [0]`
+    assert_eq!(rejected(7), Err(OptimizeError::ComputedJump(0)));
+    assert_eq!(rejected(23), Err(OptimizeError::ComputedJump(0)));
+    // the day 9 self test moves rbp by a value read from memory
+    assert_eq!(rejected(9), Err(OptimizeError::UnknownFrame(11)));
+    // day 11 has jumps on code cells that are never taken, like `jz [0], 78`, into the
+    // middle of an instruction
+    assert_eq!(rejected(11), Err(OptimizeError::InvalidInstruction(84)));
+    // days 19 and 21 call functions through pointers, day 25 jumps to an address from the
+    // stack that isn't a return address
+    assert_eq!(rejected(19), Err(OptimizeError::ComputedJump(192)));
+    assert_eq!(rejected(21), Err(OptimizeError::ComputedJump(2034)));
+    assert_eq!(rejected(25), Err(OptimizeError::ComputedJump(1159)));
+}
@@ -0,0 +1,101 @@
+use super::IntCodeCpu;
+
+// The instruction set as it grew over the puzzles. Day 2 only had add, mul and halt without
+// parameter modes, day 5 added I/O, jumps, comparisons and immediate mode, day 9 the relative
+// base. Extension opcodes are only available with the full instruction set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Profile {
+    Day2,
+    Day5,
+    Day9,
+}
+
+impl Profile {
+    pub const FULL: Profile = Profile::Day9;
+
+    // Whether an encoded instruction is part of this instruction set.
+    pub fn allows(self, inst: i64) -> bool {
+        let operand_count = match inst % 100 {
+            1 | 2 | 7 | 8 => 3,
+            3 | 4 | 9 => 1,
+            5 | 6 => 2,
+            99 => 0,
+            _ => return false,
+        };
+        let introduced = match inst % 100 {
+            1 | 2 | 99 => Profile::Day2,
+            9 => Profile::Day9,
+            _ => Profile::Day5,
+        };
+        let modes = inst / 100;
+        introduced <= self && match self {
+            Profile::Day2 => modes == 0,
+            Profile::Day5 => modes < 10_i64.pow(operand_count) && (0..operand_count).all(|n| modes / 10_i64.pow(n) % 10 <= 1),
+            // the CPU takes care of the modes
+            Profile::Day9 => true,
+        }
+    }
+}
+
+impl IntCodeCpu {
+    // Instructions outside the profile fault like unknown opcodes.
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = profile;
+    }
+
+    pub(super) fn check_profile(&self, inst: i64) {
+        if !self.profile.allows(inst) {
+            panic!("instruction {} at ip {} isn't part of the {:?} instruction set", inst, self.ip, self.profile);
+        }
+    }
+}
+
+#[test]
+fn test_profiles_allow() {
+    for inst in &[1, 2, 99] {
+        assert!(Profile::Day2.allows(*inst));
+    }
+    assert!(!Profile::Day2.allows(1101));
+    assert!(!Profile::Day2.allows(3));
+    assert!(Profile::Day5.allows(1101));
+    assert!(Profile::Day5.allows(1005));
+    assert!(!Profile::Day5.allows(204));
+    assert!(!Profile::Day5.allows(9));
+    assert!(Profile::Day9.allows(21101));
+    assert!(Profile::Day9.allows(109));
+    assert!(!Profile::Day9.allows(50));
+}
+
+#[test]
+fn test_puzzle_inputs_fit_their_profiles() {
+    let run = |day: usize, profile: Profile, input: i64| {
+        let mut cpu = IntCodeCpu::from_file(format!("./input/day{}.txt", day)).unwrap();
+        cpu.set_profile(profile);
+        if day == 2 {
+            cpu.write_memory(1, 12);
+            cpu.write_memory(2, 2);
+        }
+        cpu.input.push_back(input);
+        cpu.run();
+        cpu
+    };
+    assert_eq!(run(2, Profile::Day2, 0).memory[0], 4_023_471);
+    assert_eq!(run(5, Profile::Day5, 5).output, vec![3_419_022]);
+    assert_eq!(run(9, Profile::Day9, 1).output, vec![3_013_554_615]);
+}
+
+#[test]
+#[should_panic(expected = "instruction 1101 at ip 0 isn't part of the Day2 instruction set")]
+fn test_profile_faults() {
+    let mut cpu = IntCodeCpu::from_code("1101,1,2,0,99");
+    cpu.set_profile(Profile::Day2);
+    cpu.run();
+}
+
+#[test]
+#[should_panic(expected = "instruction 109 at ip 4 isn't part of the Day5 instruction set")]
+fn test_profile_rejects_relative_base() {
+    let mut cpu = IntCodeCpu::from_code("1101,1,2,0,109,3,99");
+    cpu.set_profile(Profile::Day5);
+    cpu.run();
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use super::IntCodeCpu;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoEvent {
+    Input { step: u64, value: i64 },
+    Output { step: u64, value: i64 },
+}
+
+impl IoEvent {
+    pub fn step(&self) -> u64 {
+        match self {
+            IoEvent::Input { step, .. } => *step,
+            IoEvent::Output { step, .. } => *step,
+        }
+    }
+}
+
+impl fmt::Display for IoEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoEvent::Input { step, value } => write!(f, "{} in {}", step, value),
+            IoEvent::Output { step, value } => write!(f, "{} out {}", step, value),
+        }
+    }
+}
+
+impl FromStr for IoEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 3 {
+            return Err(format!("expected \"<step> in|out <value>\", got \"{}\"", s));
+        }
+        let step = fields[0].parse::<u64>().map_err(|e| format!("bad step \"{}\": {}", fields[0], e))?;
+        let value = fields[2].parse::<i64>().map_err(|e| format!("bad value \"{}\": {}", fields[2], e))?;
+        match fields[1] {
+            "in" => Ok(IoEvent::Input { step, value }),
+            "out" => Ok(IoEvent::Output { step, value }),
+            other => Err(format!("bad direction \"{}\"", other)),
+        }
+    }
+}
+
+// Steps are counted from the moment the recording was started, so a recording can be
+// replayed on any CPU that is in the same state as the recorded one was at that point.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub events: Vec<IoEvent>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    Mismatch { index: usize, expected: IoEvent, actual: IoEvent },
+    Missing { index: usize, expected: IoEvent },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Mismatch { index, expected, actual } =>
+                write!(f, "event {}: expected \"{}\", got \"{}\"", index, expected, actual),
+            ReplayError::Missing { index, expected } =>
+                write!(f, "event {}: expected \"{}\", but the program stopped", index, expected),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl Recording {
+    pub fn inputs(&self) -> impl Iterator<Item = i64> + '_ {
+        self.events.iter().filter_map(|e| match e {
+            IoEvent::Input { value, .. } => Some(*value),
+            IoEvent::Output { .. } => None,
+        })
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = i64> + '_ {
+        self.events.iter().filter_map(|e| match e {
+            IoEvent::Input { .. } => None,
+            IoEvent::Output { value, .. } => Some(*value),
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Feeds the recorded inputs into the CPU and checks that it produces the same events
+    // at the same steps. Execution stops once every recorded event has been reproduced.
+    pub fn replay(&self, cpu: &mut IntCodeCpu) -> Result<(), ReplayError> {
+        let last_step = self.events.last().map_or(0, IoEvent::step);
+        cpu.input.clear();
+        cpu.input.extend(self.inputs());
+        cpu.start_recording();
+        let start = cpu.steps;
+        while cpu.running
+            && cpu.steps - start <= last_step
+            && cpu.recording().map_or(0, |r| r.events.len()) < self.events.len() {
+            cpu.step();
+        }
+        let actual = cpu.stop_recording().unwrap_or_default();
+        for (index, expected) in self.events.iter().enumerate() {
+            match actual.events.get(index) {
+                Some(actual) if actual == expected => {}
+                Some(actual) => return Err(ReplayError::Mismatch { index, expected: *expected, actual: *actual }),
+                None => return Err(ReplayError::Missing { index, expected: *expected }),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for event in &self.events {
+            writeln!(f, "{}", event)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Recording {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let events = s.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| line.parse().map_err(|e| format!("line {}: {}", i + 1, e)))
+            .collect::<Result<Vec<IoEvent>, String>>()?;
+        Ok(Recording { events })
+    }
+}
+
+// reads numbers until it sees 0 and outputs the running sum after each one
+#[cfg(test)]
+const SUM_PROGRAM: &str = "3,20,1,20,21,21,4,21,1005,20,0,99";
+
+#[test]
+fn test_record() {
+    let mut cpu = IntCodeCpu::from_code(SUM_PROGRAM);
+    cpu.input.extend(&[3, 4, 0]);
+    cpu.start_recording();
+    cpu.run();
+    let recording = cpu.stop_recording().unwrap();
+    assert_eq!(recording.events, vec![
+        IoEvent::Input { step: 0, value: 3 },
+        IoEvent::Output { step: 2, value: 3 },
+        IoEvent::Input { step: 4, value: 4 },
+        IoEvent::Output { step: 6, value: 7 },
+        IoEvent::Input { step: 8, value: 0 },
+        IoEvent::Output { step: 10, value: 7 },
+    ]);
+    assert_eq!(recording.inputs().collect::<Vec<i64>>(), vec![3, 4, 0]);
+    assert_eq!(recording.to_string().parse::<Recording>(), Ok(recording));
+}
+
+#[test]
+fn test_replay() {
+    let cpu = IntCodeCpu::from_code(SUM_PROGRAM);
+    let mut original = cpu.clone();
+    original.input.extend(&[5, 6]);
+    original.start_recording();
+    original.run_until_out();
+    original.run_until_out();
+    let recording = original.stop_recording().unwrap();
+    assert_eq!(recording.replay(&mut cpu.clone()), Ok(()));
+
+    let mut tampered = recording.clone();
+    tampered.events[3] = IoEvent::Output { step: 6, value: 12 };
+    assert_eq!(
+        tampered.replay(&mut cpu.clone()),
+        Err(ReplayError::Mismatch {
+            index: 3,
+            expected: IoEvent::Output { step: 6, value: 12 },
+            actual: IoEvent::Output { step: 6, value: 11 },
+        })
+    );
+
+    let mut halted = cpu.clone();
+    halted.memory[0] = 99;
+    assert_eq!(
+        recording.replay(&mut halted),
+        Err(ReplayError::Missing { index: 0, expected: IoEvent::Input { step: 0, value: 5 } })
+    );
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use super::{loader, IntCodeCpu};
+use super::fault::catch_fault;
+
+const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+// A test case for a program. Spec files contain one or more cases separated by lines with
+// `---`, every case is a list of `key: value` lines:
+//
+//   name: day 5 equal to 8       (optional, defaults to the file name and index)
+//   program: 3,9,8,9,10,9,4,9,99,-1,8
+//   program-file: ../input/day9.txt   (instead of program, relative to the spec file)
+//   input: 8
+//   output: 1
+//   memory: 0=3500, 3=70         (optional, expected memory cells)
+//   max-steps: 100               (optional, the program must halt within that many steps)
+//
+// '#' starts a comment and indented lines continue the previous value, lists of values
+// continue after a comma. A case without `output` expects no output at all.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Spec {
+    pub name: String,
+    pub program: Vec<i64>,
+    pub input: Vec<i64>,
+    pub output: Vec<i64>,
+    pub memory: Vec<(usize, i64)>,
+    pub max_steps: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpecFailure {
+    Timeout { steps: u64, output: Vec<i64> },
+    Output { expected: Vec<i64>, actual: Vec<i64> },
+    Memory { cells: Vec<(usize, i64, i64)> },
+    // the program faulted, e.g. with a bad opcode
+    Fault { message: String, output: Vec<i64> },
+}
+
+impl fmt::Display for SpecFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpecFailure::Timeout { steps, output } =>
+                write!(f, "didn't halt within {} steps, output so far: {}", steps, join(output)),
+            SpecFailure::Output { expected, actual } => {
+                writeln!(f, "output mismatch")?;
+                writeln!(f, "  expected: {}", join(expected))?;
+                writeln!(f, "  actual:   {}", join(actual))?;
+                let first = expected.iter().zip(actual).position(|(e, a)| e != a)
+                    .unwrap_or_else(|| expected.len().min(actual.len()));
+                write!(f, "  first difference at index {}", first)
+            }
+            SpecFailure::Memory { cells } => {
+                write!(f, "memory mismatch")?;
+                for (addr, expected, actual) in cells {
+                    write!(f, "\n  [{}] expected {}, actual {}", addr, expected, actual)?;
+                }
+                Ok(())
+            }
+            SpecFailure::Fault { message, output } =>
+                write!(f, "fault: {}, output so far: {}", message, join(output)),
+        }
+    }
+}
+
+fn join(values: &[i64]) -> String {
+    values.iter().map(i64::to_string).collect::<Vec<String>>().join(", ")
+}
+
+fn parse_values(value: &str) -> Result<Vec<i64>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<i64>().map_err(|e| format!("bad value \"{}\": {}", v, e)))
+        .collect()
+}
+
+fn parse_memory(value: &str) -> Result<Vec<(usize, i64)>, String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|cell| !cell.is_empty())
+        .map(|cell| {
+            let mut parts = cell.splitn(2, '=');
+            let addr = parts.next().unwrap().trim();
+            let value = parts.next().ok_or_else(|| format!("expected <address>=<value>, got \"{}\"", cell))?.trim();
+            Ok((addr.parse().map_err(|e| format!("bad address \"{}\": {}", addr, e))?,
+                value.parse().map_err(|e| format!("bad value \"{}\": {}", value, e))?))
+        })
+        .collect()
+}
+
+// a value continued over several lines, list items on different lines get a comma in between
+fn join_lines(value: &str, list: bool) -> String {
+    if !list {
+        return value.split('\n').collect::<Vec<&str>>().join(" ");
+    }
+    value.split('\n')
+        .map(|line| line.trim_matches(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+// Parses the cases of a spec file, `name` is used for cases without a name and program files
+// are relative to `dir`. Errors carry the line number.
+pub fn parse_specs(name: &str, text: &str, dir: &Path) -> Result<Vec<Spec>, String> {
+    // indented lines continue the value of the previous line
+    let mut lines: Vec<(usize, String)> = vec![];
+    for (line_idx, raw) in text.lines().enumerate() {
+        let line = raw.split('#').next().unwrap().trim();
+        match lines.last_mut() {
+            _ if line.is_empty() => {}
+            Some((_, previous)) if raw.starts_with(char::is_whitespace) => {
+                previous.push('\n');
+                previous.push_str(line);
+            }
+            _ => lines.push((line_idx + 1, line.to_string())),
+        }
+    }
+    let mut specs = vec![];
+    let mut spec: Option<Spec> = None;
+    for (line_no, line) in lines {
+        if line == "---" {
+            specs.extend(spec.take());
+            continue;
+        }
+        let error = |e: String| format!("line {}: {}", line_no, e);
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap().trim();
+        let value = parts.next().ok_or_else(|| error(format!("expected \"<key>: <value>\", got \"{}\"", line)))?.trim();
+        let value = join_lines(value, !matches!(key, "name" | "program-file"));
+        let value = value.as_str();
+        let spec = spec.get_or_insert_with(|| Spec {
+            name: format!("{} #{}", name, specs.len() + 1),
+            ..Spec::default()
+        });
+        match key {
+            "name" => spec.name = value.to_string(),
+            "program" => spec.program = loader::parse_program(value).map_err(|e| error(e.to_string()))?,
+            "program-file" => spec.program = loader::load_program(dir.join(value)).map_err(|e| error(e.to_string()))?,
+            "input" => spec.input = parse_values(value).map_err(error)?,
+            "output" => spec.output = parse_values(value).map_err(error)?,
+            "memory" => spec.memory = parse_memory(value).map_err(error)?,
+            "max-steps" => spec.max_steps = Some(value.parse().map_err(|e| error(format!("bad step count: {}", e)))?),
+            other => return Err(error(format!("unknown key \"{}\"", other))),
+        }
+    }
+    specs.extend(spec);
+    if let Some(spec) = specs.iter().find(|spec| spec.program.is_empty()) {
+        return Err(format!("{}: no program", spec.name));
+    }
+    Ok(specs)
+}
+
+impl Spec {
+    pub fn run(&self) -> Result<(), SpecFailure> {
+        let mut cpu = IntCodeCpu::from_program(self.program.clone());
+        cpu.input.extend(&self.input);
+        let max_steps = self.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+        let result = catch_fault(|| while cpu.running && cpu.steps < max_steps {
+            cpu.step();
+        });
+        let actual = cpu.output.iter().copied().collect::<Vec<i64>>();
+        if let Err(message) = result {
+            return Err(SpecFailure::Fault { message, output: actual });
+        }
+        if cpu.running {
+            return Err(SpecFailure::Timeout { steps: max_steps, output: actual });
+        }
+        if actual != self.output {
+            return Err(SpecFailure::Output { expected: self.output.clone(), actual });
+        }
+        let cells = self.memory.iter()
+            .map(|(addr, expected)| (*addr, *expected, cpu.memory.get(*addr).copied().unwrap_or(0)))
+            .filter(|(_, expected, actual)| expected != actual)
+            .collect::<Vec<_>>();
+        if !cells.is_empty() {
+            return Err(SpecFailure::Memory { cells });
+        }
+        Ok(())
+    }
+}
+
+// Runs every `*.spec` file in `dir`, returns a report of all failures or the number of cases
+// that passed.
+pub fn run_spec_dir<P: AsRef<Path>>(dir: P) -> io::Result<Result<usize, String>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "spec"));
+    paths.sort();
+    let mut passed = 0;
+    let mut report = String::new();
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let specs = match parse_specs(&name, &fs::read_to_string(&path)?, dir) {
+            Ok(specs) => specs,
+            Err(e) => {
+                report.push_str(&format!("{}: {}\n", path.display(), e));
+                continue;
+            }
+        };
+        for spec in specs {
+            match spec.run() {
+                Ok(()) => passed += 1,
+                Err(failure) => report.push_str(&format!("{}: {}\n", spec.name, failure)),
+            }
+        }
+    }
+    Ok(if report.is_empty() { Ok(passed) } else { Err(report) })
+}
+
+#[test]
+fn test_spec_files() {
+    match run_spec_dir("./specs").unwrap() {
+        Ok(passed) => assert!(passed > 0, "no spec cases found"),
+        Err(report) => panic!("failing specs:\n{}", report),
+    }
+}
+
+#[test]
+fn test_spec_failures() {
+    let dir = Path::new(".");
+    let specs = parse_specs("inline", "\
+program: 3,0,4,0,99
+input: 5
+output: 6
+---
+name: memory
+program: 1,0,0,0,99
+memory: 0=2, 4=98
+---
+program: 1105,1,0
+max-steps: 10
+---
+program: 104,1,1101,1,1,-1,99
+", dir).unwrap();
+    assert_eq!(specs[0].name, "inline #1");
+    assert_eq!(specs[0].run().unwrap_err().to_string(), "\
+output mismatch
+  expected: 6
+  actual:   5
+  first difference at index 0");
+    assert_eq!(specs[1].run().unwrap_err().to_string(), "memory mismatch\n  [4] expected 98, actual 99");
+    assert_eq!(specs[2].run(), Err(SpecFailure::Timeout { steps: 10, output: vec![] }));
+    assert_eq!(specs[3].run().unwrap_err().to_string(), "fault: negative address -1 at ip 2 (rbp=0), output so far: 1");
+    assert_eq!(parse_specs("bad", "program: 99\nouput: 1", dir).unwrap_err(), "line 2: unknown key \"ouput\"");
+    assert_eq!(parse_specs("bad", "input: 1", dir).unwrap_err(), "bad #1: no program");
+}
+
+#[test]
+fn test_spec_continuation_lines() {
+    let specs = parse_specs("inline", "\
+name: outputs
+  three values
+program: 104,1,104,2,
+  104,3
+  ,99
+output: 1, 2
+  3
+---
+program-file: day9.txt
+", Path::new("./input")).unwrap();
+    assert_eq!(specs[0].name, "outputs three values");
+    assert_eq!(specs[0].program, vec![104, 1, 104, 2, 104, 3, 99]);
+    assert_eq!(specs[0].output, vec![1, 2, 3]);
+    assert_eq!(specs[1].program[..2], [1102, 34_463_338]);
+}
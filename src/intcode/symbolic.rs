@@ -0,0 +1,579 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::ops::RangeInclusive;
+use super::{IntCodeCpu, ParameterMode};
+
+// Sum of monomials, a monomial is the sorted list of its symbols (repeated for powers).
+// Arithmetic wraps like two's complement, zero coefficients are never stored.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Polynomial {
+    terms: BTreeMap<Vec<String>, i64>,
+}
+
+impl Polynomial {
+    pub fn constant(value: i64) -> Polynomial {
+        let mut terms = BTreeMap::new();
+        if value != 0 {
+            terms.insert(vec![], value);
+        }
+        Polynomial { terms }
+    }
+
+    pub fn symbol(name: &str) -> Polynomial {
+        let mut terms = BTreeMap::new();
+        terms.insert(vec![name.to_string()], 1);
+        Polynomial { terms }
+    }
+
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.len() {
+            0 => Some(0),
+            1 => self.terms.get(&vec![]).copied(),
+            _ => None,
+        }
+    }
+
+    pub fn symbols(&self) -> BTreeSet<String> {
+        self.terms.keys().flatten().cloned().collect()
+    }
+
+    fn add_term(&mut self, monomial: Vec<String>, coefficient: i64) {
+        let entry = self.terms.entry(monomial).or_insert(0);
+        *entry = entry.wrapping_add(coefficient);
+        self.terms.retain(|_, coefficient| *coefficient != 0);
+    }
+
+    pub fn add(&self, other: &Polynomial) -> Polynomial {
+        let mut result = self.clone();
+        for (monomial, coefficient) in &other.terms {
+            result.add_term(monomial.clone(), *coefficient);
+        }
+        result
+    }
+
+    pub fn mul(&self, other: &Polynomial) -> Polynomial {
+        let mut result = Polynomial::default();
+        for (m1, c1) in &self.terms {
+            for (m2, c2) in &other.terms {
+                let mut monomial = m1.iter().chain(m2.iter()).cloned().collect::<Vec<String>>();
+                monomial.sort();
+                result.add_term(monomial, c1.wrapping_mul(*c2));
+            }
+        }
+        result
+    }
+
+    pub fn eval(&self, env: &HashMap<String, i64>) -> Option<i64> {
+        self.terms.iter().try_fold(0i64, |sum, (monomial, coefficient)| {
+            let product = monomial.iter()
+                .try_fold(*coefficient, |product, symbol| env.get(symbol).map(|v| product.wrapping_mul(*v)))?;
+            Some(sum.wrapping_add(product))
+        })
+    }
+
+    // If every monomial contains `symbol` at most once, splits the polynomial into
+    // `coefficient * symbol + rest`.
+    fn split_linear(&self, symbol: &str) -> Option<(Polynomial, Polynomial)> {
+        let mut coefficient = Polynomial::default();
+        let mut rest = Polynomial::default();
+        for (monomial, c) in &self.terms {
+            match monomial.iter().filter(|s| *s == symbol).count() {
+                0 => rest.add_term(monomial.clone(), *c),
+                1 => coefficient.add_term(monomial.iter().filter(|s| *s != symbol).cloned().collect(), *c),
+                _ => return None,
+            }
+        }
+        Some((coefficient, rest))
+    }
+}
+
+impl fmt::Display for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+        // highest degree first
+        for (i, (monomial, coefficient)) in self.terms.iter().rev().enumerate() {
+            let sign = if *coefficient < 0 { "-" } else { "+" };
+            if i > 0 {
+                write!(f, " {} ", sign)?;
+            } else if *coefficient < 0 {
+                write!(f, "-")?;
+            }
+            let abs = coefficient.unsigned_abs();
+            if monomial.is_empty() {
+                write!(f, "{}", abs)?;
+            } else {
+                if abs != 1 {
+                    write!(f, "{}*", abs)?;
+                }
+                write!(f, "{}", monomial.join("*"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Poly(Polynomial),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    LessThan(Box<Expr>, Box<Expr>),
+    Equals(Box<Expr>, Box<Expr>),
+    // value read from a symbolic address, it's opaque to the solver
+    Load(Box<Expr>),
+}
+
+impl Expr {
+    pub fn constant(value: i64) -> Expr {
+        Expr::Poly(Polynomial::constant(value))
+    }
+
+    pub fn symbol(name: &str) -> Expr {
+        Expr::Poly(Polynomial::symbol(name))
+    }
+
+    pub fn as_constant(&self) -> Option<i64> {
+        match self {
+            Expr::Poly(p) => p.as_constant(),
+            _ => None,
+        }
+    }
+
+    pub fn sum(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Poly(a), Expr::Poly(b)) => Expr::Poly(a.add(&b)),
+            (a, b) => Expr::Add(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn product(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Poly(a), Expr::Poly(b)) => Expr::Poly(a.mul(&b)),
+            (a, b) if a.as_constant() == Some(0) || b.as_constant() == Some(0) => Expr::constant(0),
+            (a, b) => Expr::Mul(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn less_than(a: Expr, b: Expr) -> Expr {
+        match (a.as_constant(), b.as_constant()) {
+            (Some(a), Some(b)) => Expr::constant(if a < b { 1 } else { 0 }),
+            _ if a == b => Expr::constant(0),
+            _ => Expr::LessThan(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn equals(a: Expr, b: Expr) -> Expr {
+        match (a.as_constant(), b.as_constant()) {
+            (Some(a), Some(b)) => Expr::constant(if a == b { 1 } else { 0 }),
+            _ if a == b => Expr::constant(1),
+            _ => Expr::Equals(Box::new(a), Box::new(b)),
+        }
+    }
+
+    pub fn eval(&self, env: &HashMap<String, i64>) -> Option<i64> {
+        Some(match self {
+            Expr::Poly(p) => p.eval(env)?,
+            Expr::Add(a, b) => a.eval(env)?.wrapping_add(b.eval(env)?),
+            Expr::Mul(a, b) => a.eval(env)?.wrapping_mul(b.eval(env)?),
+            Expr::LessThan(a, b) => if a.eval(env)? < b.eval(env)? { 1 } else { 0 },
+            Expr::Equals(a, b) => if a.eval(env)? == b.eval(env)? { 1 } else { 0 },
+            Expr::Load(_) => return None,
+        })
+    }
+
+    pub fn symbols(&self) -> BTreeSet<String> {
+        match self {
+            Expr::Poly(p) => p.symbols(),
+            Expr::Add(a, b) | Expr::Mul(a, b) | Expr::LessThan(a, b) | Expr::Equals(a, b) =>
+                a.symbols().union(&b.symbols()).cloned().collect(),
+            Expr::Load(addr) => addr.symbols(),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Poly(p) => write!(f, "{}", p),
+            Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+            Expr::Mul(a, b) => write!(f, "({} * {})", a, b),
+            Expr::LessThan(a, b) => write!(f, "({} < {})", a, b),
+            Expr::Equals(a, b) => write!(f, "({} == {})", a, b),
+            Expr::Load(addr) => write!(f, "[{}]", addr),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathEnd {
+    Halted,
+    Fault(String),
+    // the program did something the executor can't follow, e.g. storing to a symbolic address
+    Unsupported(String),
+    StepLimit,
+    PathLimit,
+}
+
+// A path through the program: concrete ip and rbp, memory and I/O as expressions over the
+// symbols and the branch decisions taken so far as (condition, is non-zero) pairs.
+#[derive(Clone, Debug)]
+pub struct SymbolicCpu {
+    ip: usize,
+    rbp: i64,
+    steps: u64,
+    next_input: usize,
+    pub memory: Vec<Expr>,
+    pub input: VecDeque<Expr>,
+    pub output: Vec<Expr>,
+    pub constraints: Vec<(Expr, bool)>,
+    pub end: Option<PathEnd>,
+}
+
+impl SymbolicCpu {
+    pub fn from_cpu(cpu: &IntCodeCpu) -> SymbolicCpu {
+        SymbolicCpu {
+            ip: cpu.ip,
+            rbp: cpu.rbp,
+            steps: 0,
+            next_input: 0,
+            memory: cpu.memory.iter().map(|value| Expr::constant(*value)).collect(),
+            input: cpu.input.iter().map(|value| Expr::constant(*value)).collect(),
+            output: vec![],
+            constraints: vec![],
+            end: if cpu.running { None } else { Some(PathEnd::Halted) },
+        }
+    }
+
+    pub fn make_symbolic(&mut self, addr: usize, name: &str) {
+        if addr >= self.memory.len() {
+            self.memory.resize(addr + 1, Expr::constant(0));
+        }
+        self.memory[addr] = Expr::symbol(name);
+    }
+
+    // Runs every path to its end, forking on jumps with symbolic conditions. Reads from an
+    // empty input queue produce fresh symbols named input0, input1, ...
+    pub fn explore(self, max_steps: u64, max_paths: usize) -> Vec<SymbolicCpu> {
+        let mut done = vec![];
+        let mut todo = vec![self];
+        while let Some(mut state) = todo.pop() {
+            while state.end.is_none() {
+                if state.steps >= max_steps {
+                    state.end = Some(PathEnd::StepLimit);
+                } else if let Some(mut fork) = state.step() {
+                    if done.len() + todo.len() + 2 > max_paths {
+                        fork.end = Some(PathEnd::PathLimit);
+                        done.push(fork);
+                    } else {
+                        todo.push(fork);
+                    }
+                }
+            }
+            done.push(state);
+        }
+        done
+    }
+
+    // Finds all assignments within `domains` for which `expr` evaluates to `target` on this
+    // path. If one of the symbols appears linearly it's solved for instead of enumerated.
+    pub fn solve(&self, expr: &Expr, target: i64, domains: &[(&str, RangeInclusive<i64>)])
+                 -> Result<Vec<HashMap<String, i64>>, String> {
+        let poly = match expr {
+            Expr::Poly(p) => p.add(&Polynomial::constant(target.wrapping_neg())),
+            _ => return Err(format!("{} is not a polynomial", expr)),
+        };
+        let mut symbols = poly.symbols();
+        for (condition, _) in &self.constraints {
+            symbols.extend(condition.symbols());
+        }
+        if let Some(missing) = symbols.iter().find(|s| !domains.iter().any(|(name, _)| name == s)) {
+            return Err(format!("no domain for symbol {}", missing));
+        }
+        let pivot = domains.iter()
+            .filter(|(name, _)| poly.symbols().contains(*name))
+            .find_map(|(name, range)| poly.split_linear(name).map(|split| (*name, range.clone(), split)));
+        let enumerated = domains.iter()
+            .filter(|(name, _)| pivot.as_ref().is_none_or(|(pivot, _, _)| pivot != name))
+            .collect::<Vec<_>>();
+
+        let mut solutions = vec![];
+        let mut env = HashMap::new();
+        let mut indices = vec![0; enumerated.len()];
+        loop {
+            for ((name, range), i) in enumerated.iter().zip(&indices) {
+                env.insert(name.to_string(), range.start() + i);
+            }
+            let candidates = match &pivot {
+                None => vec![None],
+                Some((name, range, (coefficient, rest))) => {
+                    let coefficient = coefficient.eval(&env).ok_or("unbound symbol")?;
+                    let rest = rest.eval(&env).ok_or("unbound symbol")?;
+                    if coefficient == 0 {
+                        if rest == 0 { range.clone().map(|v| Some((*name, v))).collect() } else { vec![] }
+                    } else {
+                        // coefficient * value + rest == 0, without overflowing on i64::MIN
+                        let value = rest.checked_rem(coefficient)
+                            .filter(|remainder| *remainder == 0)
+                            .and_then(|_| rest.checked_div(coefficient))
+                            .and_then(i64::checked_neg)
+                            .filter(|value| range.contains(value));
+                        value.map(|value| Some((*name, value))).into_iter().collect()
+                    }
+                }
+            };
+            for candidate in candidates {
+                if let Some((name, value)) = candidate {
+                    env.insert(name.to_string(), value);
+                }
+                if poly.eval(&env) == Some(0) && self.satisfies(&env)? {
+                    solutions.push(env.clone());
+                }
+            }
+            // advance the odometer over the enumerated domains
+            let mut digit = 0;
+            loop {
+                if digit == indices.len() {
+                    return Ok(solutions);
+                }
+                let (_, range) = enumerated[digit];
+                indices[digit] += 1;
+                if range.start().checked_add(indices[digit]).is_some_and(|value| value <= *range.end()) {
+                    break;
+                }
+                indices[digit] = 0;
+                digit += 1;
+            }
+        }
+    }
+
+    fn satisfies(&self, env: &HashMap<String, i64>) -> Result<bool, String> {
+        for (condition, non_zero) in &self.constraints {
+            match condition.eval(env) {
+                Some(value) => if (value != 0) != *non_zero {
+                    return Ok(false);
+                },
+                None => return Err(format!("can't evaluate path condition {}", condition)),
+            }
+        }
+        Ok(true)
+    }
+
+    fn read(&self, addr: i64) -> Expr {
+        if addr < 0 {
+            return Expr::constant(0);
+        }
+        self.memory.get(addr as usize).cloned().unwrap_or_else(|| Expr::constant(0))
+    }
+
+    fn operand(&self, n: usize) -> Expr {
+        let mode = ParameterMode::of_operand(self.read(self.ip as i64).as_constant().unwrap_or(0), n as u32);
+        let word = self.read((self.ip + 1 + n) as i64);
+        let addr = match mode {
+            ParameterMode::Immediate => return word,
+            ParameterMode::Position => word,
+            ParameterMode::Relative => Expr::sum(Expr::constant(self.rbp), word),
+        };
+        match addr.as_constant() {
+            Some(addr) => self.read(addr),
+            None => Expr::Load(Box::new(addr)),
+        }
+    }
+
+    fn dst(&self, n: usize) -> Result<usize, PathEnd> {
+        let inst = self.read(self.ip as i64).as_constant().unwrap_or(0);
+        let word = self.read((self.ip + 1 + n) as i64);
+        let addr = match ParameterMode::of_operand(inst, n as u32) {
+            ParameterMode::Immediate => return Err(PathEnd::Fault("dst operand cannot use immediate mode".to_string())),
+            ParameterMode::Position => word,
+            ParameterMode::Relative => Expr::sum(Expr::constant(self.rbp), word),
+        };
+        match addr.as_constant() {
+            Some(addr) if addr >= 0 => Ok(addr as usize),
+            Some(addr) => Err(PathEnd::Fault(format!("store to negative address {}", addr))),
+            None => Err(PathEnd::Unsupported(format!("store to symbolic address {}", addr))),
+        }
+    }
+
+    fn store(&mut self, n: usize, value: Expr) -> Result<(), PathEnd> {
+        let addr = self.dst(n)?;
+        if addr >= self.memory.len() {
+            self.memory.resize(addr + 1, Expr::constant(0));
+        }
+        self.memory[addr] = value;
+        Ok(())
+    }
+
+    fn jump_target(&self) -> Result<usize, PathEnd> {
+        match self.operand(1).as_constant() {
+            Some(target) if target >= 0 => Ok(target as usize),
+            Some(target) => Err(PathEnd::Fault(format!("jump to negative address {}", target))),
+            None => Err(PathEnd::Unsupported(format!("jump to symbolic address {}", self.operand(1)))),
+        }
+    }
+
+    // Executes one instruction, returns the other half of a fork.
+    fn step(&mut self) -> Option<SymbolicCpu> {
+        match self.try_step() {
+            Ok(fork) => fork,
+            Err(end) => {
+                self.end = Some(end);
+                None
+            }
+        }
+    }
+
+    fn try_step(&mut self) -> Result<Option<SymbolicCpu>, PathEnd> {
+        let inst = match self.read(self.ip as i64).as_constant() {
+            Some(inst) => inst,
+            None => return Err(PathEnd::Unsupported(format!("symbolic instruction at {}", self.ip))),
+        };
+        self.steps += 1;
+        match inst % 100 {
+            opcode @ (1 | 2 | 7 | 8) => {
+                let (a, b) = (self.operand(0), self.operand(1));
+                let value = match opcode {
+                    1 => Expr::sum(a, b),
+                    2 => Expr::product(a, b),
+                    7 => Expr::less_than(a, b),
+                    _ => Expr::equals(a, b),
+                };
+                self.store(2, value)?;
+                self.ip += 4;
+            }
+            3 => {
+                let value = self.input.pop_front().unwrap_or_else(|| {
+                    self.next_input += 1;
+                    Expr::symbol(&format!("input{}", self.next_input - 1))
+                });
+                self.store(0, value)?;
+                self.ip += 2;
+            }
+            4 => {
+                self.output.push(self.operand(0));
+                self.ip += 2;
+            }
+            opcode @ (5 | 6) => {
+                let cond = self.operand(0);
+                let jump_if_non_zero = opcode == 5;
+                let non_zero = match cond.as_constant() {
+                    Some(value) => Some(value != 0),
+                    None => self.constraints.iter().find(|(c, _)| *c == cond).map(|(_, non_zero)| *non_zero),
+                };
+                match non_zero {
+                    Some(non_zero) if non_zero == jump_if_non_zero => self.ip = self.jump_target()?,
+                    Some(_) => self.ip += 3,
+                    None => {
+                        let target = self.jump_target()?;
+                        let mut fork = self.clone();
+                        fork.constraints.push((cond.clone(), !jump_if_non_zero));
+                        fork.ip += 3;
+                        self.constraints.push((cond, jump_if_non_zero));
+                        self.ip = target;
+                        return Ok(Some(fork));
+                    }
+                }
+            }
+            9 => {
+                match self.operand(0).as_constant() {
+                    Some(offset) => match self.rbp.checked_add(offset) {
+                        Some(rbp) => self.rbp = rbp,
+                        None => return Err(PathEnd::Fault(format!("relative base {} + {} overflows at ip {}", self.rbp, offset, self.ip))),
+                    },
+                    None => return Err(PathEnd::Unsupported("symbolic rbp adjustment".to_string())),
+                }
+                self.ip += 2;
+            }
+            99 => return Err(PathEnd::Halted),
+            opcode => return Err(PathEnd::Fault(format!("bad opcode {}", opcode))),
+        }
+        Ok(None)
+    }
+}
+
+#[test]
+fn test_polynomial() {
+    let x = Polynomial::symbol("x");
+    let y = Polynomial::symbol("y");
+    let p = x.mul(&x).add(&x.mul(&y).mul(&Polynomial::constant(-3))).add(&Polynomial::constant(5));
+    assert_eq!(p.to_string(), "-3*x*y + x*x + 5");
+    let env = [("x".to_string(), 2), ("y".to_string(), 1)].iter().cloned().collect();
+    assert_eq!(p.eval(&env), Some(3));
+    assert_eq!(p.add(&p.mul(&Polynomial::constant(-1))).as_constant(), Some(0));
+    assert!(p.split_linear("x").is_none());
+    let (coefficient, rest) = p.split_linear("y").unwrap();
+    assert_eq!(coefficient.to_string(), "-3*x");
+    assert_eq!(rest.to_string(), "x*x + 5");
+}
+
+#[test]
+fn test_symbolic_day2_style() {
+    // mem[0] = noun * verb + noun + 7
+    let cpu = IntCodeCpu::from_code("2,13,14,15,1,15,13,15,1001,15,7,0,99,0,0,0");
+    let mut symbolic = SymbolicCpu::from_cpu(&cpu);
+    symbolic.make_symbolic(13, "noun");
+    symbolic.make_symbolic(14, "verb");
+    let paths = symbolic.explore(1000, 10);
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].end, Some(PathEnd::Halted));
+    assert_eq!(paths[0].memory[0].to_string(), "noun*verb + noun + 7");
+    let solutions = paths[0].solve(&paths[0].memory[0], 27, &[("noun", 0..=99), ("verb", 0..=99)]).unwrap();
+    let mut pairs = solutions.iter().map(|s| (s["noun"], s["verb"])).collect::<Vec<_>>();
+    pairs.sort();
+    assert_eq!(pairs, vec![(1, 19), (2, 9), (4, 4), (5, 3), (10, 1), (20, 0)]);
+}
+
+#[test]
+fn test_symbolic_fork_on_input() {
+    // outputs input * 2 if it's less than 10, otherwise input + 100
+    let cpu = IntCodeCpu::from_code("3,30,1007,30,10,31,1005,31,16,1001,30,100,32,1105,1,20,1002,30,2,32,4,32,99");
+    let mut paths = SymbolicCpu::from_cpu(&cpu).explore(1000, 10);
+    assert_eq!(paths.len(), 2);
+    paths.sort_by_key(|path| path.output[0].to_string());
+    assert_eq!(paths[0].output, vec![Expr::Poly(Polynomial::symbol("input0").mul(&Polynomial::constant(2)))]);
+    assert_eq!(paths[1].output[0].to_string(), "input0 + 100");
+    let domains = [("input0", -1000..=1000)];
+    let small = paths[0].solve(&paths[0].output[0], 8, &domains).unwrap();
+    assert_eq!(small.len(), 1);
+    assert_eq!(small[0]["input0"], 4);
+    // 2 * 60 = 120 would be a solution without the path condition
+    assert!(paths[0].solve(&paths[0].output[0], 120, &domains).unwrap().is_empty());
+    assert_eq!(paths[1].solve(&paths[1].output[0], 120, &domains).unwrap()[0]["input0"], 20);
+}
+
+#[test]
+fn test_symbolic_unsupported() {
+    let cpu = IntCodeCpu::from_code("3,2,0");
+    let paths = SymbolicCpu::from_cpu(&cpu).explore(1000, 10);
+    assert_eq!(paths[0].end, Some(PathEnd::Unsupported("symbolic instruction at 2".to_string())));
+    let cpu = IntCodeCpu::from_code("3,5,1105,1,0,0");
+    let paths = SymbolicCpu::from_cpu(&cpu).explore(100, 10);
+    assert_eq!(paths[0].end, Some(PathEnd::StepLimit));
+}
+
+#[test]
+fn test_solve_doesnt_overflow() {
+    // -x == i64::MIN leaves rest == i64::MIN and coefficient == -1
+    let path = SymbolicCpu::from_cpu(&IntCodeCpu::from_code("99"));
+    let expr = Expr::Poly(Polynomial::symbol("x").mul(&Polynomial::constant(-1)));
+    assert!(path.solve(&expr, i64::MIN, &[("x", i64::MIN..=i64::MAX)]).unwrap().is_empty());
+}
+
+#[test]
+fn test_solve_domain_ending_at_max() {
+    // x * x + y == 0 is solved for y, x is enumerated up to i64::MAX
+    let path = SymbolicCpu::from_cpu(&IntCodeCpu::from_code("99"));
+    let expr = Expr::Poly(Polynomial::symbol("x").mul(&Polynomial::symbol("x")).add(&Polynomial::symbol("y")));
+    let max = i64::MAX;
+    let solutions = path.solve(&expr, 0, &[("x", max - 1..=max), ("y", max - 1..=max)]).unwrap();
+    assert!(solutions.is_empty());
+}
+
+#[test]
+fn test_symbolic_rbp_overflow() {
+    let mut cpu = IntCodeCpu::from_code("109,1,99");
+    cpu.rbp = i64::MAX;
+    let paths = SymbolicCpu::from_cpu(&cpu).explore(10, 10);
+    assert_eq!(paths[0].end, Some(PathEnd::Fault(format!("relative base {} + 1 overflows at ip 0", i64::MAX))));
+}
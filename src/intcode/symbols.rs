@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use super::disasm::disassemble;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Int,
+    String,
+    Code,
+}
+
+impl FromStr for SymbolKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "int" => Ok(SymbolKind::Int),
+            "string" => Ok(SymbolKind::String),
+            "code" => Ok(SymbolKind::Code),
+            _ => Err(format!("unknown symbol type \"{}\"", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub address: usize,
+    pub name: String,
+    pub kind: SymbolKind,
+    pub length: usize,
+}
+
+// Symbol files have one symbol per line: "<address> <name> [int|string|code] [length]",
+// type defaults to int and length to 1. Everything after a '#' is a comment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    symbols: BTreeMap<usize, Symbol>,
+}
+
+impl SymbolMap {
+    pub fn new() -> SymbolMap {
+        SymbolMap::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<SymbolMap> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn insert(&mut self, symbol: Symbol) {
+        self.symbols.insert(symbol.address, symbol);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.values()
+    }
+
+    // symbol starting exactly at `address`
+    pub fn get(&self, address: usize) -> Option<&Symbol> {
+        self.symbols.get(&address)
+    }
+
+    // symbol covering `address` and the offset of `address` into it
+    pub fn lookup(&self, address: usize) -> Option<(&Symbol, usize)> {
+        self.symbols.range(..=address)
+            .next_back()
+            .map(|(_, symbol)| (symbol, address - symbol.address))
+            .filter(|(symbol, offset)| *offset < symbol.length)
+    }
+
+    pub fn name_of(&self, address: usize) -> Option<String> {
+        self.lookup(address).map(|(symbol, offset)| if offset == 0 {
+            symbol.name.clone()
+        } else {
+            format!("{}+{}", symbol.name, offset)
+        })
+    }
+}
+
+impl FromStr for SymbolMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = SymbolMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() < 2 || fields.len() > 4 {
+                return Err(format!("line {}: expected \"<address> <name> [type] [length]\"", i + 1));
+            }
+            let address = fields[0].parse::<usize>()
+                .map_err(|e| format!("line {}: bad address \"{}\": {}", i + 1, fields[0], e))?;
+            let kind = fields.get(2).map_or(Ok(SymbolKind::Int), |kind| kind.parse())
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+            let length = fields.get(3).map_or(Ok(1), |length| length.parse::<usize>())
+                .map_err(|e| format!("line {}: bad length: {}", i + 1, e))?;
+            if length == 0 {
+                return Err(format!("line {}: symbol \"{}\" has length 0", i + 1, fields[1]));
+            }
+            map.insert(Symbol { address, name: fields[1].to_string(), kind, length });
+        }
+        Ok(map)
+    }
+}
+
+// Prints memory with named regions labeled: int symbols as values, string symbols decoded
+// as ASCII and code symbols disassembled. Unnamed cells are printed 8 per line.
+pub fn dump_memory(memory: &[i64], range: Range<usize>, symbols: &SymbolMap) -> String {
+    let mut result = String::new();
+    let end = range.end.min(memory.len());
+    let mut addr = range.start;
+    while addr < end {
+        if let Some(symbol) = symbols.get(addr) {
+            // symbols inserted with length 0 still cover their first cell
+            let symbol_end = (addr + symbol.length.max(1)).min(end);
+            match symbol.kind {
+                SymbolKind::Code => result.push_str(&disassemble(memory, addr..symbol_end, Some(symbols))),
+                SymbolKind::Int => {
+                    result.push_str(&format!("{}:\n", symbol.name));
+                    result.push_str(&format_words(memory, addr..symbol_end));
+                }
+                SymbolKind::String => {
+                    result.push_str(&format!("{}:\n", symbol.name));
+                    result.push_str(&format!("{:>6}  {:?}\n", addr, decode_ascii(&memory[addr..symbol_end])));
+                }
+            }
+            addr = symbol_end;
+        } else {
+            let next_symbol = symbols.symbols.range(addr..end).next().map_or(end, |(next, _)| *next);
+            result.push_str(&format_words(memory, addr..next_symbol));
+            addr = next_symbol;
+        }
+    }
+    result
+}
+
+fn format_words(memory: &[i64], range: Range<usize>) -> String {
+    let mut result = String::new();
+    for (i, chunk) in memory[range.clone()].chunks(8).enumerate() {
+        let words = chunk.iter().map(i64::to_string).collect::<Vec<String>>();
+        result.push_str(&format!("{:>6}  {}\n", range.start + i * 8, words.join(" ")));
+    }
+    result
+}
+
+pub fn decode_ascii(words: &[i64]) -> String {
+    words.iter()
+        .map(|word| if (0..=255).contains(word) { *word as u8 as char } else { char::REPLACEMENT_CHARACTER })
+        .collect()
+}
+
+#[test]
+fn test_parse_symbols() {
+    let symbols: SymbolMap = "# day 13\n\
+                              392 ball_x\n\
+                              1000 rooms string 4 # room names\n\
+                              \n\
+                              12 update_score code 30\n".parse().unwrap();
+    assert_eq!(symbols.get(1000), Some(&Symbol {
+        address: 1000,
+        name: "rooms".to_string(),
+        kind: SymbolKind::String,
+        length: 4,
+    }));
+    assert_eq!(symbols.name_of(392), Some("ball_x".to_string()));
+    assert_eq!(symbols.name_of(393), None);
+    assert_eq!(symbols.name_of(15), Some("update_score+3".to_string()));
+    assert_eq!(symbols.iter().count(), 3);
+    assert_eq!("1 x float".parse::<SymbolMap>(), Err("line 1: unknown symbol type \"float\"".to_string()));
+    assert!("x 1".parse::<SymbolMap>().is_err());
+}
+
+#[test]
+fn test_dump_memory() {
+    let symbols: SymbolMap = "0 start code 4\n4 score int 2\n6 name string 3".parse().unwrap();
+    let memory = vec![1101, 1, 2, 4, 0, 7, 66, 111, 98, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+    assert_eq!(dump_memory(&memory, 0..memory.len(), &symbols), "\
+start:
+     0  add 1, 2, [score]
+score:
+     4  0 7
+name:
+     6  \"Bob\"
+     9  1 2 3 4 5 6 7 8
+    17  9
+");
+}
+
+#[test]
+fn test_zero_length_symbol() {
+    assert_eq!("2 empty int 0".parse::<SymbolMap>(), Err("line 1: symbol \"empty\" has length 0".to_string()));
+    let mut symbols = SymbolMap::new();
+    symbols.insert(Symbol { address: 1, name: "empty".to_string(), kind: SymbolKind::Int, length: 0 });
+    assert_eq!(dump_memory(&[4, 5, 6], 0..3, &symbols), "     0  4\nempty:\n     1  5\n     2  6\n");
+}
@@ -0,0 +1,751 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use super::IntCodeCpu;
+use super::hooks::{Hook, HookId};
+
+// Conditions on the CPU state, like `mem[392] > 10` or `ip == 1034 && rbp > 3000`. Values are
+// integers, comparisons and `&&`, `||`, `!` produce 0 or 1 and anything non-zero is true.
+// `value` and `old` are the new and previous value of the cell a memory watchpoint triggered
+// on (the same for reads) and 0 everywhere else.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Ip,
+    Rbp,
+    Value,
+    Old,
+    Memory(Box<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        }
+    }
+
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            BinOp::Add => a.wrapping_add(b),
+            BinOp::Sub => a.wrapping_sub(b),
+            BinOp::Mul => a.wrapping_mul(b),
+            BinOp::Eq => (a == b) as i64,
+            BinOp::Ne => (a != b) as i64,
+            BinOp::Lt => (a < b) as i64,
+            BinOp::Le => (a <= b) as i64,
+            BinOp::Gt => (a > b) as i64,
+            BinOp::Ge => (a >= b) as i64,
+            BinOp::And => (a != 0 && b != 0) as i64,
+            BinOp::Or => (a != 0 || b != 0) as i64,
+        }
+    }
+}
+
+impl Expr {
+    // cells outside of the memory read as 0
+    pub fn eval(&self, cpu: &IntCodeCpu, access: Option<&Access>) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Ip => cpu.ip as i64,
+            Expr::Rbp => cpu.rbp,
+            Expr::Value => access.map_or(0, |access| access.value),
+            Expr::Old => access.map_or(0, |access| access.old),
+            Expr::Memory(addr) => {
+                let addr = addr.eval(cpu, access);
+                if addr < 0 { 0 } else { cpu.memory.get(addr as usize).copied().unwrap_or(0) }
+            }
+            Expr::Neg(e) => e.eval(cpu, access).wrapping_neg(),
+            Expr::Not(e) => (e.eval(cpu, access) == 0) as i64,
+            Expr::Binary(op, a, b) => op.apply(a.eval(cpu, access), b.eval(cpu, access)),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // nested binary expressions are parenthesized, so precedence doesn't matter here
+        let operand = |f: &mut fmt::Formatter, e: &Expr| match e {
+            Expr::Binary(..) => write!(f, "({})", e),
+            _ => write!(f, "{}", e),
+        };
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Ip => write!(f, "ip"),
+            Expr::Rbp => write!(f, "rbp"),
+            Expr::Value => write!(f, "value"),
+            Expr::Old => write!(f, "old"),
+            Expr::Memory(addr) => write!(f, "mem[{}]", addr),
+            Expr::Neg(e) => {
+                write!(f, "-")?;
+                operand(f, e)
+            }
+            Expr::Not(e) => {
+                write!(f, "!")?;
+                operand(f, e)
+            }
+            Expr::Binary(op, a, b) => {
+                operand(f, a)?;
+                write!(f, " {} ", op.symbol())?;
+                operand(f, b)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExprError {
+    // 1-based column of the offending token
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 17] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "!", "(", ")", "[", "]", "="];
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        let c = rest.chars().next().unwrap();
+        let column = text[..pos].chars().count() + 1;
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            let word = &rest[..len];
+            let token = if c.is_ascii_digit() {
+                Token::Number(word.parse().map_err(|e| ExprError { column, message: format!("bad number \"{}\": {}", word, e) })?)
+            } else {
+                Token::Ident(word.to_string())
+            };
+            tokens.push((column, token));
+            pos += len;
+        } else {
+            // a single '=' is only listed so it gets a better error message than "unexpected"
+            match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                Some(&"=") => return Err(ExprError { column, message: "'=' isn't an operator, use '=='".to_string() }),
+                Some(symbol) => {
+                    tokens.push((column, Token::Symbol(symbol)));
+                    pos += symbol.len();
+                }
+                None => return Err(ExprError { column, message: format!("unexpected '{}'", c) }),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Recursive descent, from lowest to highest precedence: `||`, `&&`, comparisons, `+ -`, `*`,
+// unary `- !`. Comparisons don't chain.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(column, _)| *column)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ExprError> {
+        let found = match self.peek() {
+            Some(Token::Number(n)) => n.to_string(),
+            Some(Token::Ident(name)) => name.clone(),
+            Some(Token::Symbol(symbol)) => symbol.to_string(),
+            None => "end of input".to_string(),
+        };
+        Err(ExprError { column: self.column(), message: format!("expected {}, found {}", message, found) })
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ExprError> {
+        if self.eat(symbol) { Ok(()) } else { self.error(&format!("'{}'", symbol)) }
+    }
+
+    fn binary<F>(&mut self, ops: &[(&str, BinOp)], mut next: F) -> Result<Expr, ExprError>
+        where F: FnMut(&mut Parser) -> Result<Expr, ExprError> {
+        let mut result = next(self)?;
+        'outer: loop {
+            for (symbol, op) in ops {
+                if self.eat(symbol) {
+                    result = Expr::Binary(*op, Box::new(result), Box::new(next(self)?));
+                    continue 'outer;
+                }
+            }
+            return Ok(result);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("||", BinOp::Or)], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("&&", BinOp::And)], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ExprError> {
+        let left = self.sum()?;
+        let ops = [("==", BinOp::Eq), ("!=", BinOp::Ne), ("<=", BinOp::Le), (">=", BinOp::Ge), ("<", BinOp::Lt), (">", BinOp::Gt)];
+        for (symbol, op) in &ops {
+            if self.eat(symbol) {
+                return Ok(Expr::Binary(*op, Box::new(left), Box::new(self.sum()?)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn sum(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("+", BinOp::Add), ("-", BinOp::Sub)], Parser::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, ExprError> {
+        self.binary(&[("*", BinOp::Mul)], Parser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, ExprError> {
+        let expr = match self.peek() {
+            Some(Token::Number(n)) => Expr::Number(*n),
+            Some(Token::Ident(name)) if name == "ip" => Expr::Ip,
+            Some(Token::Ident(name)) if name == "rbp" => Expr::Rbp,
+            Some(Token::Ident(name)) if name == "value" => Expr::Value,
+            Some(Token::Ident(name)) if name == "old" => Expr::Old,
+            Some(Token::Ident(name)) if name == "mem" => {
+                self.pos += 1;
+                self.expect("[")?;
+                let addr = self.or()?;
+                self.expect("]")?;
+                return Ok(Expr::Memory(Box::new(addr)));
+            }
+            Some(Token::Symbol("(")) => {
+                self.pos += 1;
+                let expr = self.or()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            _ => return self.error("a number, ip, rbp, value, old, mem[...] or '('"),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0, end: s.chars().count() + 1 };
+        let expr = parser.or()?;
+        if parser.pos < parser.tokens.len() {
+            return parser.error("an operator");
+        }
+        Ok(expr)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Read(usize),
+    Write(usize),
+    // reads and writes
+    Access(usize),
+    // checked after every instruction, fires when the condition becomes true
+    Condition,
+}
+
+// Stops execution when an instruction accesses a cell and the optional condition holds for
+// that access, or when a condition becomes true. Written as `read 392`, `write 392 if value > 10`,
+// `access 392` or `if ip == 1034 && rbp > 3000`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub trigger: Trigger,
+    pub condition: Option<Expr>,
+}
+
+impl Watchpoint {
+    pub fn read(address: usize) -> Watchpoint {
+        Watchpoint { trigger: Trigger::Read(address), condition: None }
+    }
+
+    pub fn write(address: usize) -> Watchpoint {
+        Watchpoint { trigger: Trigger::Write(address), condition: None }
+    }
+
+    pub fn access(address: usize) -> Watchpoint {
+        Watchpoint { trigger: Trigger::Access(address), condition: None }
+    }
+
+    pub fn condition(condition: Expr) -> Watchpoint {
+        Watchpoint { trigger: Trigger::Condition, condition: Some(condition) }
+    }
+
+    pub fn with_condition(mut self, condition: Expr) -> Watchpoint {
+        self.condition = Some(condition);
+        self
+    }
+
+    fn watches(&self, access: &Access) -> bool {
+        match (self.trigger, access.kind) {
+            (Trigger::Read(address), AccessKind::Read) => address == access.address,
+            (Trigger::Write(address), AccessKind::Write) => address == access.address,
+            (Trigger::Access(address), _) => address == access.address,
+            _ => false,
+        }
+    }
+
+    fn holds(&self, cpu: &IntCodeCpu, access: Option<&Access>) -> bool {
+        self.condition.as_ref().is_none_or(|condition| condition.eval(cpu, access) != 0)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.trigger {
+            Trigger::Read(address) => write!(f, "read {}", address)?,
+            Trigger::Write(address) => write!(f, "write {}", address)?,
+            Trigger::Access(address) => write!(f, "access {}", address)?,
+            Trigger::Condition => {}
+        }
+        match &self.condition {
+            Some(condition) if self.trigger == Trigger::Condition => write!(f, "if {}", condition),
+            Some(condition) => write!(f, " if {}", condition),
+            None => Ok(()),
+        }
+    }
+}
+
+// the condition after a leading `if`
+fn strip_if(s: &str) -> Option<&str> {
+    s.strip_prefix("if").filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+impl FromStr for Watchpoint {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_start();
+        // columns in errors are relative to the whole spec
+        let offset = |e: ExprError, rest: &str| ExprError { column: e.column + s.len() - rest.len(), ..e };
+        let condition = |rest: &str| -> Result<Option<Expr>, ExprError> {
+            let trimmed = rest.trim_start();
+            if trimmed.is_empty() {
+                return Ok(None);
+            }
+            match strip_if(trimmed) {
+                Some(expr) => expr.parse().map(Some).map_err(|e| offset(e, expr)),
+                None => Err(ExprError { column: s.len() - trimmed.len() + 1, message: "expected 'if' before the condition".to_string() }),
+            }
+        };
+        if let Some(expr) = strip_if(s) {
+            return Ok(Watchpoint::condition(expr.parse().map_err(|e| offset(e, expr))?));
+        }
+        let mut parts = s.splitn(3, ' ');
+        let kind = parts.next().unwrap_or("");
+        let trigger: fn(usize) -> Trigger = match kind {
+            "read" => Trigger::Read,
+            "write" => Trigger::Write,
+            "access" => Trigger::Access,
+            _ => return Err(ExprError { column: 1, message: format!("expected read, write, access or if, found \"{}\"", kind) }),
+        };
+        let address_text = parts.next().unwrap_or("");
+        let address = address_text.parse::<usize>().map_err(|_| ExprError {
+            column: kind.len() + 2,
+            message: format!("bad address \"{}\"", address_text),
+        })?;
+        Ok(Watchpoint { trigger: trigger(address), condition: condition(parts.next().unwrap_or(""))? })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// An operand access by the instruction at `ip`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub ip: usize,
+    pub address: usize,
+    pub kind: AccessKind,
+    pub old: i64,
+    pub value: i64,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            AccessKind::Read => write!(f, "read [{}] = {} at ip {}", self.address, self.value, self.ip),
+            AccessKind::Write => write!(f, "write [{}] {} -> {} at ip {}", self.address, self.old, self.value, self.ip),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WatchId(pub(super) usize);
+
+impl fmt::Display for WatchId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Halted,
+    // the next instruction reads input but none is queued, the VM would read -1
+    WaitingForInput,
+    // `access` is the access that triggered a memory watchpoint
+    Watchpoint { id: WatchId, access: Option<Access> },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Halted => write!(f, "program halted"),
+            StopReason::WaitingForInput => write!(f, "program is waiting for input"),
+            StopReason::Watchpoint { id, access: Some(access) } => write!(f, "watchpoint {}: {}", id, access),
+            StopReason::Watchpoint { id, access: None } => write!(f, "watchpoint {}", id),
+        }
+    }
+}
+
+// The hook collecting accesses to watched cells during an instruction, they are checked
+// once it finished.
+#[derive(Clone, Default)]
+struct Watcher {
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    // whether a condition watchpoint's condition held after the last check
+    held: BTreeMap<usize, bool>,
+    next_id: usize,
+    ip: usize,
+    accesses: Vec<Access>,
+    // the watchpoint that stopped the last run method
+    stopped: Option<StopReason>,
+}
+
+impl Watcher {
+    fn record(&mut self, access: Access) {
+        if self.watchpoints.values().any(|watchpoint| watchpoint.watches(&access)) {
+            self.accesses.push(access);
+        }
+    }
+}
+
+impl Hook for Watcher {
+    fn before_decode(&mut self, ip: usize, _inst: i64) {
+        self.ip = ip;
+        self.accesses.clear();
+    }
+
+    fn memory_read(&mut self, address: usize, value: i64) {
+        self.record(Access { ip: self.ip, address, kind: AccessKind::Read, old: value, value });
+    }
+
+    fn memory_write(&mut self, address: usize, old: i64, value: i64) {
+        self.record(Access { ip: self.ip, address, kind: AccessKind::Write, old, value });
+    }
+}
+
+impl IntCodeCpu {
+    fn watcher(&mut self) -> HookId {
+        match self.find_hook::<Watcher>() {
+            Some(id) => id,
+            None => self.add_hook(Watcher::default()),
+        }
+    }
+
+    // Watchpoints stop `run_until_stop`, `step_watched` and the other run methods, those
+    // leave the reason for `take_stop_reason`.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> WatchId {
+        let held = watchpoint.trigger == Trigger::Condition && watchpoint.holds(self, None);
+        let hook = self.watcher();
+        let watcher = self.hook_mut::<Watcher>(hook).unwrap();
+        let id = watcher.next_id;
+        watcher.next_id += 1;
+        watcher.watchpoints.insert(id, watchpoint);
+        watcher.held.insert(id, held);
+        WatchId(id)
+    }
+
+    pub fn remove_watchpoint(&mut self, id: WatchId) -> bool {
+        let hook = self.watcher();
+        let watcher = self.hook_mut::<Watcher>(hook).unwrap();
+        watcher.held.remove(&id.0);
+        watcher.watchpoints.remove(&id.0).is_some()
+    }
+
+    pub fn watchpoints(&self) -> Vec<(WatchId, Watchpoint)> {
+        let watcher = self.find_hook::<Watcher>().and_then(|hook| self.hook::<Watcher>(hook));
+        watcher.map_or_else(Vec::new, |watcher| {
+            watcher.watchpoints.iter().map(|(id, watchpoint)| (WatchId(*id), watchpoint.clone())).collect()
+        })
+    }
+
+    // The watchpoint that stopped the last call of `run`, `run_until_io`, `run_until_out` or
+    // `single_step`, if any.
+    pub fn take_stop_reason(&mut self) -> Option<StopReason> {
+        let hook = self.find_hook::<Watcher>()?;
+        self.hook_mut::<Watcher>(hook).unwrap().stopped.take()
+    }
+
+    pub(super) fn watchpoint_fired(&mut self) -> bool {
+        if self.hooks.is_empty() {
+            return false;
+        }
+        let reason = self.check_watchpoints();
+        if let Some(hook) = self.find_hook::<Watcher>() {
+            self.hook_mut::<Watcher>(hook).unwrap().stopped = reason;
+        }
+        reason.is_some()
+    }
+
+    // Runs until the program halts, waits for input that isn't queued or a watchpoint fires.
+    // Watchpoints fire after the instruction that triggered them.
+    pub fn run_until_stop(&mut self) -> StopReason {
+        loop {
+            if let Some(reason) = self.step_watched() {
+                return reason;
+            }
+        }
+    }
+
+    // Executes a single instruction unless the program halted or waits for input, `None` if
+    // nothing stopped it.
+    pub fn step_watched(&mut self) -> Option<StopReason> {
+        if !self.running {
+            return Some(StopReason::Halted);
+        }
+        if self.waiting_for_input() {
+            return Some(StopReason::WaitingForInput);
+        }
+        self.step();
+        match self.check_watchpoints() {
+            Some(reason) => Some(reason),
+            None if !self.running => Some(StopReason::Halted),
+            None => None,
+        }
+    }
+
+    fn check_watchpoints(&mut self) -> Option<StopReason> {
+        let hook = self.find_hook::<Watcher>()?;
+        let watcher = self.hook::<Watcher>(hook).unwrap();
+        let mut fired = None;
+        let mut held = vec![];
+        for (id, watchpoint) in &watcher.watchpoints {
+            let reason = if watchpoint.trigger == Trigger::Condition {
+                let holds = watchpoint.holds(self, None);
+                let fires = holds && !watcher.held[id];
+                held.push((*id, holds));
+                if fires { Some(StopReason::Watchpoint { id: WatchId(*id), access: None }) } else { None }
+            } else {
+                watcher.accesses.iter()
+                    .find(|access| watchpoint.watches(access) && watchpoint.holds(self, Some(access)))
+                    .map(|access| StopReason::Watchpoint { id: WatchId(*id), access: Some(*access) })
+            };
+            fired = fired.or(reason);
+        }
+        let watcher = self.hook_mut::<Watcher>(hook).unwrap();
+        watcher.held.extend(held);
+        watcher.accesses.clear();
+        fired
+    }
+}
+
+#[test]
+fn test_parse_expr() {
+    let expr = "ip == 1034 && rbp > 3000".parse::<Expr>().unwrap();
+    assert_eq!(expr, Expr::Binary(
+        BinOp::And,
+        Box::new(Expr::Binary(BinOp::Eq, Box::new(Expr::Ip), Box::new(Expr::Number(1034)))),
+        Box::new(Expr::Binary(BinOp::Gt, Box::new(Expr::Rbp), Box::new(Expr::Number(3000)))),
+    ));
+    assert_eq!(expr.to_string(), "(ip == 1034) && (rbp > 3000)");
+    let expr = "mem[rbp - 2] * -3 + 1 || !(value != old)".parse::<Expr>().unwrap();
+    assert_eq!(expr.to_string(), "((mem[rbp - 2] * -3) + 1) || !(value != old)");
+    assert_eq!(expr.to_string().parse::<Expr>().unwrap(), expr);
+
+    let error = |text: &str| text.parse::<Expr>().unwrap_err().to_string();
+    assert_eq!(error("mem[392] = 10"), "column 10: '=' isn't an operator, use '=='");
+    assert_eq!(error("mem[392 > 10"), "column 13: expected ']', found end of input");
+    assert_eq!(error("ip == rip"), "column 7: expected a number, ip, rbp, value, old, mem[...] or '(', found rip");
+    assert_eq!(error("1 2"), "column 3: expected an operator, found 2");
+    assert_eq!(error("ip # 3"), "column 4: unexpected '#'");
+}
+
+#[test]
+fn test_eval_expr() {
+    let mut cpu = IntCodeCpu::from_program(vec![5, 6, 7]);
+    cpu.rbp = -2;
+    let eval = |text: &str, access: Option<&Access>| text.parse::<Expr>().unwrap().eval(&cpu, access);
+    assert_eq!(eval("mem[rbp + 3] + mem[2] * 2 - mem[-1] - mem[100]", None), 20);
+    assert_eq!(eval("1 + 2 * 3 == 7 && !0 && -ip == 0", None), 1);
+    let access = Access { ip: 0, address: 1, kind: AccessKind::Write, old: 6, value: 11 };
+    assert_eq!(eval("value - old", Some(&access)), 5);
+    assert_eq!(eval("value", None), 0);
+}
+
+#[test]
+fn test_parse_watchpoint() {
+    let parse = |text: &str| text.parse::<Watchpoint>();
+    assert_eq!(parse("read 392"), Ok(Watchpoint::read(392)));
+    assert_eq!(parse("access 7"), Ok(Watchpoint::access(7)));
+    let watchpoint = parse("write 392 if value > 10").unwrap();
+    assert_eq!(watchpoint, Watchpoint::write(392).with_condition("value > 10".parse().unwrap()));
+    assert_eq!(watchpoint.to_string(), "write 392 if value > 10");
+    let watchpoint = parse("if ip == 1034 && rbp > 3000").unwrap();
+    assert_eq!(watchpoint.trigger, Trigger::Condition);
+    assert_eq!(watchpoint.to_string(), "if (ip == 1034) && (rbp > 3000)");
+
+    let error = |text: &str| parse(text).unwrap_err().to_string();
+    assert_eq!(error("break 3"), "column 1: expected read, write, access or if, found \"break\"");
+    assert_eq!(error("write x"), "column 7: bad address \"x\"");
+    assert_eq!(error("write 3 when value > 1"), "column 9: expected 'if' before the condition");
+    assert_eq!(error("write 3 if value >"), "column 19: expected a number, ip, rbp, value, old, mem[...] or '(', found end of input");
+}
+
+#[test]
+fn test_memory_watchpoints() {
+    // counts [20] down from 3
+    let mut cpu = IntCodeCpu::from_code("1101,3,0,20,1001,20,-1,20,1005,20,4,99");
+    let low = cpu.add_watchpoint("write 20 if value < 2".parse().unwrap());
+    let reason = cpu.run_until_stop();
+    let access = Access { ip: 4, address: 20, kind: AccessKind::Write, old: 2, value: 1 };
+    assert_eq!(reason, StopReason::Watchpoint { id: low, access: Some(access) });
+    assert_eq!(reason.to_string(), "watchpoint 0: write [20] 2 -> 1 at ip 4");
+    assert_eq!(cpu.ip(), 8);
+    // the next instruction reads the cell
+    let read = cpu.add_watchpoint(Watchpoint::read(20));
+    let access = Access { ip: 8, address: 20, kind: AccessKind::Read, old: 1, value: 1 };
+    assert_eq!(cpu.run_until_stop(), StopReason::Watchpoint { id: read, access: Some(access) });
+    assert!(cpu.remove_watchpoint(read));
+    assert!(!cpu.remove_watchpoint(read));
+    assert_eq!(cpu.watchpoints().len(), 1);
+    let reason = cpu.run_until_stop();
+    assert!(matches!(reason, StopReason::Watchpoint { access: Some(Access { value: 0, .. }), .. }));
+    assert_eq!(cpu.run_until_stop(), StopReason::Halted);
+    assert_eq!(cpu.run_until_stop(), StopReason::Halted);
+}
+
+#[test]
+fn test_condition_watchpoints() {
+    let mut cpu = IntCodeCpu::from_code("1101,3,0,20,1001,20,-1,20,1005,20,4,99");
+    let id = cpu.add_watchpoint("if mem[20] == 2 && ip == 8".parse().unwrap());
+    assert_eq!(cpu.watchpoints(), vec![(id, "if mem[20] == 2 && ip == 8".parse().unwrap())]);
+    assert_eq!(cpu.run_until_stop(), StopReason::Watchpoint { id, access: None });
+    assert_eq!((cpu.ip(), cpu.memory[20]), (8, 2));
+    // fires again only once the condition was false in between
+    assert_eq!(cpu.run_until_stop(), StopReason::Halted);
+
+    // a condition that already holds when the watchpoint is added doesn't fire
+    let mut cpu = IntCodeCpu::from_code("3,5,4,5,99");
+    cpu.add_watchpoint("if rbp == 0".parse().unwrap());
+    assert_eq!(cpu.run_until_stop(), StopReason::WaitingForInput);
+    assert_eq!(cpu.ip(), 0);
+    cpu.input.push_back(7);
+    assert_eq!(cpu.run_until_stop(), StopReason::Halted);
+    assert_eq!(cpu.output, vec![7]);
+}
+
+#[test]
+fn test_run_until_stop_with_input_device() {
+    use super::devices::Joystick;
+    let mut cpu = IntCodeCpu::from_code("3,20,3,21,99");
+    cpu.attach_input(Joystick { position: 4 });
+    assert_eq!(cpu.run_until_stop(), StopReason::Halted);
+    assert_eq!(&cpu.memory[20..22], &[4, 4]);
+}
+
+#[test]
+fn test_run_methods_stop_at_watchpoints() {
+    // counts [20] down from 3 and outputs it
+    let code = "1101,3,0,20,1001,20,-1,20,4,20,1005,20,4,99";
+    let mut cpu = IntCodeCpu::from_code(code);
+    let id = cpu.add_watchpoint("write 20 if value == 1".parse().unwrap());
+    cpu.run();
+    assert!(cpu.running);
+    assert_eq!(cpu.ip(), 8);
+    let access = Access { ip: 4, address: 20, kind: AccessKind::Write, old: 2, value: 1 };
+    assert_eq!(cpu.take_stop_reason(), Some(StopReason::Watchpoint { id, access: Some(access) }));
+    assert_eq!(cpu.take_stop_reason(), None);
+    cpu.run();
+    assert!(!cpu.running);
+    assert_eq!(cpu.take_stop_reason(), None);
+    assert_eq!(cpu.output, vec![2, 1, 0]);
+
+    let mut cpu = IntCodeCpu::from_code(code);
+    cpu.add_watchpoint("if mem[20] == 1".parse().unwrap());
+    assert_eq!(cpu.run_until_out(), Some(2));
+    assert_eq!(cpu.run_until_out(), None);
+    assert!(cpu.running && cpu.take_stop_reason().is_some());
+    assert_eq!(cpu.run_until_out(), Some(1));
+    cpu.run_until_io();
+    assert_eq!(cpu.take_stop_reason(), None);
+    assert_eq!(cpu.ip(), 10);
+}
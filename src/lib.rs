@@ -1,3 +1,4 @@
+pub mod ffi;
 pub mod intcode;
 
 pub fn gcd(mut a: i64, mut b: i64) -> i64 {